@@ -1,7 +1,18 @@
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use crate::downloader::{DownloadProgress, DownloadRequest, DownloadStatus, Downloader};
-use crate::log::{get_log_from_progress, LogEntry};
+use crate::cli::{read_requests, RequestsSource};
+use crate::config::{Config, KeyBindings, LogFormat, Quality};
+use crate::downloader::{
+    is_barcode, is_short_link, parse_album_request, parse_deezer_input, CollisionChoice,
+    CollisionPrompt, DownloadItem, DownloadProgress, DownloadRequest, DownloadStatus, Downloader,
+    FileInfo, GroupId, Id, LargeBatch, SkipReason,
+};
+use crate::history::{recent_downloads, RecentDownload};
+use crate::log::{get_log_from_progress, LogEntry, LogLine};
+use crate::session::{SavedLog, SavedQueueItem, Session, SessionState};
 use crate::{tui::Tui, Action, Event, Frame};
 use color_eyre::eyre::{eyre, Result};
 use deezer::models::Track;
@@ -9,11 +20,42 @@ use ratatui::{prelude::*, widgets::*};
 use tui_input::backend::crossterm::EventHandler;
 use tui_input::Input;
 
+/// Number of consecutive terminal input errors after which the app exits.
+static MAX_INPUT_ERRORS: u8 = 5;
+
+/// Maximum number of progress events handled per tick, the rest are left for the next
+/// ones so that a burst of events doesn't freeze the UI.
+static MAX_PROGRESS_PER_TICK: usize = 500;
+
+/// How long the input must stay unchanged before the typed song is looked up.
+static PREVIEW_DELAY: Duration = Duration::from_millis(400);
+
+/// How long a toast stays on screen.
+static TOAST_DURATION: Duration = Duration::from_secs(3);
+
+/// Number of finished downloads shown in the recent downloads popup.
+static RECENT_DOWNLOADS: usize = 15;
+
+/// How often the session is saved to be restored after a crash.
+static SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of the last log entries saved with the session.
+static SESSION_LOGS: usize = 500;
+
+/// Number of entered inputs kept to be brought back with the arrows.
+static INPUT_HISTORY: usize = 100;
+
+/// Longest time the screen is left as is when nothing changes, so that the elapsed times
+/// it shows keep going.
+static REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Default)]
 enum InputMode {
     #[default]
     Song,
     Album,
+    Playlist,
+    Barcode,
 }
 
 impl Display for InputMode {
@@ -22,65 +64,535 @@ impl Display for InputMode {
     }
 }
 
+/// Pane the scroll keys apply to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    #[default]
+    Input,
+    Logs,
+    Queue,
+}
+
 #[derive(Debug)]
 struct QueueItem {
-    pub song: Track,
+    pub item: DownloadItem,
     pub status: DownloadStatus,
+    /// File the track was written to, once downloaded.
+    pub path: Option<PathBuf>,
+    /// Why the download failed, once failed.
+    pub error: Option<String>,
+    /// Percentage of the download done, while downloading.
+    pub progress: Option<u8>,
+    /// Quality the track is downloaded in, when lower than the requested one. It changes
+    /// as failed downloads are tried again in a lower one.
+    pub quality: Option<Quality>,
+    /// Percentage of the MP3 copy done, while it is transcoded from the downloaded song.
+    pub mp3_copy: Option<u8>,
+}
+
+impl QueueItem {
+    /// Whether the download of the item is over, successfully or not.
+    fn is_done(&self) -> bool {
+        matches!(
+            self.status,
+            DownloadStatus::Completed | DownloadStatus::Failed
+        )
+    }
+}
+
+/// Inputs entered before, brought back with the arrows like in a shell.
+#[derive(Debug, Default)]
+struct InputHistory {
+    entries: VecDeque<String>,
+    /// Entry shown in the input, `None` when it holds what is being typed.
+    position: Option<usize>,
+    /// What was being typed when the history started being browsed.
+    draft: String,
+}
+
+impl InputHistory {
+    fn push(&mut self, entry: String) {
+        self.position = None;
+        if self.entries.back() == Some(&entry) {
+            return;
+        }
+        if self.entries.len() == INPUT_HISTORY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Entry older than the shown one, keeping `typed` to come back to it. `None` when
+    /// there is none.
+    fn previous(&mut self, typed: &str) -> Option<&str> {
+        let position = match self.position {
+            Some(position) => position.checked_sub(1)?,
+            None => {
+                let newest = self.entries.len().checked_sub(1)?;
+                self.draft = typed.to_string();
+                newest
+            }
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// Entry newer than the shown one, or what was being typed after the newest one.
+    fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(&self.entries[position])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+
+    /// Edits make the shown entry what is being typed.
+    fn stop_browsing(&mut self) {
+        self.position = None;
+    }
+}
+
+/// Lets the user choose which tracks of an album get queued.
+#[derive(Debug)]
+struct TrackPicker {
+    pub album_id: Id,
+    pub tracks: Vec<DownloadItem>,
+    pub checked: Vec<bool>,
+    pub state: ListState,
+}
+
+impl TrackPicker {
+    fn new(album_id: Id, tracks: Vec<DownloadItem>) -> Self {
+        let checked = vec![true; tracks.len()];
+        let state = ListState::default().with_selected((!tracks.is_empty()).then_some(0));
+
+        Self {
+            album_id,
+            tracks,
+            checked,
+            state,
+        }
+    }
+
+    fn select_previous(&mut self) {
+        if let Some(selected) = self.state.selected() {
+            self.state.select(Some(selected.saturating_sub(1)));
+        }
+    }
+
+    fn select_next(&mut self) {
+        if let Some(selected) = self.state.selected() {
+            self.state.select(Some(
+                (selected + 1).min(self.tracks.len().saturating_sub(1)),
+            ));
+        }
+    }
+
+    fn toggle_selected(&mut self) {
+        if let Some(checked) = self.state.selected().and_then(|x| self.checked.get_mut(x)) {
+            *checked = !*checked;
+        }
+    }
+
+    /// Checks every track, or unchecks them all if they already are.
+    fn toggle_all(&mut self) {
+        let value = !self.checked.iter().all(|x| *x);
+        self.checked.iter_mut().for_each(|x| *x = value);
+    }
+
+    fn into_checked_tracks(self) -> Vec<DownloadItem> {
+        self.tracks
+            .into_iter()
+            .zip(self.checked)
+            .filter_map(|(track, checked)| checked.then_some(track))
+            .collect()
+    }
+}
+
+/// A download waiting for the user to pick a new file name, its own being taken.
+#[derive(Debug)]
+struct Collision {
+    track: Track,
+    /// The file taking the name of the song.
+    existing: PathBuf,
+    /// Infos of the existing file and of the download, to tell which one to keep.
+    infos: (FileInfo, FileInfo),
+    prompt: CollisionPrompt,
+    name: Input,
+}
+
+/// Kept for the whole session since on some platforms the copied text is lost once
+/// the clipboard is dropped.
+struct Clipboard(arboard::Clipboard);
+
+impl std::fmt::Debug for Clipboard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Clipboard").finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug)]
 pub struct App {
     should_quit: bool,
     input: Input,
+    input_history: InputHistory,
     downloader: Downloader,
     queue: Vec<QueueItem>,
+    queue_state: ListState,
     input_mode: InputMode,
-    logs: Vec<LogEntry>,
+    logs: Vec<LogLine>,
     logs_offset: u16,
+    /// Whether long log entries are wrapped rather than cut at the edge of the pane.
+    wrap_logs: bool,
+    /// Whether queued and started tracks are logged too.
+    verbose_logs: bool,
+    track_picker: Option<TrackPicker>,
+    /// Pickers of albums resolved while another picker was open, shown once it is closed.
+    queued_pickers: VecDeque<TrackPicker>,
+    input_errors: u8,
+    event_tx: Option<tokio::sync::mpsc::UnboundedSender<Event>>,
+    input_changed_at: Option<Instant>,
+    preview: Option<(Id, Option<Track>)>,
+    clipboard: Option<Clipboard>,
+    /// Last finished downloads, most recent last.
+    recent_downloads: VecDeque<RecentDownload>,
+    /// Whether the recent downloads popup is open.
+    show_recent_downloads: bool,
+    /// Track each worker is downloading and since when, `None` when it is idle.
+    worker_tasks: Vec<Option<(Track, Instant)>>,
+    /// Whether the worker status popup is open.
+    show_workers: bool,
+    /// Worker selected in the worker status popup.
+    selected_worker: usize,
+    /// Whether the details of the selected queue item are shown.
+    show_queue_item_details: bool,
+    /// Whether queued items that haven't started are left out of the queue pane.
+    hide_inactive: bool,
+    /// Prompt for a new download directory, when open.
+    dir_prompt: Option<Input>,
+    /// Downloads waiting for a new file name, the first one is prompted for.
+    collisions: VecDeque<Collision>,
+    /// Choice applied to every later collision, once picked for all of them.
+    collision_choice_for_all: Option<CollisionChoice>,
+    /// Whether every download worker failed to initialize.
+    no_workers: bool,
+    /// Short-lived confirmation message and when it was shown, kept out of the logs.
+    toast: Option<(String, Instant)>,
+    focus: Focus,
+    keys: KeyBindings,
+    log_format: LogFormat,
+    exit_when_done: bool,
+    wrap_queue_selection: bool,
+    /// Number of items the queue pane showed when last drawn.
+    queue_page_size: usize,
+    /// Source requests are read from while running, if any.
+    requests_source: Option<RequestsSource>,
+    /// Whether requests are still read from the source.
+    reading_requests: bool,
+    /// Whether anything was requested or queued since the app started.
+    requested: bool,
+    /// Session saved to be restored after a crash, `None` if it can't be saved.
+    session: Option<Session>,
+    session_saved_at: Instant,
+    /// State of a previous session that didn't exit cleanly, offered to be restored.
+    restore_prompt: Option<SessionState>,
+    /// Albums too large to be queued without confirming, the first one is prompted for.
+    batch_prompts: VecDeque<LargeBatch>,
+    /// Whether something changed since the screen was last drawn.
+    dirty: bool,
 }
 
 impl Default for App {
     fn default() -> Self {
-        Self::new()
+        Self::new(Config::default())
     }
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
+        let downloader = Downloader::new(config.clone());
+        let worker_tasks = vec![None; downloader.workers() as usize];
+        let keys = config.keys.clone();
+        let log_format = config.log_format.clone();
+        let exit_when_done = config.exit_when_done;
+        let verbose_logs = config.verbose_logs;
+        let wrap_queue_selection = config.wrap_queue_selection;
         Self {
             should_quit: false,
             input: Input::default(),
-            downloader: Downloader::new(),
+            input_history: InputHistory::default(),
+            downloader,
             queue: Vec::new(),
+            queue_state: ListState::default(),
             input_mode: InputMode::default(),
             logs: Vec::new(),
             logs_offset: 0,
+            wrap_logs: false,
+            verbose_logs,
+            recent_downloads: recent_downloads(RECENT_DOWNLOADS).into(),
+            show_recent_downloads: false,
+            worker_tasks,
+            show_workers: false,
+            selected_worker: 0,
+            show_queue_item_details: false,
+            hide_inactive: false,
+            track_picker: None,
+            queued_pickers: VecDeque::new(),
+            input_errors: 0,
+            event_tx: None,
+            input_changed_at: None,
+            preview: None,
+            clipboard: None,
+            dir_prompt: None,
+            collisions: VecDeque::new(),
+            collision_choice_for_all: None,
+            no_workers: false,
+            toast: None,
+            focus: Focus::default(),
+            keys,
+            log_format,
+            exit_when_done,
+            wrap_queue_selection,
+            queue_page_size: 1,
+            requests_source: None,
+            reading_requests: false,
+            requested: false,
+            session: None,
+            session_saved_at: Instant::now(),
+            restore_prompt: None,
+            batch_prompts: VecDeque::new(),
+            dirty: true,
         }
     }
 
+    /// Enqueue the requests read from a source while running, e.g. ids piped by a script.
+    pub fn read_requests_from(&mut self, source: RequestsSource) {
+        self.requests_source = Some(source);
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         let mut tui = Tui::new()?;
+        self.event_tx = Some(tui.event_tx.clone());
+        if let Some(source) = self.requests_source.take() {
+            self.reading_requests = true;
+            tokio::spawn(read_requests(source, tui.event_tx.clone()));
+        }
+        if let Some((session, previous)) = Session::start() {
+            self.session = Some(session);
+            self.restore_prompt = previous.filter(|x| !x.queue.is_empty() || !x.logs.is_empty());
+        }
         tui.enter()?;
+        let mut drawn_at = Instant::now();
         while !self.should_quit {
-            tui.draw(|f| self.ui(f).expect("Unexpected error during drawing"))?;
+            // Drawing every tick is slow over SSH and makes some terminals flicker
+            if self.dirty || drawn_at.elapsed() >= REDRAW_INTERVAL {
+                tui.draw(|f| self.ui(f).expect("Unexpected error during drawing"))?;
+                self.dirty = false;
+                drawn_at = Instant::now();
+            }
             let event = tui.next().await.ok_or(eyre!("Unable to get event"))?; // blocks until next event
             let message = self.handle_event(event)?;
             self.update(message)?;
         }
         tui.exit()?;
+        if let Some(session) = self.session.take() {
+            session.end();
+        }
         Ok(())
     }
 
     fn handle_event(&mut self, event: Event) -> Result<Action> {
+        use crossterm::event::{KeyCode, KeyModifiers};
+
+        if !matches!(event, Event::Tick) {
+            self.dirty = true;
+        }
+        let event = match event {
+            Event::Key(key) => {
+                self.input_errors = 0;
+                Event::Key(normalize_key(key))
+            }
+            event => event,
+        };
+
         let msg = match event {
+            Event::Error(error) => {
+                self.input_errors = self.input_errors.saturating_add(1);
+                self.log(LogEntry::Error(format!(
+                    "Unable to read terminal input: {}",
+                    error
+                )));
+
+                // A broken input stream won't recover, leave instead of ignoring every key
+                if self.input_errors >= MAX_INPUT_ERRORS {
+                    Action::Quit
+                } else {
+                    Action::Tick
+                }
+            }
+            Event::Preview(id, track) => {
+                // Discard lookups for an input that has since changed
+                if let (InputMode::Song, Ok(input_id)) =
+                    (&self.input_mode, self.input.value().parse::<Id>())
+                {
+                    if input_id == id {
+                        self.preview = Some((id, track.map(|x| *x)));
+                    }
+                }
+                Action::Tick
+            }
+            Event::ExternalRequest(Ok(request)) => {
+                self.requested = true;
+                self.downloader.request_download(request);
+                Action::Tick
+            }
+            Event::ExternalRequest(Err(line)) => {
+                self.log(LogEntry::Error(format!("Invalid request read: {}", line)));
+                Action::Tick
+            }
+            Event::RequestsEnded(error) => {
+                self.reading_requests = false;
+                self.log(match error {
+                    Some(error) => LogEntry::Error(format!("Unable to read requests: {}", error)),
+                    None => LogEntry::Info(String::from("No more requests to read")),
+                });
+                Action::Tick
+            }
+            // Downloads are blocked until their collision is answered
+            Event::Key(key) if !self.collisions.is_empty() => match key.code {
+                KeyCode::Esc => Action::CollisionSkip,
+                KeyCode::Enter => Action::CollisionRename,
+                KeyCode::Char('o') if key.modifiers == KeyModifiers::CONTROL => {
+                    Action::CollisionOverwrite
+                }
+                KeyCode::Char('a') if key.modifiers == KeyModifiers::CONTROL => {
+                    Action::CollisionOverwriteAll
+                }
+                KeyCode::Char('s') if key.modifiers == KeyModifiers::CONTROL => {
+                    Action::CollisionSkipAll
+                }
+                _ => {
+                    if let Some(collision) = self.collisions.front_mut() {
+                        collision
+                            .name
+                            .handle_event(&crossterm::event::Event::Key(key));
+                    }
+                    Action::Tick
+                }
+            },
+            Event::Key(key) if self.restore_prompt.is_some() => match key.code {
+                KeyCode::Enter => Action::RestoreSession,
+                KeyCode::Esc => Action::DiscardSession,
+                _ => Action::Tick,
+            },
+            Event::Key(key) if !self.batch_prompts.is_empty() => match key.code {
+                KeyCode::Enter | KeyCode::Char('y') => Action::ConfirmBatch,
+                KeyCode::Esc | KeyCode::Char('n') => Action::DeclineBatch,
+                _ => Action::Tick,
+            },
+            Event::Key(key) if self.track_picker.is_some() => match key.code {
+                KeyCode::Esc => Action::PickerCancel,
+                KeyCode::Enter => Action::PickerConfirm,
+                KeyCode::Up => Action::PickerUp,
+                KeyCode::Down => Action::PickerDown,
+                KeyCode::Char(' ') => Action::PickerToggle,
+                KeyCode::Char('a') => Action::PickerToggleAll,
+                _ => Action::Tick,
+            },
+            Event::Key(key) if self.show_queue_item_details => match key.code {
+                KeyCode::Esc => Action::CloseQueueItemDetails,
+                _ => Action::Tick,
+            },
+            Event::Key(key) if self.show_workers => match key.code {
+                KeyCode::Esc => Action::ToggleWorkers,
+                KeyCode::Up => Action::SelectPreviousWorker,
+                KeyCode::Down => Action::SelectNextWorker,
+                KeyCode::Char(' ') => Action::ToggleWorkerPaused,
+                KeyCode::Char('k') if key.modifiers == KeyModifiers::CONTROL => {
+                    Action::ToggleWorkers
+                }
+                _ => Action::Tick,
+            },
+            Event::Key(key) if self.show_recent_downloads => match key.code {
+                KeyCode::Esc => Action::ToggleRecentDownloads,
+                KeyCode::Char('d') if key.modifiers == KeyModifiers::CONTROL => {
+                    Action::ToggleRecentDownloads
+                }
+                _ => Action::Tick,
+            },
+            Event::Key(key) if self.dir_prompt.is_some() => match key.code {
+                KeyCode::Esc => Action::DirPromptCancel,
+                KeyCode::Enter => Action::DirPromptConfirm,
+                _ => {
+                    if let Some(prompt) = self.dir_prompt.as_mut() {
+                        prompt.handle_event(&crossterm::event::Event::Key(key));
+                    }
+                    Action::Tick
+                }
+            },
+            // Remappable keys take precedence over the built-in ones
+            Event::Key(key) if self.keys.cycle_focus.matches(&key) => Action::CycleFocus,
+            Event::Key(key) if self.keys.cycle_input_mode.matches(&key) => Action::ToggleInputMode,
+            Event::Key(key) if key.modifiers == KeyModifiers::CONTROL => match key.code {
+                KeyCode::Char('c') => Action::CancelAll,
+                KeyCode::Char('t') => Action::OpenTrackPicker,
+                KeyCode::Char('y') => Action::CopyTrackUrl,
+                KeyCode::Char('r') => Action::RedownloadSelected,
+                KeyCode::Char('p') => Action::ResumeDownloads,
+                KeyCode::Char('o') => Action::OpenDirPrompt,
+                KeyCode::Char('w') => Action::ToggleLogWrap,
+                KeyCode::Char('l') => Action::ToggleVerboseLogs,
+                KeyCode::Char('d') => Action::ToggleRecentDownloads,
+                KeyCode::Char('s') => Action::SortQueue,
+                KeyCode::Char('f') => Action::ToggleInactiveItems,
+                KeyCode::Char('k') => Action::ToggleWorkers,
+                _ => Action::Tick,
+            },
             Event::Key(key) => match key.code {
                 crossterm::event::KeyCode::Esc => Action::Quit,
+                crossterm::event::KeyCode::Enter if self.focus == Focus::Queue => {
+                    Action::ShowQueueItemDetails
+                }
                 crossterm::event::KeyCode::Enter => Action::Download,
-                crossterm::event::KeyCode::Tab => Action::ToggleInputMode,
+                crossterm::event::KeyCode::PageUp | crossterm::event::KeyCode::Up
+                    if self.focus == Focus::Logs =>
+                {
+                    Action::ScrollLogsUp
+                }
+                crossterm::event::KeyCode::PageDown | crossterm::event::KeyCode::Down
+                    if self.focus == Focus::Logs =>
+                {
+                    Action::ScrollLogsDown
+                }
+                crossterm::event::KeyCode::PageUp if self.focus == Focus::Queue => {
+                    Action::QueuePageUp
+                }
+                crossterm::event::KeyCode::PageDown if self.focus == Focus::Queue => {
+                    Action::QueuePageDown
+                }
+                crossterm::event::KeyCode::Home if self.focus == Focus::Queue => {
+                    Action::SelectFirstQueueItem
+                }
+                crossterm::event::KeyCode::End if self.focus == Focus::Queue => {
+                    Action::SelectLastQueueItem
+                }
                 crossterm::event::KeyCode::PageUp => Action::ScrollLogsUp,
                 crossterm::event::KeyCode::PageDown => Action::ScrollLogsDown,
+                crossterm::event::KeyCode::Up if self.focus == Focus::Input => {
+                    Action::PreviousInput
+                }
+                crossterm::event::KeyCode::Down if self.focus == Focus::Input => Action::NextInput,
+                crossterm::event::KeyCode::Up => Action::SelectPreviousQueueItem,
+                crossterm::event::KeyCode::Down => Action::SelectNextQueueItem,
                 _ => {
-                    self.input.handle_event(&crossterm::event::Event::Key(key));
+                    let state_changed = self.input.handle_event(&crossterm::event::Event::Key(key));
+                    if state_changed.is_some_and(|x| x.value) {
+                        self.input_history.stop_browsing();
+                        self.input_changed();
+                    }
                     Action::Tick
                 }
             },
@@ -90,131 +602,925 @@ impl App {
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
+        if action != Action::Tick {
+            self.dirty = true;
+        }
         match action {
-            Action::Tick => self.update_progress(),
+            Action::Tick => {
+                // Read before the progress, the tracks of a request are all reported by the
+                // time it stops being pending
+                let requests_pending = self.downloader.has_pending_requests();
+                self.update_progress();
+                self.update_preview();
+                if self.exit_when_done && !requests_pending && self.is_done() {
+                    self.quit();
+                }
+                if self.session_saved_at.elapsed() >= SESSION_SAVE_INTERVAL {
+                    self.save_session();
+                }
+                if self
+                    .toast
+                    .as_ref()
+                    .is_some_and(|(_, shown_at)| shown_at.elapsed() >= TOAST_DURATION)
+                {
+                    self.toast = None;
+                    self.dirty = true;
+                }
+            }
             Action::Quit => self.quit(),
             Action::ToggleInputMode => {
                 self.input_mode = match self.input_mode {
                     InputMode::Song => InputMode::Album,
-                    InputMode::Album => InputMode::Song,
-                }
+                    InputMode::Album => InputMode::Playlist,
+                    InputMode::Playlist => InputMode::Barcode,
+                    InputMode::Barcode => InputMode::Song,
+                };
+                self.input_changed();
             }
             Action::Download => {
-                let request = match self.input_mode {
-                    InputMode::Song => DownloadRequest::Song,
-                    InputMode::Album => DownloadRequest::Album,
+                let value = self.input.value();
+                // Links say what they point to, whatever the input mode
+                let request = match parse_deezer_input(value) {
+                    Some((kind, id)) => Some(kind.request(id)),
+                    None if is_short_link(value) => {
+                        Some(DownloadRequest::ShortLink(value.trim().to_string()))
+                    }
+                    None => match self.input_mode {
+                        InputMode::Song => value.parse().ok().map(DownloadRequest::Song),
+                        InputMode::Album => parse_album_request(value),
+                        InputMode::Playlist => value.parse().ok().map(DownloadRequest::Playlist),
+                        InputMode::Barcode => is_barcode(value)
+                            .then(|| DownloadRequest::AlbumBarcode(value.to_string())),
+                    },
                 };
 
-                if let Ok(id) = self.input.value().parse::<u64>() {
+                if let Some(request) = request {
+                    self.input_history.push(value.to_string());
                     self.input.reset();
-                    self.downloader.request_download(request(id));
+                    self.input_changed();
+                    self.requested = true;
+                    self.downloader.request_download(request);
+                }
+            }
+            Action::CancelAll => {
+                self.downloader.cancel_all();
+                self.collisions.clear();
+                // Queuing them now would bring back what was just cancelled
+                self.batch_prompts.clear();
+
+                // Tracks being downloaded are removed once their cancellation is reported
+                let queued = self
+                    .queue
+                    .iter()
+                    .filter(|x| matches!(x.status, DownloadStatus::Inactive))
+                    .count();
+                let in_progress = self
+                    .queue
+                    .iter()
+                    .filter(|x| matches!(x.status, DownloadStatus::Downloading))
+                    .count();
+                self.queue
+                    .retain(|x| !matches!(x.status, DownloadStatus::Inactive));
+
+                if queued + in_progress > 0 {
+                    self.log(LogEntry::Info(format!(
+                        "Cancelled {} queued and {} in-progress downloads",
+                        queued, in_progress
+                    )));
+                }
+            }
+            Action::CycleFocus => {
+                self.focus = match self.focus {
+                    Focus::Input => Focus::Logs,
+                    Focus::Logs => Focus::Queue,
+                    Focus::Queue => Focus::Input,
                 }
             }
             Action::ScrollLogsUp => self.logs_offset = self.logs_offset.saturating_sub(1),
             Action::ScrollLogsDown => self.logs_offset = self.logs_offset.saturating_add(1),
+            Action::ToggleLogWrap => self.wrap_logs = !self.wrap_logs,
+            Action::ToggleVerboseLogs => {
+                self.verbose_logs = !self.verbose_logs;
+                let state = if self.verbose_logs { "on" } else { "off" };
+                self.show_toast(format!("Verbose logs {}", state));
+            }
+            Action::ShowQueueItemDetails => {
+                self.show_queue_item_details = self.queue_state.selected().is_some()
+            }
+            Action::CloseQueueItemDetails => self.show_queue_item_details = false,
+            Action::ToggleRecentDownloads => {
+                self.show_recent_downloads = !self.show_recent_downloads
+            }
+            Action::ToggleWorkers => self.show_workers = !self.show_workers,
+            Action::SelectPreviousWorker => {
+                self.selected_worker = self.selected_worker.saturating_sub(1);
+            }
+            Action::SelectNextWorker => {
+                if self.selected_worker + 1 < self.worker_tasks.len() {
+                    self.selected_worker += 1;
+                }
+            }
+            Action::ToggleWorkerPaused => {
+                let worker = self.selected_worker;
+                if let Some(paused) = self.downloader.toggle_worker_paused(worker) {
+                    self.log(LogEntry::Info(match paused {
+                        true => format!("Worker #{} paused, it won't take new tracks", worker + 1),
+                        false => format!("Worker #{} resumed", worker + 1),
+                    }));
+                }
+            }
+            Action::PreviousInput => {
+                if let Some(entry) = self.input_history.previous(self.input.value()) {
+                    self.input = Input::new(entry.to_string());
+                    self.input_changed();
+                }
+            }
+            Action::NextInput => {
+                if let Some(entry) = self.input_history.next() {
+                    self.input = Input::new(entry.to_string());
+                    self.input_changed();
+                }
+            }
+            Action::SelectPreviousQueueItem => self.step_queue_selection(false, 1),
+            Action::SelectNextQueueItem => self.step_queue_selection(true, 1),
+            Action::QueuePageUp => self.step_queue_selection(false, self.queue_page_size),
+            Action::QueuePageDown => self.step_queue_selection(true, self.queue_page_size),
+            Action::SelectFirstQueueItem => self.step_queue_selection(false, usize::MAX),
+            Action::SelectLastQueueItem => self.step_queue_selection(true, usize::MAX),
+            Action::ToggleInactiveItems => {
+                self.hide_inactive = !self.hide_inactive;
+                let selected = self.queue_state.selected().and_then(|x| self.queue.get(x));
+                if selected.is_some_and(|x| !self.is_shown(x)) {
+                    let first = self.queue.iter().position(|x| self.is_shown(x));
+                    self.queue_state.select(first);
+                }
+            }
+            Action::CopyTrackUrl => self.copy_selected_track_url(),
+            Action::RedownloadSelected => self.redownload_selected(),
+            Action::OpenDirPrompt => {
+                let dir = self.downloader.download_dir().unwrap_or_default();
+                self.dir_prompt = Some(Input::new(dir.display().to_string()));
+            }
+            Action::DirPromptCancel => self.dir_prompt = None,
+            Action::DirPromptConfirm => {
+                if let Some(prompt) = self.dir_prompt.take() {
+                    let dir = PathBuf::from(prompt.value());
+                    self.log(match self.downloader.set_download_dir(dir.clone()) {
+                        Ok(_) => LogEntry::Info(format!("Downloading to {}", dir.display())),
+                        Err(err) => LogEntry::Error(format!(
+                            "Unable to change the download directory: {}",
+                            err
+                        )),
+                    });
+                }
+            }
+            Action::SortQueue => {
+                let order = self.downloader.sort_queue();
+                sort_inactive(&mut self.queue, &order);
+                self.show_toast(String::from("Queue sorted by album"));
+            }
+            Action::ResumeDownloads => {
+                if self.downloader.is_paused() {
+                    self.downloader.resume();
+                    self.show_toast(String::from("Downloads resumed"));
+                }
+            }
+            Action::OpenTrackPicker => {
+                if let InputMode::Album = self.input_mode {
+                    if let Ok(id) = self.input.value().parse::<u64>() {
+                        self.input.reset();
+                        self.input_changed();
+                        self.downloader.request_album_tracks(id);
+                    }
+                }
+            }
+            Action::PickerUp => {
+                if let Some(picker) = self.track_picker.as_mut() {
+                    picker.select_previous();
+                }
+            }
+            Action::PickerDown => {
+                if let Some(picker) = self.track_picker.as_mut() {
+                    picker.select_next();
+                }
+            }
+            Action::PickerToggle => {
+                if let Some(picker) = self.track_picker.as_mut() {
+                    picker.toggle_selected();
+                }
+            }
+            Action::PickerToggleAll => {
+                if let Some(picker) = self.track_picker.as_mut() {
+                    picker.toggle_all();
+                }
+            }
+            Action::PickerConfirm => {
+                if let Some(picker) = self.close_track_picker() {
+                    let group_name = picker
+                        .tracks
+                        .first()
+                        .map(|x| format!("Album \"{}\"", x.track.album.title));
+                    self.downloader
+                        .enqueue_items(picker.into_checked_tracks(), group_name);
+                }
+            }
+            Action::PickerCancel => {
+                self.close_track_picker();
+            }
+            Action::RestoreSession => {
+                if let Some(state) = self.restore_prompt.take() {
+                    self.restore_session(state);
+                }
+            }
+            Action::DiscardSession => self.restore_prompt = None,
+            Action::ConfirmBatch => {
+                if let Some(batch) = self.batch_prompts.pop_front() {
+                    self.downloader.confirm_batch(batch);
+                }
+            }
+            Action::DeclineBatch => {
+                if let Some(batch) = self.batch_prompts.pop_front() {
+                    if let Some(album) = batch.album {
+                        self.downloader.forget_album_request(album);
+                    }
+                    self.log(LogEntry::Info(format!("{} wasn't queued", batch.name)));
+                }
+            }
+            Action::CollisionRename => {
+                let name = self
+                    .collisions
+                    .front()
+                    .map(|x| x.name.value().trim().to_string());
+                if let Some(name) = name.filter(|x| !x.is_empty()) {
+                    self.answer_collision(CollisionChoice::Rename(name), false);
+                }
+            }
+            Action::CollisionOverwrite => self.answer_collision(CollisionChoice::Overwrite, false),
+            Action::CollisionSkip => self.answer_collision(CollisionChoice::Skip, false),
+            Action::CollisionOverwriteAll => {
+                self.answer_collision(CollisionChoice::Overwrite, true)
+            }
+            Action::CollisionSkipAll => self.answer_collision(CollisionChoice::Skip, true),
         }
 
         Ok(())
     }
 
     fn update_progress(&mut self) {
-        while let Ok(progress) = self.downloader.progress_rx.try_recv() {
-            if let Some(str) = get_log_from_progress(&progress) {
-                self.logs.push(str);
+        let pending = self
+            .downloader
+            .progress_rx
+            .try_iter()
+            .take(MAX_PROGRESS_PER_TICK)
+            .collect::<Vec<_>>();
+        if !pending.is_empty() {
+            self.dirty = true;
+        }
+
+        for progress in pending {
+            if let Some(mut log) = get_log_from_progress(&progress, self.verbose_logs) {
+                // Track progress doesn't say which request the track comes from
+                if let (None, Some(id)) = (log.group, log.track_id) {
+                    log.group = self
+                        .queue
+                        .iter()
+                        .rev()
+                        .find(|x| x.item.track.id == id)
+                        .and_then(|x| x.item.group);
+                }
+                self.logs.push(log);
             }
 
             match progress {
-                DownloadProgress::Queue(track) => self.queue.push(QueueItem {
-                    song: track,
-                    status: DownloadStatus::Inactive,
-                }),
+                DownloadProgress::Queue(item) => {
+                    self.requested = true;
+                    self.queue.push(QueueItem {
+                        item,
+                        status: DownloadStatus::Inactive,
+                        path: None,
+                        error: None,
+                        progress: None,
+                        quality: None,
+                        mp3_copy: None,
+                    });
+                }
                 DownloadProgress::Start(track) => {
-                    for item in self.queue.iter_mut() {
-                        if item.song.id == track.id {
-                            item.status = DownloadStatus::Downloading
-                        }
+                    self.set_queue_status(
+                        &track,
+                        DownloadStatus::Inactive,
+                        DownloadStatus::Downloading,
+                    );
+                }
+                DownloadProgress::Progress(track, percent) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Downloading,
+                        DownloadStatus::Downloading,
+                    ) {
+                        queue_item.progress = Some(percent);
                     }
                 }
-                DownloadProgress::Finish(track) => {
-                    let pos = self
-                        .queue
-                        .iter()
-                        .position(|x| x.song.id == track.id)
-                        .expect("Track should be in queue.");
-                    self.queue.remove(pos);
+                DownloadProgress::Finish(track, path) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Downloading,
+                        DownloadStatus::Completed,
+                    ) {
+                        queue_item.path = Some(path.clone());
+                    }
+                    self.add_recent_download(RecentDownload {
+                        artist: track.artist.name,
+                        title: track.title,
+                        path,
+                    });
+                }
+                DownloadProgress::DownloadError(track, err) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Downloading,
+                        DownloadStatus::Failed,
+                    ) {
+                        queue_item.error = Some(err.to_string());
+                    }
+                }
+                DownloadProgress::Skipped(track, reason) => {
+                    let pos = match reason {
+                        // Skipped as soon as queued
+                        SkipReason::Explicit | SkipReason::NotExplicit | SkipReason::Unreadable => {
+                            self.queue_position(&track, DownloadStatus::Inactive, true)
+                        }
+                        // Skipped once taken by a worker, before it starts
+                        SkipReason::AlreadyDownloading => {
+                            self.queue_position(&track, DownloadStatus::Inactive, false)
+                        }
+                        _ => self.queue_position(&track, DownloadStatus::Downloading, false),
+                    };
+                    if let Some(pos) = pos {
+                        self.queue.remove(pos);
+                    }
                 }
-                DownloadProgress::DownloadError(track) => {
+                DownloadProgress::Cancelled(track) => {
+                    // Cancelling all downloads may already have removed the track
                     let pos = self
-                        .queue
-                        .iter()
-                        .position(|x| x.song.id == track.id)
-                        .expect("Track should be in queue.");
-                    self.queue.remove(pos);
+                        .queue_position(&track, DownloadStatus::Inactive, false)
+                        .or_else(|| {
+                            self.queue_position(&track, DownloadStatus::Downloading, false)
+                        });
+                    if let Some(pos) = pos {
+                        self.queue.remove(pos);
+                    }
                 }
                 DownloadProgress::SongNotFoundError(_) => {}
+                DownloadProgress::NotFoundRetry(_, _) => {}
+                DownloadProgress::GroupStarted(_, _, _) => {}
+                DownloadProgress::GroupFinished(_) => {}
+                DownloadProgress::Paused(_) => {}
+                DownloadProgress::Overwrite(_, _, _) => {}
+                DownloadProgress::QualityDowngrade(track, _, quality)
+                | DownloadProgress::QualityRetry(track, _, quality, _) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Downloading,
+                        DownloadStatus::Downloading,
+                    ) {
+                        queue_item.quality = Some(quality);
+                        queue_item.progress = None;
+                    }
+                }
+                DownloadProgress::Upgrade(_, _, _) => {}
+                DownloadProgress::SessionLimitReached(_) => {}
+                DownloadProgress::WorkerStatus(worker, track) => {
+                    if let Some(task) = self.worker_tasks.get_mut(worker) {
+                        *task = track.map(|x| (x, Instant::now()));
+                    }
+                }
+                DownloadProgress::WorkerInitError(_) => {}
+                DownloadProgress::ProxyError(_) => {}
+                DownloadProgress::HistoryError(_) => {}
+                DownloadProgress::SidecarCoverError(_, _) => {}
+                DownloadProgress::LyricsError(_, _) => {}
+                DownloadProgress::Mp3CopyProgress(track, percent) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Completed,
+                        DownloadStatus::Completed,
+                    ) {
+                        queue_item.mp3_copy = Some(percent);
+                    }
+                }
+                DownloadProgress::Mp3CopyFinish(track, path) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Completed,
+                        DownloadStatus::Completed,
+                    ) {
+                        queue_item.mp3_copy = None;
+                    }
+                    self.add_recent_download(RecentDownload {
+                        artist: track.artist.name,
+                        title: track.title,
+                        path,
+                    });
+                }
+                DownloadProgress::Mp3CopyError(track, _)
+                | DownloadProgress::Mp3CopySkipped(track) => {
+                    if let Some(queue_item) = self.set_queue_status(
+                        &track,
+                        DownloadStatus::Completed,
+                        DownloadStatus::Completed,
+                    ) {
+                        queue_item.mp3_copy = None;
+                    }
+                }
+                DownloadProgress::PostDownloadCommandError(_, _) => {}
+                DownloadProgress::AlbumMetadataError(_, _) => {}
+                DownloadProgress::NoWorkers => self.no_workers = true,
                 DownloadProgress::AlbumNotFoundError(_) => {}
+                DownloadProgress::AlbumAlreadyRequested(_) => {}
+                DownloadProgress::TrackRangeOutOfBounds(_, _, _) => {}
+                DownloadProgress::ContentFiltered(_) => {}
+                DownloadProgress::AlbumTrackNotFoundError(_, _) => {}
+                DownloadProgress::BarcodeNotFoundError(_) => {}
+                DownloadProgress::ShortLinkError(_) => {}
+                DownloadProgress::PlaylistNotFoundError(_) => {}
+                DownloadProgress::PlaylistTrackNotFoundError(_, _) => {}
+                DownloadProgress::PlaylistSynced(_, _) => {}
+                DownloadProgress::Collision(
+                    track,
+                    path,
+                    existing,
+                    existing_info,
+                    new_info,
+                    prompt,
+                ) => self.add_collision(track, path, existing, (existing_info, new_info), prompt),
+                DownloadProgress::AlbumTracks(id, tracks) => {
+                    self.open_track_picker(TrackPicker::new(id, tracks));
+                }
+                DownloadProgress::LargeBatch(batch) => self.batch_prompts.push_back(batch),
             }
         }
     }
 
-    fn quit(&mut self) {
-        self.should_quit = true;
+    /// Whether a queue item is shown in the queue pane.
+    fn is_shown(&self, queue_item: &QueueItem) -> bool {
+        !self.hide_inactive || queue_item.status != DownloadStatus::Inactive
     }
 
-    fn ui(&mut self, f: &mut Frame) -> Result<()> {
-        let area = f.size();
+    /// Move the selection forward or backward by a number of shown queue items, stopping
+    /// at the ends. Single steps wrap around them instead when configured to.
+    fn step_queue_selection(&mut self, forward: bool, steps: usize) {
+        let shown = (0..self.queue.len())
+            .filter(|x| self.is_shown(&self.queue[*x]))
+            .collect::<Vec<_>>();
+        let Some(last) = shown.len().checked_sub(1) else {
+            return;
+        };
+        // Without a selection, the first step goes to the first item
+        let Some(selected) = self.queue_state.selected() else {
+            let position = if forward { (steps - 1).min(last) } else { 0 };
+            self.queue_state.select(Some(shown[position]));
+            return;
+        };
 
-        let info_chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![Constraint::Min(1), Constraint::Length(1)])
-            .split(area);
+        let wrap = self.wrap_queue_selection && steps == 1;
+        let position = shown.partition_point(|x| *x < selected).min(last);
+        let position = match forward {
+            true if wrap && position == last => 0,
+            true => position.saturating_add(steps).min(last),
+            false if wrap && position == 0 => last,
+            false => position.saturating_sub(steps),
+        };
+        self.queue_state.select(Some(shown[position]));
+    }
 
-        let main_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Percentage(70), Constraint::Percentage(30)])
-            .split(info_chunks[0]);
+    /// Select a queue item, staying within the queue's bounds.
+    fn select_queue_item(&mut self, index: usize) {
+        let selected = match self.queue.len() {
+            0 => None,
+            len => Some(index.min(len - 1)),
+        };
+        self.queue_state.select(selected);
+    }
 
-        let log_chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![Constraint::Min(1), Constraint::Length(3)])
-            .split(main_chunks[0]);
+    /// Show a track picker, after the one already open if there is one so that its
+    /// selection isn't lost.
+    fn open_track_picker(&mut self, picker: TrackPicker) {
+        if self.track_picker.is_some() {
+            self.queued_pickers.push_back(picker);
+        } else {
+            self.track_picker = Some(picker);
+        }
+    }
 
-        let input_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Length(7), Constraint::Min(1)])
-            .split(log_chunks[1]);
+    /// Prompt for a new file name, unless a choice was already made for all collisions.
+    fn add_collision(
+        &mut self,
+        track: Track,
+        path: PathBuf,
+        existing: PathBuf,
+        infos: (FileInfo, FileInfo),
+        prompt: CollisionPrompt,
+    ) {
+        if let Some(choice) = &self.collision_choice_for_all {
+            prompt.answer(choice.clone());
+            return;
+        }
 
-        self.render_info(f, info_chunks[1]);
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.collisions.push_back(Collision {
+            track,
+            existing,
+            infos,
+            prompt,
+            name: Input::new(name),
+        });
+    }
 
-        self.render_logs(f, log_chunks[0]);
+    /// Answer the prompted collision, and every later one too when `for_all` is set.
+    fn answer_collision(&mut self, choice: CollisionChoice, for_all: bool) {
+        if let Some(collision) = self.collisions.pop_front() {
+            collision.prompt.answer(choice.clone());
+        }
+        if for_all {
+            for collision in self.collisions.drain(..) {
+                collision.prompt.answer(choice.clone());
+            }
+            self.collision_choice_for_all = Some(choice);
+        }
+    }
 
-        f.render_widget(
-            Paragraph::new(self.input_mode.to_string())
-                .alignment(Alignment::Center)
-                .block(Block::default().padding(Padding::uniform(1))),
-            input_chunks[0],
-        );
-        self.render_input(f, input_chunks[1]);
+    /// Close the open track picker, returning it, and show the next one.
+    fn close_track_picker(&mut self) -> Option<TrackPicker> {
+        let picker = self.track_picker.take();
+        self.track_picker = self.queued_pickers.pop_front();
+        picker
+    }
 
-        // Queue list
-        self.render_queue_list(f, main_chunks[1]);
+    /// Position of the track's first queue item in a status, or its last one with `last`.
+    fn queue_position(&self, track: &Track, status: DownloadStatus, last: bool) -> Option<usize> {
+        let matches = |x: &QueueItem| x.item.track.id == track.id && x.status == status;
+        match last {
+            true => self.queue.iter().rposition(matches),
+            false => self.queue.iter().position(matches),
+        }
+    }
 
-        Ok(())
+    /// Change the status of the track's first queue item in the `from` status, returning
+    /// the item if it was found.
+    fn set_queue_status(
+        &mut self,
+        track: &Track,
+        from: DownloadStatus,
+        to: DownloadStatus,
+    ) -> Option<&mut QueueItem> {
+        let queue_item = self
+            .queue
+            .iter_mut()
+            .find(|x| x.item.track.id == track.id && x.status == from)?;
+        queue_item.status = to;
+        Some(queue_item)
     }
 
-    fn render_info(&self, f: &mut Frame, rect: Rect) {
-        let key_style = Style::default();
-        let command_style = Style::default().on_dark_gray();
+    fn redownload_selected(&mut self) {
+        let Some(selected) = self.queue_state.selected() else {
+            return;
+        };
+        if !self.queue.get(selected).is_some_and(|x| x.is_done()) {
+            return;
+        }
 
-        static COMMANDS: [(&str, &str); 4] = [
+        let QueueItem { mut item, .. } = self.queue.remove(selected);
+        // The group of the original request has already been summarized
+        item.group = None;
+        self.log(LogEntry::Info(format!(
+            "{} - {} queued again",
+            item.track.artist.name, item.track.title
+        )));
+        self.downloader.enqueue_items(vec![item], None);
+    }
+
+    fn copy_selected_track_url(&mut self) {
+        let Some(item) = self.queue_state.selected().and_then(|x| self.queue.get(x)) else {
+            return;
+        };
+        let url = get_track_url(&item.item.track);
+
+        if self.clipboard.is_none() {
+            self.clipboard = arboard::Clipboard::new().ok().map(Clipboard);
+        }
+        let result = match self.clipboard.as_mut() {
+            Some(Clipboard(clipboard)) => clipboard.set_text(url.clone()),
+            None => Err(arboard::Error::ClipboardNotSupported),
+        };
+
+        match result {
+            Ok(_) => self.show_toast(format!("Copied {} to the clipboard", url)),
+            Err(err) => self.log(LogEntry::Error(format!("Unable to copy {}: {}", url, err))),
+        }
+    }
+
+    /// Whether every queued download is over, no more requests are coming and nothing
+    /// else is awaiting the user. Requests still being looked up are left to the caller.
+    fn is_done(&self) -> bool {
+        self.requested
+            && !self.reading_requests
+            && self.queue.iter().all(|x| x.is_done())
+            && self.input.value().is_empty()
+            && self.track_picker.is_none()
+            && self.dir_prompt.is_none()
+            && self.batch_prompts.is_empty()
+    }
+
+    fn add_recent_download(&mut self, download: RecentDownload) {
+        if self.recent_downloads.len() == RECENT_DOWNLOADS {
+            self.recent_downloads.pop_front();
+        }
+        self.recent_downloads.push_back(download);
+    }
+
+    fn log(&mut self, entry: LogEntry) {
+        self.logs.push(entry.into());
+        self.dirty = true;
+    }
+
+    fn show_toast(&mut self, message: String) {
+        self.toast = Some((message, Instant::now()));
+    }
+
+    fn input_changed(&mut self) {
+        self.preview = None;
+        self.input_changed_at = Some(Instant::now());
+    }
+
+    /// Look up the typed song id once the input stopped changing for [PREVIEW_DELAY].
+    fn update_preview(&mut self) {
+        let Some(changed_at) = self.input_changed_at else {
+            return;
+        };
+        if changed_at.elapsed() < PREVIEW_DELAY {
+            return;
+        }
+        self.input_changed_at = None;
+
+        let (InputMode::Song, Ok(id)) = (&self.input_mode, self.input.value().parse::<Id>()) else {
+            return;
+        };
+        let Some(event_tx) = self.event_tx.clone() else {
+            return;
+        };
+
+        let client = self.downloader.client();
+        tokio::spawn(async move {
+            let track = client.track(id).await.ok().flatten().map(Box::new);
+
+            // The app may have quit during the lookup, in which case nobody wants the result
+            let _ = event_tx.send(Event::Preview(id, track));
+        });
+    }
+
+    /// Save the queue and the last logs, to be restored if the app doesn't exit cleanly.
+    fn save_session(&mut self) {
+        self.session_saved_at = Instant::now();
+        let Some(session) = &self.session else {
+            return;
+        };
+
+        let state = SessionState {
+            queue: self
+                .queue
+                .iter()
+                .map(|x| SavedQueueItem {
+                    item: x.item.clone(),
+                    status: x.status,
+                    path: x.path.clone(),
+                    error: x.error.clone(),
+                })
+                .collect(),
+            logs: self.logs[self.logs.len().saturating_sub(SESSION_LOGS)..]
+                .iter()
+                .map(SavedLog::from)
+                .collect(),
+        };
+        if let Err(err) = session.save(&state) {
+            // Reported once rather than every few seconds
+            self.session = None;
+            self.log(LogEntry::Error(format!(
+                "Unable to save the session, it won't be restored after a crash: {}",
+                err
+            )));
+        }
+    }
+
+    /// Bring back the queue and logs of a previous session, downloading again the tracks
+    /// that weren't done.
+    fn restore_session(&mut self, state: SessionState) {
+        let mut logs = state
+            .logs
+            .into_iter()
+            .map(LogLine::from)
+            .collect::<Vec<_>>();
+        logs.append(&mut self.logs);
+        self.logs = logs;
+
+        let mut pending = Vec::new();
+        for saved in state.queue {
+            // Groups are numbered anew by each session
+            let item = DownloadItem {
+                group: None,
+                ..saved.item
+            };
+            match saved.status {
+                DownloadStatus::Completed | DownloadStatus::Failed => self.queue.push(QueueItem {
+                    item,
+                    status: saved.status,
+                    path: saved.path,
+                    error: saved.error,
+                    progress: None,
+                    quality: None,
+                    mp3_copy: None,
+                }),
+                // Downloads cut short are started over
+                DownloadStatus::Inactive | DownloadStatus::Downloading => pending.push(item),
+            }
+        }
+
+        self.log(LogEntry::Info(format!(
+            "Restored the previous session, {} download(s) queued again",
+            pending.len()
+        )));
+        if !pending.is_empty() {
+            self.downloader.enqueue_items(pending, None);
+        }
+    }
+
+    fn quit(&mut self) {
+        self.should_quit = true;
+    }
+
+    fn ui(&mut self, f: &mut Frame) -> Result<()> {
+        let area = f.size();
+
+        let info_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(1), Constraint::Length(1)])
+            .split(area);
+
+        let main_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Percentage(70), Constraint::Percentage(30)])
+            .split(info_chunks[0]);
+
+        let preview_height = if self.preview.is_some() { 3 } else { 0 };
+        let log_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Min(1),
+                Constraint::Length(preview_height),
+                Constraint::Length(3),
+            ])
+            .split(main_chunks[0]);
+
+        let input_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Length(11), Constraint::Min(1)])
+            .split(log_chunks[2]);
+
+        self.render_info(f, info_chunks[1]);
+
+        self.render_logs(f, log_chunks[0]);
+
+        self.render_preview(f, log_chunks[1]);
+
+        f.render_widget(
+            Paragraph::new(format!(
+                "{} {}",
+                get_input_mode_symbol(&self.input_mode),
+                self.input_mode
+            ))
+            .style(
+                Style::default()
+                    .fg(get_input_mode_color(&self.input_mode))
+                    .bold(),
+            )
+            .alignment(Alignment::Center)
+            .block(Block::default().padding(Padding::uniform(1))),
+            input_chunks[0],
+        );
+        self.render_input(f, input_chunks[1]);
+
+        // Queue list
+        self.render_queue_list(f, main_chunks[1]);
+
+        if self.restore_prompt.is_some() {
+            self.render_restore_prompt(f, main_chunks[0]);
+        } else if !self.batch_prompts.is_empty() {
+            self.render_batch_prompt(f, main_chunks[0]);
+        } else if self.track_picker.is_some() {
+            self.render_track_picker(f, main_chunks[0]);
+        } else if self.show_queue_item_details {
+            self.render_queue_item_details(f, main_chunks[0]);
+        } else if self.show_workers {
+            self.render_workers(f, main_chunks[0]);
+        } else if self.show_recent_downloads {
+            self.render_recent_downloads(f, main_chunks[0]);
+        }
+
+        self.render_toast(f, log_chunks[0]);
+
+        Ok(())
+    }
+
+    fn render_info(&self, f: &mut Frame, rect: Rect) {
+        let key_style = Style::default();
+        let command_style = Style::default().on_dark_gray();
+
+        // Most useful first, the last ones are left out when the terminal is too narrow
+        let input_mode_key = self.keys.cycle_input_mode.to_string();
+        let focus_key = self.keys.cycle_focus.to_string();
+        let arrows_command = match self.focus {
+            Focus::Input => "Previous inputs",
+            Focus::Logs => "Scroll logs",
+            Focus::Queue => "Select in queue",
+        };
+        let commands = [
             ("Esc", "Quit"),
-            ("PgUp/PgDown", "Scroll logs"),
-            ("Tab", "Toggle Song ↔ Album"),
             ("Enter", "Start Download"),
+            (input_mode_key.as_str(), "Cycle Song/Album/Playlist/Barcode"),
+            (focus_key.as_str(), "Switch pane"),
+            ("PgUp/PgDown", "Scroll focused pane"),
+            ("Ctrl+T", "Pick album tracks"),
+            ("Ctrl+C", "Cancel all"),
+            ("↑/↓", arrows_command),
+            ("Home/End", "First/last in queue"),
+            ("Ctrl+Y", "Copy track URL"),
+            ("Ctrl+R", "Download again"),
+            ("Ctrl+S", "Sort queue by album"),
+            ("Ctrl+F", "Hide queued items"),
+            ("Ctrl+P", "Resume downloads"),
+            ("Ctrl+O", "Change download directory"),
+            ("Ctrl+W", "Wrap logs"),
+            ("Ctrl+L", "Verbose logs"),
+            ("Ctrl+D", "Recent downloads"),
+            ("Ctrl+K", "Workers"),
+            ("Enter in queue", "Track details"),
+        ];
+        static PICKER_COMMANDS: [(&str, &str); 5] = [
+            ("Esc", "Cancel"),
+            ("↑/↓", "Move"),
+            ("Space", "Toggle track"),
+            ("A", "Toggle all"),
+            ("Enter", "Download selected"),
+        ];
+        static POPUP_COMMANDS: [(&str, &str); 1] = [("Esc", "Close")];
+        static WORKERS_COMMANDS: [(&str, &str); 3] = [
+            ("Esc", "Close"),
+            ("↑/↓", "Move"),
+            ("Space", "Pause/resume worker"),
         ];
+        static DIR_PROMPT_COMMANDS: [(&str, &str); 2] = [("Esc", "Cancel"), ("Enter", "Apply")];
+        static COLLISION_COMMANDS: [(&str, &str); 5] = [
+            ("Enter", "Rename"),
+            ("Esc", "Skip"),
+            ("Ctrl+O", "Overwrite"),
+            ("Ctrl+A", "Overwrite all"),
+            ("Ctrl+S", "Skip all"),
+        ];
+
+        static RESTORE_COMMANDS: [(&str, &str); 2] = [("Enter", "Restore"), ("Esc", "Discard")];
+        static BATCH_COMMANDS: [(&str, &str); 2] = [("Y/Enter", "Download"), ("N/Esc", "Cancel")];
+
+        let commands: &[(&str, &str)] = if !self.collisions.is_empty() {
+            &COLLISION_COMMANDS
+        } else if self.restore_prompt.is_some() {
+            &RESTORE_COMMANDS
+        } else if !self.batch_prompts.is_empty() {
+            &BATCH_COMMANDS
+        } else if self.track_picker.is_some() {
+            &PICKER_COMMANDS
+        } else if self.show_queue_item_details {
+            &POPUP_COMMANDS
+        } else if self.show_workers {
+            &WORKERS_COMMANDS
+        } else if self.show_recent_downloads {
+            &POPUP_COMMANDS
+        } else if self.dir_prompt.is_some() {
+            &DIR_PROMPT_COMMANDS
+        } else {
+            &commands
+        };
 
         let mut commands_spans = Vec::new();
-        for (a, b) in COMMANDS {
+        let mut width = 0;
+        for (a, b) in commands {
+            // Leave out the commands that don't fit rather than cutting them
+            width += a.chars().count() + b.chars().count() + 4;
+            if width > rect.width as usize {
+                break;
+            }
+
             commands_spans.append(&mut vec![
                 Span::styled(format!(" {} ", a), key_style),
                 Span::styled(format!(" {} ", b), command_style),
@@ -226,21 +1532,52 @@ impl App {
     }
 
     fn render_logs(&mut self, f: &mut Frame, rect: Rect) {
+        let download_dir = match self.downloader.download_dir() {
+            Some(dir) => format!(" Downloading to {} ", dir.display()),
+            None => String::from(" No download directory "),
+        };
         let logs_block = Block::default()
             .title("Logs")
+            .title(
+                block::Title::from(Span::styled(download_dir, Style::default().fg(Color::Gray)))
+                    .position(block::Position::Bottom)
+                    .alignment(Alignment::Right),
+            )
             .borders(Borders::all())
-            .border_type(BorderType::Rounded);
-        let height = logs_block.inner(rect).height as usize;
+            .border_type(get_border_type(self.focus == Focus::Logs));
+        let inner = logs_block.inner(rect);
+        let height = inner.height as usize;
 
-        f.render_widget(
-            Paragraph::new(self.logs.iter().map(|x| format_log(x)).collect::<Vec<_>>())
-                .scroll((self.logs_offset, 0))
-                .block(logs_block),
-            rect,
-        );
+        let lines = if self.log_format.group_requests {
+            group_logs(&self.logs)
+        } else {
+            self.logs.iter().map(|x| (x, false)).collect()
+        }
+        .into_iter()
+        .map(|(log, indented)| {
+            let mut line = format_log(log, &self.log_format);
+            if indented {
+                line.spans.insert(0, Span::raw("  "));
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+        let line_count = if self.wrap_logs {
+            lines.iter().map(|x| wrapped_height(x, inner.width)).sum()
+        } else {
+            lines.len()
+        };
+
+        let mut paragraph = Paragraph::new(lines)
+            .scroll((self.logs_offset, 0))
+            .block(logs_block);
+        if self.wrap_logs {
+            paragraph = paragraph.wrap(Wrap { trim: false });
+        }
+        f.render_widget(paragraph, rect);
 
         // Adjust vertical position so the bar is fully scrolled when the last item is at the bottom of the screen
-        let max_position = self.logs.len().saturating_sub(height) * (height + 1);
+        let max_position = line_count.saturating_sub(height) * (height + 1);
         let offset = (self.logs_offset as usize) * height;
         let mut scrollbar_state = ScrollbarState::default()
             .content_length(max_position)
@@ -255,12 +1592,125 @@ impl App {
         );
     }
 
+    fn render_preview(&self, f: &mut Frame, rect: Rect) {
+        let Some((id, track)) = &self.preview else {
+            return;
+        };
+
+        let line = match track {
+            Some(track) => Line::from(vec![
+                Span::styled(format!("{} ", track.artist.name), Style::default().bold()),
+                Span::raw(format!("- {} ", track.title)),
+                Span::styled(
+                    format!(
+                        "({}, {}:{:02})",
+                        track.album.title,
+                        track.duration / 60,
+                        track.duration % 60
+                    ),
+                    Style::default().fg(Color::Gray),
+                ),
+            ]),
+            None => Line::styled(
+                format!("No song found with id {}", id),
+                Style::default().fg(Color::Red),
+            ),
+        };
+
+        f.render_widget(
+            Paragraph::new(line).block(
+                Block::default()
+                    .title("Preview")
+                    .borders(Borders::all())
+                    .border_type(BorderType::Rounded)
+                    .padding(Padding::horizontal(1)),
+            ),
+            rect,
+        );
+    }
+
+    /// Render the toast over the bottom right corner of the given area.
+    fn render_toast(&self, f: &mut Frame, rect: Rect) {
+        let Some((message, _)) = &self.toast else {
+            return;
+        };
+
+        let width = (message.chars().count() as u16 + 4).min(rect.width);
+        let height = 3.min(rect.height);
+        let toast_rect = Rect {
+            x: rect.right() - width,
+            y: rect.bottom() - height,
+            width,
+            height,
+        };
+
+        f.render_widget(Clear, toast_rect);
+        f.render_widget(
+            Paragraph::new(message.as_str()).block(
+                Block::default()
+                    .borders(Borders::all())
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::LightGreen))
+                    .padding(Padding::horizontal(1)),
+            ),
+            toast_rect,
+        );
+    }
+
     fn render_input(&mut self, f: &mut Frame, rect: Rect) {
+        if let Some(collision) = self.collisions.front() {
+            let waiting = match self.collisions.len() - 1 {
+                0 => String::new(),
+                count => format!(" ({} more waiting)", count),
+            };
+            f.render_widget(
+                Paragraph::new(collision.name.value()).block(
+                    Block::default()
+                        .title(format!(
+                            "{} - {} ({}): name taken by {} ({}){}",
+                            collision.track.artist.name,
+                            collision.track.title,
+                            collision.infos.1,
+                            collision.existing.display(),
+                            collision.infos.0,
+                            waiting
+                        ))
+                        .borders(Borders::all())
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Yellow))
+                        .padding(Padding::horizontal(1)),
+                ),
+                rect,
+            );
+            f.set_cursor(
+                collision.name.visual_cursor() as u16 + 2 + rect.x,
+                rect.y + 1,
+            );
+            return;
+        }
+
+        if let Some(prompt) = &self.dir_prompt {
+            f.render_widget(
+                Paragraph::new(prompt.value()).block(
+                    Block::default()
+                        .title("Download directory")
+                        .borders(Borders::all())
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Yellow))
+                        .padding(Padding::horizontal(1)),
+                ),
+                rect,
+            );
+            f.set_cursor(prompt.visual_cursor() as u16 + 2 + rect.x, rect.y + 1);
+            return;
+        }
+
         f.render_widget(
             Paragraph::new(self.input.value()).block(
                 Block::default()
                     .borders(Borders::all())
-                    .border_type(BorderType::Rounded)
+                    .border_type(get_border_type(self.focus == Focus::Input))
+                    .border_style(Style::default().fg(get_input_mode_color(&self.input_mode)))
                     .padding(Padding::horizontal(1)),
             ),
             rect,
@@ -269,52 +1719,1234 @@ impl App {
     }
 
     fn render_queue_list(&mut self, f: &mut Frame, rect: Rect) {
+        // Items may have been removed since the selection was made
+        if let Some(selected) = self.queue_state.selected() {
+            self.select_queue_item(selected);
+        }
+
+        // Queued items beyond the busy workers are waiting for one to be free
+        let busy_workers = self
+            .queue
+            .iter()
+            .filter(|x| x.status == DownloadStatus::Downloading)
+            .count();
+        let workers = self.downloader.workers();
+        let workers_color = if busy_workers as u64 >= workers {
+            Color::Yellow
+        } else {
+            Color::Gray
+        };
+
+        let mut queue_block = Block::default()
+            .borders(Borders::all())
+            .border_type(get_border_type(self.focus == Focus::Queue))
+            .title("Download queue")
+            .title(
+                block::Title::from(Span::styled(
+                    format!(" {}/{} workers busy ", busy_workers, workers),
+                    Style::default().fg(workers_color),
+                ))
+                .alignment(Alignment::Right),
+            );
+        if self.no_workers {
+            queue_block = queue_block
+                .title(Span::styled(
+                    " No download worker, restart once online ",
+                    Style::default().fg(Color::White).on_red().bold(),
+                ))
+                .border_style(Style::default().fg(Color::Red));
+        } else if let Some(resume_at) = self.downloader.scheduled_until() {
+            queue_block = queue_block
+                .title(Span::styled(
+                    format!(
+                        " Scheduled, downloads resume at {} ",
+                        resume_at.format("%H:%M")
+                    ),
+                    Style::default().fg(Color::Black).on_yellow().bold(),
+                ))
+                .border_style(Style::default().fg(Color::Yellow));
+        } else if self.downloader.is_paused() {
+            queue_block = queue_block
+                .title(Span::styled(
+                    " Paused, press Ctrl+P to resume ",
+                    Style::default().fg(Color::White).on_red().bold(),
+                ))
+                .border_style(Style::default().fg(Color::Red));
+        }
+
+        let shown = (0..self.queue.len())
+            .filter(|x| self.is_shown(&self.queue[*x]))
+            .collect::<Vec<_>>();
+        let selected = self.queue_state.selected();
+        let mut items = shown
+            .iter()
+            .map(|index| {
+                let x = &self.queue[*index];
+                let mut spans = vec![
+                    Span::styled(
+                        format!("[{}]", x.status),
+                        Style::default().fg(get_status_color(&x.status)).bold(),
+                    ),
+                    Span::styled(
+                        format!(" {} ", x.item.track.artist.name),
+                        Style::default().bold(),
+                    ),
+                    Span::raw(format!("- {}", x.item.track.title.clone())),
+                ];
+                if let Some(quality) = x.quality.filter(|_| !x.is_done() || x.path.is_some()) {
+                    spans.push(Span::styled(
+                        format!(" [{}]", quality),
+                        Style::default().fg(Color::Gray),
+                    ));
+                }
+                if let Some(percent) = x
+                    .progress
+                    .filter(|_| x.status == DownloadStatus::Downloading)
+                {
+                    spans.insert(
+                        1,
+                        Span::styled(
+                            format!(" {}%", percent),
+                            Style::default().fg(Color::LightBlue),
+                        ),
+                    );
+                }
+                if let Some(percent) = x.mp3_copy {
+                    spans.push(Span::styled(
+                        format!(" [MP3 {}%]", percent),
+                        Style::default().fg(Color::LightBlue),
+                    ));
+                }
+                // Only the selected item gets its reason, the list stays readable
+                if let Some(error) = x.error.as_ref().filter(|_| selected == Some(*index)) {
+                    spans.push(Span::styled(
+                        format!(" ({})", error),
+                        Style::default().fg(Color::Red),
+                    ));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect::<Vec<_>>();
+        let hidden = self.queue.len() - shown.len();
+        if hidden > 0 {
+            items.push(ListItem::new(Line::styled(
+                format!("(+{} queued)", hidden),
+                Style::default().fg(Color::Gray),
+            )));
+        }
+
+        let height = queue_block.inner(rect).height as usize;
+        self.queue_page_size = height.max(1);
+        let line_count = items.len();
+        let list = List::new(items)
+            .highlight_style(Style::default().on_dark_gray())
+            .block(queue_block);
+        if self.hide_inactive {
+            // The selection is an index in the whole queue, not in the shown items
+            let selected = self
+                .queue_state
+                .selected()
+                .and_then(|selected| shown.iter().position(|x| *x == selected));
+            let mut state = ListState::default()
+                .with_selected(selected)
+                .with_offset(self.queue_state.offset());
+            f.render_stateful_widget(list, rect, &mut state);
+            *self.queue_state.offset_mut() = state.offset();
+        } else {
+            f.render_stateful_widget(list, rect, &mut self.queue_state);
+        }
+
+        // Adjust vertical position so the bar is fully scrolled when the last item is at the bottom of the screen
+        let max_position = line_count.saturating_sub(height) * (height + 1);
+        let offset = self.queue_state.offset() * height;
+        let mut scrollbar_state = ScrollbarState::default()
+            .content_length(max_position)
+            .position(offset);
+        f.render_stateful_widget(
+            Scrollbar::default().begin_symbol(None).end_symbol(None),
+            rect.inner(&Margin {
+                horizontal: 1,
+                vertical: 1,
+            }),
+            &mut scrollbar_state,
+        );
+    }
+
+    fn render_queue_item_details(&self, f: &mut Frame, rect: Rect) {
+        let Some(queue_item) = self.queue_state.selected().and_then(|x| self.queue.get(x)) else {
+            return;
+        };
+        let track = &queue_item.item.track;
+        let yes_no = |value| if value { "Yes" } else { "No" };
+        let path = match &queue_item.path {
+            Some(path) => path.display().to_string(),
+            None => String::from("Not written yet"),
+        };
+
+        let fields = [
+            ("Title", track.title.clone()),
+            ("Artist", track.artist.name.clone()),
+            ("Album", track.album.title.clone()),
+            (
+                "Duration",
+                format!("{}:{:02}", track.duration / 60, track.duration % 60),
+            ),
+            ("Release date", track.release_date.clone()),
+            ("Explicit", String::from(yes_no(track.explicit_lyrics))),
+            ("Readable", String::from(yes_no(track.readable))),
+            ("Track id", track.id.to_string()),
+            ("Status", queue_item.status.to_string()),
+            ("Path", path),
+        ];
+        let error = queue_item.error.clone().map(|error| ("Failure", error));
+        let lines = fields
+            .into_iter()
+            .chain(error)
+            .map(|(name, value)| {
+                Line::from(vec![
+                    Span::styled(format!("{}: ", name), Style::default().bold()),
+                    Span::raw(value),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        f.render_widget(Clear, rect);
         f.render_widget(
-            List::new(
-                self.queue
-                    .iter()
-                    .map(|x| {
-                        ListItem::new(Line::from(vec![
-                            Span::styled(
-                                format!("[{}]", x.status),
-                                Style::default().fg(get_status_color(&x.status)).bold(),
-                            ),
-                            Span::styled(
-                                format!(" {} ", x.song.artist.name),
-                                Style::default().bold(),
-                            ),
-                            Span::raw(format!("- {}", x.song.title.clone())),
-                        ]))
-                    })
-                    .collect::<Vec<_>>(),
-            )
-            .block(
+            Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                Block::default()
+                    .borders(Borders::all())
+                    .border_type(BorderType::Rounded)
+                    .padding(Padding::horizontal(1))
+                    .title("Track details"),
+            ),
+            rect,
+        );
+    }
+
+    fn render_restore_prompt(&self, f: &mut Frame, rect: Rect) {
+        let Some(state) = &self.restore_prompt else {
+            return;
+        };
+        let finished = state
+            .queue
+            .iter()
+            .filter(|x| matches!(x.status, DownloadStatus::Completed | DownloadStatus::Failed))
+            .count();
+
+        let text = format!(
+            "The previous session didn't exit cleanly. Restore its {} unfinished and {} finished download(s), and its {} log entries?",
+            state.queue.len() - finished,
+            finished,
+            state.logs.len()
+        );
+
+        f.render_widget(Clear, rect);
+        f.render_widget(
+            Paragraph::new(text).wrap(Wrap { trim: false }).block(
+                Block::default()
+                    .borders(Borders::all())
+                    .border_type(BorderType::Rounded)
+                    .padding(Padding::horizontal(1))
+                    .title("Restore session"),
+            ),
+            rect,
+        );
+    }
+
+    fn render_batch_prompt(&self, f: &mut Frame, rect: Rect) {
+        let Some(batch) = self.batch_prompts.front() else {
+            return;
+        };
+        let megabytes = batch.size as f64 / 1024.0 / 1024.0;
+        let size = if megabytes >= 1024.0 {
+            format!("{:.1} GB", megabytes / 1024.0)
+        } else {
+            format!("{:.1} MB", megabytes)
+        };
+
+        let text = format!(
+            "About to download {} tracks of {}, ~{} in {}. Proceed?",
+            batch.items.len(),
+            batch.name,
+            size,
+            batch.quality
+        );
+
+        f.render_widget(Clear, rect);
+        f.render_widget(
+            Paragraph::new(text).wrap(Wrap { trim: false }).block(
                 Block::default()
                     .borders(Borders::all())
                     .border_type(BorderType::Rounded)
-                    .title("Download queue"),
+                    .padding(Padding::horizontal(1))
+                    .title("Large download"),
             ),
             rect,
         );
     }
+
+    fn render_workers(&self, f: &mut Frame, rect: Rect) {
+        let rows = self
+            .worker_tasks
+            .iter()
+            .enumerate()
+            .map(|(index, task)| {
+                let paused = self.downloader.is_worker_paused(index);
+                let (mut track, elapsed) = match task {
+                    Some((track, started_at)) => (
+                        format!("{} - {}", track.artist.name, track.title),
+                        format!("{}s", started_at.elapsed().as_secs()),
+                    ),
+                    None if paused => (String::from("Paused"), String::new()),
+                    None => (String::from("Idle"), String::new()),
+                };
+                // Its current track is still downloaded
+                if paused && task.is_some() {
+                    track = format!("Pausing after {}", track);
+                }
+
+                let row = Row::new(vec![format!("#{}", index + 1), track, elapsed]);
+                match index == self.selected_worker {
+                    true => row.on_dark_gray(),
+                    false => row,
+                }
+            })
+            .collect::<Vec<_>>();
+        // The track takes what the other columns, the borders and the spacing leave
+        let widths = [
+            Constraint::Length(6),
+            Constraint::Length(rect.width.saturating_sub(6 + 8 + 2 + 2)),
+            Constraint::Length(8),
+        ];
+
+        f.render_widget(Clear, rect);
+        f.render_widget(
+            Table::new(rows)
+                .header(Row::new(vec!["Worker", "Track", "Elapsed"]).bold())
+                .widths(&widths)
+                .block(
+                    Block::default()
+                        .borders(Borders::all())
+                        .border_type(BorderType::Rounded)
+                        .title("Workers"),
+                ),
+            rect,
+        );
+    }
+
+    fn render_recent_downloads(&self, f: &mut Frame, rect: Rect) {
+        let items = self
+            .recent_downloads
+            .iter()
+            .rev()
+            .map(|download| {
+                ListItem::new(vec![
+                    Line::from(vec![
+                        Span::styled(format!("{} ", download.artist), Style::default().bold()),
+                        Span::raw(format!("- {}", download.title)),
+                    ]),
+                    Line::styled(
+                        format!("  {}", download.path.display()),
+                        Style::default().fg(Color::Gray),
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        f.render_widget(Clear, rect);
+        f.render_widget(
+            List::new(items).block(
+                Block::default()
+                    .borders(Borders::all())
+                    .border_type(BorderType::Rounded)
+                    .title(format!(
+                        "Recent downloads ({})",
+                        self.recent_downloads.len()
+                    )),
+            ),
+            rect,
+        );
+    }
+
+    fn render_track_picker(&mut self, f: &mut Frame, rect: Rect) {
+        let Some(picker) = self.track_picker.as_mut() else {
+            return;
+        };
+
+        let items = picker
+            .tracks
+            .iter()
+            .zip(picker.checked.iter())
+            .map(|(DownloadItem { track, .. }, checked)| {
+                ListItem::new(Line::from(vec![
+                    Span::raw(if *checked { "[x] " } else { "[ ] " }),
+                    Span::raw(format!("{:02}. ", track.track_position_in_album)),
+                    Span::styled(format!("{} ", track.artist.name), Style::default().bold()),
+                    Span::raw(format!("- {}", track.title)),
+                ]))
+            })
+            .collect::<Vec<_>>();
+
+        let selected_count = picker.checked.iter().filter(|x| **x).count();
+        let waiting = match self.queued_pickers.len() {
+            0 => String::new(),
+            count => format!(", {} more album(s) waiting", count),
+        };
+
+        f.render_widget(Clear, rect);
+        f.render_stateful_widget(
+            List::new(items)
+                .highlight_style(Style::default().on_dark_gray())
+                .block(
+                    Block::default()
+                        .borders(Borders::all())
+                        .border_type(BorderType::Rounded)
+                        .title(format!(
+                            "Album {} ({}/{} selected{})",
+                            picker.album_id,
+                            selected_count,
+                            picker.tracks.len(),
+                            waiting
+                        )),
+                ),
+            rect,
+            &mut picker.state,
+        );
+    }
 }
 
-fn format_log(log: &LogEntry) -> Line {
-    match log {
-        LogEntry::Success(msg) => Line::from(vec![
-            Span::styled("[Success] ", Style::default().fg(Color::LightGreen).bold()),
-            Span::raw(msg),
-        ]),
-        LogEntry::Error(msg) => Line::from(vec![
-            Span::styled("[Error] ", Style::default().fg(Color::Red).bold()),
-            Span::raw(msg),
-        ]),
+/// Sort the items waiting to be downloaded in the order of the downloader's queue, given
+/// by track id, without moving the other ones. Those no longer in its queue are about to
+/// start and go first.
+fn sort_inactive(queue: &mut Vec<QueueItem>, order: &[Id]) {
+    let inactive_slots = queue
+        .iter()
+        .map(|x| x.status == DownloadStatus::Inactive)
+        .collect::<Vec<_>>();
+    let (mut inactive, others): (Vec<_>, Vec<_>) = std::mem::take(queue)
+        .into_iter()
+        .partition(|x| x.status == DownloadStatus::Inactive);
+    inactive.sort_by_key(|x| {
+        order
+            .iter()
+            .position(|id| *id == x.item.track.id)
+            .map_or(0, |x| x + 1)
+    });
+
+    let (mut inactive, mut others) = (inactive.into_iter(), others.into_iter());
+    *queue = inactive_slots
+        .into_iter()
+        .filter_map(|x| if x { inactive.next() } else { others.next() })
+        .collect();
+}
+
+fn format_log<'a>(log: &'a LogLine, format: &LogFormat) -> Line<'a> {
+    let color = match log.entry {
+        LogEntry::Success(_) => Color::LightGreen,
+        LogEntry::Error(_) => Color::Red,
+        LogEntry::Info(_) => Color::LightBlue,
+    };
+
+    let mut spans = Vec::new();
+    if format.timestamps {
+        spans.push(Span::styled(
+            log.time.format("%H:%M:%S ").to_string(),
+            Style::default().fg(Color::Gray),
+        ));
+    }
+    spans.push(Span::styled(
+        format.prefix.replace("{level}", log.entry.level()),
+        Style::default().fg(color).bold(),
+    ));
+    if let (true, Some(id)) = (format.track_ids, log.track_id) {
+        spans.push(Span::styled(
+            format!("#{} ", id),
+            Style::default().fg(Color::Gray),
+        ));
+    }
+    spans.push(Span::raw(log.entry.message()));
+
+    Line::from(spans)
+}
+
+/// Bring the variants of a key sent by different terminals back to the one matched on.
+fn normalize_key(mut key: crossterm::event::KeyEvent) -> crossterm::event::KeyEvent {
+    use crossterm::event::{KeyCode, KeyEventState, KeyModifiers};
+
+    // Numpad keys are flagged as such by some terminals, they do the same as the others
+    key.state = KeyEventState::NONE;
+    match key.code {
+        // Shift is often held by habit with navigation keys, or added by the terminal
+        KeyCode::PageUp
+        | KeyCode::PageDown
+        | KeyCode::Up
+        | KeyCode::Down
+        | KeyCode::Home
+        | KeyCode::End => key.modifiers -= KeyModifiers::SHIFT,
+        // Some terminals report Ctrl+letter shortcuts uppercase and with Shift
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            key.code = KeyCode::Char(c.to_ascii_lowercase());
+            key.modifiers -= KeyModifiers::SHIFT;
+        }
+        _ => {}
     }
+    key
+}
+
+/// Order logs so that the entries of each group directly follow its first one, flagging
+/// the following ones to be indented.
+fn group_logs(logs: &[LogLine]) -> Vec<(&LogLine, bool)> {
+    let mut groups: HashMap<GroupId, Vec<&LogLine>> = HashMap::new();
+    for log in logs {
+        if let Some(group) = log.group {
+            groups.entry(group).or_default().push(log);
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(logs.len());
+    for log in logs {
+        let Some(group) = log.group else {
+            ordered.push((log, false));
+            continue;
+        };
+
+        // The whole group is added at its first entry
+        if let Some(entries) = groups.remove(&group) {
+            let mut entries = entries.into_iter();
+            ordered.extend(entries.next().map(|x| (x, false)));
+            ordered.extend(entries.map(|x| (x, true)));
+        }
+    }
+    ordered
+}
+
+/// Number of rows a line takes once wrapped to a width. Words moved to the next row may
+/// make it take a few more, which only makes the scrollbar slightly off.
+fn wrapped_height(line: &Line, width: u16) -> usize {
+    line.width().div_ceil(width.max(1) as usize).max(1)
+}
+
+fn get_track_url(track: &Track) -> String {
+    format!("https://www.deezer.com/track/{}", track.id)
 }
 
 fn get_status_color(download_status: &DownloadStatus) -> Color {
     match download_status {
         DownloadStatus::Downloading => Color::LightBlue,
         DownloadStatus::Inactive => Color::Gray,
+        DownloadStatus::Completed => Color::LightGreen,
+        DownloadStatus::Failed => Color::Red,
+    }
+}
+
+/// Border of a pane, thicker when it has the focus.
+fn get_border_type(focused: bool) -> BorderType {
+    if focused {
+        BorderType::Thick
+    } else {
+        BorderType::Rounded
+    }
+}
+
+fn get_input_mode_color(input_mode: &InputMode) -> Color {
+    match input_mode {
+        InputMode::Song => Color::LightCyan,
+        InputMode::Album => Color::LightMagenta,
+        InputMode::Playlist => Color::LightGreen,
+        InputMode::Barcode => Color::LightYellow,
+    }
+}
+
+fn get_input_mode_symbol(input_mode: &InputMode) -> &'static str {
+    match input_mode {
+        InputMode::Song => "♪",
+        InputMode::Album => "◉",
+        InputMode::Playlist => "≡",
+        InputMode::Barcode => "▥",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+
+    use super::*;
+    use crate::downloader::tests::track;
+    use crate::downloader::DownloadError;
+
+    fn queue_item(id: Id, status: DownloadStatus) -> QueueItem {
+        QueueItem {
+            item: DownloadItem::new(track(id, 1, 1)),
+            status,
+            path: None,
+            error: None,
+            progress: None,
+            quality: None,
+            mp3_copy: None,
+        }
+    }
+
+    /// Render the app and return the screen's content, one line per row.
+    fn render(app: &mut App, width: u16, height: u16) -> String {
+        let mut tui = Tui::with_backend(TestBackend::new(width, height)).unwrap();
+        tui.draw(|f| app.ui(f).unwrap()).unwrap();
+
+        tui.backend()
+            .buffer()
+            .content
+            .chunks(width as usize)
+            .map(|line| line.iter().map(|x| x.symbol.as_str()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[tokio::test]
+    async fn pickers_opened_meanwhile_wait_for_the_open_one() {
+        let mut app = App::new(Config::default());
+        app.open_track_picker(TrackPicker::new(1, Vec::new()));
+        app.open_track_picker(TrackPicker::new(2, Vec::new()));
+
+        assert_eq!(Some(1), app.track_picker.as_ref().map(|x| x.album_id));
+        assert_eq!(Some(1), app.close_track_picker().map(|x| x.album_id));
+        assert_eq!(Some(2), app.track_picker.as_ref().map(|x| x.album_id));
+        assert_eq!(Some(2), app.close_track_picker().map(|x| x.album_id));
+        assert!(app.track_picker.is_none());
+    }
+
+    #[test]
+    fn formats_logs_from_config() {
+        let log = LogLine {
+            track_id: Some(3135556),
+            ..LogLine::from(LogEntry::Success(String::from("Downloaded")))
+        };
+        let text = |format: &LogFormat| {
+            format_log(&log, format)
+                .spans
+                .iter()
+                .map(|x| x.content.to_string())
+                .collect::<String>()
+        };
+
+        assert_eq!("[Success] Downloaded", text(&LogFormat::default()));
+        let format = LogFormat {
+            prefix: String::from("{level}: "),
+            timestamps: true,
+            track_ids: true,
+            ..LogFormat::default()
+        };
+        let expected = format!(
+            "{}Success: #3135556 Downloaded",
+            log.time.format("%H:%M:%S ")
+        );
+        assert_eq!(expected, text(&format));
+    }
+
+    #[test]
+    fn normalizes_key_variants() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
+
+        let key = |code, modifiers, state| KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            state,
+        };
+        let normalized = |code, modifiers| key(code, modifiers, KeyEventState::NONE);
+
+        assert_eq!(
+            normalized(KeyCode::PageUp, KeyModifiers::NONE),
+            normalize_key(key(
+                KeyCode::PageUp,
+                KeyModifiers::SHIFT,
+                KeyEventState::KEYPAD
+            ))
+        );
+        assert_eq!(
+            normalized(KeyCode::Char('t'), KeyModifiers::CONTROL),
+            normalize_key(key(
+                KeyCode::Char('T'),
+                KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+                KeyEventState::NONE
+            ))
+        );
+        assert_eq!(
+            normalized(KeyCode::Char('A'), KeyModifiers::SHIFT),
+            normalize_key(key(
+                KeyCode::Char('A'),
+                KeyModifiers::SHIFT,
+                KeyEventState::NONE
+            ))
+        );
+    }
+
+    #[test]
+    fn groups_logs_under_their_first_entry() {
+        let log = |message: &str, group| LogLine {
+            group,
+            ..LogLine::from(LogEntry::Info(String::from(message)))
+        };
+        let logs = vec![
+            log("Album A queued", Some(1)),
+            log("Song downloaded", None),
+            log("Album B queued", Some(2)),
+            log("A1 downloaded", Some(1)),
+            log("B1 downloaded", Some(2)),
+            log("A done", Some(1)),
+        ];
+
+        let grouped = group_logs(&logs)
+            .into_iter()
+            .map(|(log, indented)| (log.entry.message(), indented))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("Album A queued", false),
+                ("A1 downloaded", true),
+                ("A done", true),
+                ("Song downloaded", false),
+                ("Album B queued", false),
+                ("B1 downloaded", true),
+            ],
+            grouped
+        );
+    }
+
+    #[tokio::test]
+    async fn wraps_long_logs_when_toggled() {
+        let mut app = App::new(Config::default());
+        app.log(LogEntry::Error(format!(
+            "Unable to download: {}end",
+            "x".repeat(100)
+        )));
+
+        assert!(!render(&mut app, 60, 20).contains("end"));
+        app.update(Action::ToggleLogWrap).unwrap();
+        assert!(render(&mut app, 60, 20).contains("end"));
+    }
+
+    #[tokio::test]
+    async fn restores_previous_session() {
+        let mut app = App::default();
+        app.log(LogEntry::Info(String::from("Current")));
+
+        let saved = |id, status| SavedQueueItem {
+            item: DownloadItem::new(track(id, 1, 1)),
+            status,
+            path: None,
+            error: None,
+        };
+        app.update(Action::Tick).unwrap();
+        app.restore_prompt = Some(SessionState {
+            queue: vec![
+                saved(1, DownloadStatus::Completed),
+                saved(2, DownloadStatus::Failed),
+            ],
+            logs: vec![SavedLog::from(&LogLine::from(LogEntry::Info(
+                String::from("Previous"),
+            )))],
+        });
+        app.update(Action::RestoreSession).unwrap();
+
+        assert!(app.restore_prompt.is_none());
+        let ids = app
+            .queue
+            .iter()
+            .map(|x| x.item.track.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2], ids);
+        let logs = app
+            .logs
+            .iter()
+            .map(|x| x.entry.message())
+            .collect::<Vec<_>>();
+        assert_eq!("Previous", logs[0]);
+        assert_eq!("Current", logs[1]);
+    }
+
+    #[tokio::test]
+    async fn keeps_selected_queue_item_in_view() {
+        let mut app = App::new(Config::default());
+        app.queue = (1..=40)
+            .map(|id| queue_item(id, DownloadStatus::Completed))
+            .collect();
+
+        assert!(!render(&mut app, 160, 30).contains("Title 40"));
+        app.select_queue_item(39);
+        let screen = render(&mut app, 160, 30);
+        assert!(screen.contains("Title 40"));
+        assert!(screen.contains('█'));
+    }
+
+    #[test]
+    fn sorts_waiting_items_in_downloader_order() {
+        let item = |id, album, position, status| {
+            let mut track = track(id, position, 1);
+            track.album.title = String::from(album);
+            QueueItem {
+                item: DownloadItem::new(track),
+                ..queue_item(id, status)
+            }
+        };
+        let mut queue = vec![
+            item(1, "B", 2, DownloadStatus::Completed),
+            item(2, "B", 2, DownloadStatus::Inactive),
+            item(3, "A", 2, DownloadStatus::Inactive),
+            item(4, "C", 1, DownloadStatus::Downloading),
+            item(5, "B", 1, DownloadStatus::Inactive),
+            item(6, "A", 1, DownloadStatus::Inactive),
+        ];
+
+        sort_inactive(&mut queue, &[6, 3, 2]);
+
+        // Track 5 was taken by a worker
+        let ids = queue.iter().map(|x| x.item.track.id).collect::<Vec<_>>();
+        assert_eq!(vec![1, 5, 6, 4, 3, 2], ids);
+    }
+
+    #[tokio::test]
+    async fn lists_finished_downloads_most_recent_first() {
+        let mut app = App::new(Config::default());
+        app.recent_downloads.clear();
+        for id in 0..=RECENT_DOWNLOADS {
+            app.add_recent_download(RecentDownload {
+                artist: String::from("Artist"),
+                title: format!("Title {}", id),
+                path: PathBuf::from(format!("/music/{}.mp3", id)),
+            });
+        }
+        app.update(Action::ToggleRecentDownloads).unwrap();
+
+        let screen = render(&mut app, 120, 20);
+        assert!(screen.contains(&format!("Recent downloads ({})", RECENT_DOWNLOADS)));
+        let last = format!("Title {}", RECENT_DOWNLOADS);
+        let previous = format!("Title {}", RECENT_DOWNLOADS - 1);
+        assert!(screen.find(&last).unwrap() < screen.find(&previous).unwrap());
+        assert!(!screen.contains("Title 0"));
+    }
+
+    #[tokio::test]
+    async fn shows_details_of_selected_queue_item() {
+        let mut app = App::new(Config::default());
+        app.queue.push(QueueItem {
+            path: Some(PathBuf::from("/music/Artist - Title 1.mp3")),
+            ..queue_item(1, DownloadStatus::Completed)
+        });
+        app.update(Action::ShowQueueItemDetails).unwrap();
+        assert!(!app.show_queue_item_details);
+
+        app.select_queue_item(0);
+        app.update(Action::ShowQueueItemDetails).unwrap();
+        let screen = render(&mut app, 120, 20);
+
+        assert!(screen.contains("Track details"));
+        assert!(screen.contains("Release date: 2001-03-07"));
+        assert!(screen.contains("Duration: 3:00"));
+        assert!(screen.contains("Path: /music/Artist - Title 1.mp3"));
+    }
+
+    #[test]
+    fn browses_input_history() {
+        let mut history = InputHistory::default();
+        assert_eq!(None, history.previous("typed"));
+        history.push(String::from("1"));
+        history.push(String::from("2"));
+        history.push(String::from("2"));
+
+        assert_eq!(Some("2"), history.previous("typed"));
+        assert_eq!(Some("1"), history.previous("2"));
+        assert_eq!(None, history.previous("1"));
+        assert_eq!(Some("2"), history.next());
+        assert_eq!(Some("typed"), history.next());
+        assert_eq!(None, history.next());
+    }
+
+    #[tokio::test]
+    async fn brings_back_previous_inputs() {
+        use crossterm::event::{KeyCode, KeyEvent};
+
+        let mut app = App::new(Config::default());
+        app.input_history.push(String::from("3135556"));
+        app.input = Input::new(String::from("12"));
+
+        let action = app
+            .handle_event(Event::Key(KeyEvent::from(KeyCode::Up)))
+            .unwrap();
+        app.update(action).unwrap();
+        assert_eq!("3135556", app.input.value());
+        let action = app
+            .handle_event(Event::Key(KeyEvent::from(KeyCode::Down)))
+            .unwrap();
+        app.update(action).unwrap();
+        assert_eq!("12", app.input.value());
+    }
+
+    #[tokio::test]
+    async fn pauses_selected_worker() {
+        let mut app = App::new(Config {
+            concurrency: 2,
+            ..Config::default()
+        });
+        app.update(Action::ToggleWorkers).unwrap();
+        app.update(Action::SelectNextWorker).unwrap();
+        app.update(Action::SelectNextWorker).unwrap();
+        app.update(Action::ToggleWorkerPaused).unwrap();
+
+        assert!(!app.downloader.is_worker_paused(0));
+        assert!(app.downloader.is_worker_paused(1));
+        assert!(render(&mut app, 120, 20).contains("Paused"));
+
+        app.update(Action::ToggleWorkerPaused).unwrap();
+        assert!(!app.downloader.is_worker_paused(1));
+    }
+
+    #[tokio::test]
+    async fn only_redraws_after_changes() {
+        let mut app = App::new(Config::default());
+        app.dirty = false;
+        let action = app.handle_event(Event::Tick).unwrap();
+        app.update(action).unwrap();
+        assert!(!app.dirty);
+
+        let action = app.handle_event(Event::Resize).unwrap();
+        app.update(action).unwrap();
+        assert!(app.dirty);
+
+        app.dirty = false;
+        let key = crossterm::event::KeyEvent::from(crossterm::event::KeyCode::Char('1'));
+        let action = app.handle_event(Event::Key(key)).unwrap();
+        app.update(action).unwrap();
+        assert!(app.dirty);
+    }
+
+    #[tokio::test]
+    async fn shows_failure_reason_of_selected_item() {
+        use crate::downloader::DownloadError;
+
+        let mut app = App::new(Config::default());
+        app.queue.push(queue_item(1, DownloadStatus::Downloading));
+        let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+        app.downloader.progress_rx = progress_rx;
+        progress_tx
+            .send(DownloadProgress::DownloadError(
+                track(1, 1, 1),
+                DownloadError::Timeout,
+            ))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+
+        assert_eq!(DownloadStatus::Failed, app.queue[0].status);
+        assert!(!render(&mut app, 160, 20).contains("(timed out)"));
+        app.select_queue_item(0);
+        assert!(render(&mut app, 160, 20).contains("(timed out)"));
+        app.update(Action::ShowQueueItemDetails).unwrap();
+        assert!(render(&mut app, 160, 20).contains("Failure: timed out"));
+    }
+
+    #[tokio::test]
+    async fn removes_the_skipped_item_of_the_track() {
+        let mut app = App::new(Config::default());
+        for status in [
+            DownloadStatus::Inactive,
+            DownloadStatus::Downloading,
+            DownloadStatus::Inactive,
+        ] {
+            app.queue.push(queue_item(1, status));
+        }
+        app.queue[0].item.group = Some(1);
+        let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+        app.downloader.progress_rx = progress_rx;
+
+        progress_tx
+            .send(DownloadProgress::Skipped(
+                track(1, 1, 1),
+                SkipReason::SameTrack,
+            ))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+        let statuses = app.queue.iter().map(|x| x.status).collect::<Vec<_>>();
+        assert_eq!(
+            vec![DownloadStatus::Inactive, DownloadStatus::Inactive],
+            statuses
+        );
+
+        progress_tx
+            .send(DownloadProgress::Skipped(
+                track(1, 1, 1),
+                SkipReason::Explicit,
+            ))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+        assert_eq!(1, app.queue.len());
+        assert_eq!(Some(1), app.queue[0].item.group);
+    }
+
+    #[tokio::test]
+    async fn shows_progress_of_downloading_items() {
+        let mut app = App::new(Config::default());
+        app.queue.push(queue_item(1, DownloadStatus::Downloading));
+        let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+        app.downloader.progress_rx = progress_rx;
+        progress_tx
+            .send(DownloadProgress::Progress(track(1, 1, 1), 42))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+
+        assert_eq!(Some(42), app.queue[0].progress);
+        assert!(render(&mut app, 160, 20).contains("[Downloading] 42% Artist"));
+        app.queue[0].status = DownloadStatus::Completed;
+        assert!(!render(&mut app, 160, 20).contains("42%"));
+    }
+
+    #[tokio::test]
+    async fn shows_quality_of_retried_downloads() {
+        let mut app = App::new(Config::default());
+        app.queue.push(queue_item(1, DownloadStatus::Downloading));
+        let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+        app.downloader.progress_rx = progress_rx;
+        progress_tx
+            .send(DownloadProgress::Progress(track(1, 1, 1), 42))
+            .unwrap();
+        progress_tx
+            .send(DownloadProgress::QualityRetry(
+                track(1, 1, 1),
+                Quality::Flac,
+                Quality::Mp3_320,
+                DownloadError::Unavailable,
+            ))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+
+        assert_eq!(Some(Quality::Mp3_320), app.queue[0].quality);
+        assert_eq!(None, app.queue[0].progress);
+        assert!(render(&mut app, 160, 20).contains("Title 1 [MP3 320kbps]"));
+    }
+
+    #[tokio::test]
+    async fn shows_progress_of_mp3_copies() {
+        let mut app = App::new(Config::default());
+        app.queue.push(queue_item(1, DownloadStatus::Downloading));
+        let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+        app.downloader.progress_rx = progress_rx;
+        let path = PathBuf::from("/music/Artist - Title 1.flac");
+        progress_tx
+            .send(DownloadProgress::Finish(track(1, 1, 1), path.clone()))
+            .unwrap();
+        progress_tx
+            .send(DownloadProgress::Mp3CopyProgress(track(1, 1, 1), 42))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+
+        assert_eq!(Some(path), app.queue[0].path);
+        assert!(render(&mut app, 160, 20).contains("Title 1 [MP3 42%]"));
+
+        progress_tx
+            .send(DownloadProgress::Mp3CopyFinish(
+                track(1, 1, 1),
+                PathBuf::from("/music/Artist - Title 1.mp3"),
+            ))
+            .unwrap();
+        app.update(Action::Tick).unwrap();
+        assert_eq!(None, app.queue[0].mp3_copy);
+        assert!(!render(&mut app, 160, 20).contains("[MP3 42%]"));
+    }
+
+    #[tokio::test]
+    async fn exits_once_queue_is_done() {
+        let mut app = App::new(Config {
+            exit_when_done: true,
+            ..Config::default()
+        });
+        app.update(Action::Tick).unwrap();
+        assert!(!app.should_quit);
+
+        app.requested = true;
+        app.queue.push(queue_item(1, DownloadStatus::Downloading));
+        app.update(Action::Tick).unwrap();
+        assert!(!app.should_quit);
+
+        app.queue[0].status = DownloadStatus::Completed;
+        app.input = Input::new(String::from("3135556"));
+        app.update(Action::Tick).unwrap();
+        assert!(!app.should_quit);
+
+        app.input.reset();
+        app.reading_requests = true;
+        app.update(Action::Tick).unwrap();
+        assert!(!app.should_quit);
+
+        app.reading_requests = false;
+        app.update(Action::Tick).unwrap();
+        assert!(app.should_quit);
+    }
+
+    #[tokio::test]
+    async fn exits_once_everything_is_cancelled() {
+        let mut app = App::new(Config {
+            exit_when_done: true,
+            ..Config::default()
+        });
+        let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+        app.downloader.progress_rx = progress_rx;
+        let item = DownloadItem::new(track(1, 1, 1));
+        progress_tx.send(DownloadProgress::Queue(item)).unwrap();
+        app.update(Action::Tick).unwrap();
+        assert!(!app.should_quit);
+
+        app.batch_prompts.push_back(LargeBatch {
+            album: Some(2),
+            name: String::from("Album \"Album\""),
+            items: Vec::new(),
+            quality: Default::default(),
+            size: 0,
+        });
+        app.update(Action::CancelAll).unwrap();
+        assert!(app.queue.is_empty());
+        assert!(app.batch_prompts.is_empty());
+        app.update(Action::Tick).unwrap();
+        assert!(app.should_quit);
+    }
+
+    #[tokio::test]
+    async fn answers_collisions_for_all() {
+        let mut app = App::default();
+        let mut choices = Vec::new();
+        for id in 1..=3 {
+            let (prompt, choice_rx) = CollisionPrompt::new();
+            app.add_collision(
+                track(id, 1, 1),
+                PathBuf::from(format!("/music/{}.mp3", id)),
+                PathBuf::from(format!("/music/{}.MP3", id)),
+                (FileInfo { size: 0, kbps: 0 }, FileInfo { size: 0, kbps: 0 }),
+                prompt,
+            );
+            choices.push(choice_rx);
+        }
+        assert_eq!("1.mp3", app.collisions[0].name.value());
+        app.collisions[0].infos.0 = FileInfo {
+            size: 9_600_000,
+            kbps: 320,
+        };
+        assert!(render(&mut app, 160, 20).contains("taken by /music/1.MP3 (9.2 MB, ~320 kbps)"));
+
+        app.collisions[0].name = Input::new(String::from("1 (2).mp3"));
+        app.update(Action::CollisionRename).unwrap();
+        app.update(Action::CollisionSkipAll).unwrap();
+        assert!(app.collisions.is_empty());
+
+        let (prompt, choice_rx) = CollisionPrompt::new();
+        app.add_collision(
+            track(4, 1, 1),
+            PathBuf::from("/music/4.mp3"),
+            PathBuf::from("/music/4.mp3"),
+            (FileInfo { size: 0, kbps: 0 }, FileInfo { size: 0, kbps: 0 }),
+            prompt,
+        );
+        assert!(app.collisions.is_empty());
+        choices.push(choice_rx);
+
+        let choices = choices
+            .into_iter()
+            .map(|mut x| x.try_recv().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                CollisionChoice::Rename(String::from("1 (2).mp3")),
+                CollisionChoice::Skip,
+                CollisionChoice::Skip,
+                CollisionChoice::Skip,
+            ],
+            choices
+        );
+    }
+
+    #[tokio::test]
+    async fn hides_inactive_queue_items() {
+        let mut app = App::default();
+        for (id, status) in [
+            (1, DownloadStatus::Completed),
+            (2, DownloadStatus::Inactive),
+            (3, DownloadStatus::Downloading),
+            (4, DownloadStatus::Inactive),
+        ] {
+            app.queue.push(queue_item(id, status));
+        }
+        app.queue_state.select(Some(1));
+
+        app.update(Action::ToggleInactiveItems).unwrap();
+        assert_eq!(Some(0), app.queue_state.selected());
+        app.update(Action::SelectNextQueueItem).unwrap();
+        assert_eq!(Some(2), app.queue_state.selected());
+        app.update(Action::SelectNextQueueItem).unwrap();
+        assert_eq!(Some(2), app.queue_state.selected());
+
+        let screen = render(&mut app, 120, 20);
+        assert!(!screen.contains("Title 2"));
+        assert!(screen.contains("Title 3"));
+        assert!(screen.contains("(+2 queued)"));
+
+        app.update(Action::ToggleInactiveItems).unwrap();
+        app.update(Action::SelectNextQueueItem).unwrap();
+        assert_eq!(Some(3), app.queue_state.selected());
+    }
+
+    #[tokio::test]
+    async fn jumps_and_wraps_in_the_queue() {
+        let mut app = App::new(Config {
+            wrap_queue_selection: true,
+            ..Config::default()
+        });
+        app.queue = (1..=10)
+            .map(|id| queue_item(id, DownloadStatus::Completed))
+            .collect();
+        app.queue_page_size = 4;
+
+        app.update(Action::SelectLastQueueItem).unwrap();
+        assert_eq!(Some(9), app.queue_state.selected());
+        app.update(Action::SelectNextQueueItem).unwrap();
+        assert_eq!(Some(0), app.queue_state.selected());
+        app.update(Action::SelectPreviousQueueItem).unwrap();
+        assert_eq!(Some(9), app.queue_state.selected());
+        app.update(Action::QueuePageUp).unwrap();
+        assert_eq!(Some(5), app.queue_state.selected());
+        app.update(Action::QueuePageDown).unwrap();
+        app.update(Action::QueuePageDown).unwrap();
+        assert_eq!(Some(9), app.queue_state.selected());
+        app.update(Action::SelectFirstQueueItem).unwrap();
+        assert_eq!(Some(0), app.queue_state.selected());
+    }
+
+    #[tokio::test]
+    async fn shows_what_each_worker_downloads() {
+        let mut app = App::new(Config {
+            concurrency: 2,
+            ..Config::default()
+        });
+        app.update(Action::ToggleWorkers).unwrap();
+        app.worker_tasks[1] = Some((track(7, 1, 1), Instant::now()));
+
+        let screen = render(&mut app, 120, 20);
+
+        assert!(screen.contains("#1"));
+        assert!(screen.contains("Idle"));
+        assert!(screen.contains("#2"));
+        assert!(screen.contains("Artist - Title 7"));
+    }
+
+    #[tokio::test]
+    async fn renders_main_layout() {
+        let mut app = App::new(Config::default());
+        app.input = Input::new(String::from("3135556"));
+        app.log(LogEntry::Error(String::from(
+            "Song with id 1 was not found",
+        )));
+
+        let screen = render(&mut app, 120, 20);
+
+        assert!(screen.contains("Logs"));
+        assert!(screen.contains("Download queue"));
+        assert!(screen.contains("♪ Song"));
+        assert!(screen.contains("3135556"));
+        assert!(screen.contains("[Error] Song with id 1 was not found"));
+        assert!(screen.contains("Start Download"));
     }
 }
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use deezer::models::Track;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::downloader::{AlbumInfo, Id};
+use crate::history::HistoryWriter;
+
+/// A lookup recorded to the cache file, one per line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CachedLookup {
+    Track(Box<Track>),
+    Album(Id, AlbumInfo),
+}
+
+/// Tracks and albums already looked up, kept across sessions so that requests can be
+/// resolved again without the API, e.g. to rebuild a queue on a flaky connection.
+#[derive(Debug)]
+pub struct ResolutionCache {
+    tracks: Mutex<HashMap<Id, Track>>,
+    albums: Mutex<HashMap<Id, AlbumInfo>>,
+    /// Whether the cached lookups are ignored, the fresh ones then replace them.
+    refresh: bool,
+    path: PathBuf,
+    writer: HistoryWriter,
+}
+
+/// File lookups are recorded to, `None` if no data directory was found.
+fn cache_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "deeznuts-downloader")
+        .map(|dirs| dirs.data_dir().join("lookups.jsonl"))
+}
+
+impl ResolutionCache {
+    /// Load the lookups of the previous sessions, `None` if no data directory was found.
+    pub fn open(refresh: bool, writer: HistoryWriter) -> Option<Self> {
+        Some(Self::open_at(cache_path()?, refresh, writer))
+    }
+
+    fn open_at(path: PathBuf, refresh: bool, writer: HistoryWriter) -> Self {
+        let mut tracks = HashMap::new();
+        let mut albums = HashMap::new();
+        let content = std::fs::read_to_string(&path).unwrap_or_default();
+        // Later lookups replace earlier ones, lines cut by a crash are left out
+        for lookup in content.lines().filter_map(|x| serde_json::from_str(x).ok()) {
+            match lookup {
+                CachedLookup::Track(track) => {
+                    tracks.insert(track.id, *track);
+                }
+                CachedLookup::Album(id, album) => {
+                    albums.insert(id, album);
+                }
+            }
+        }
+
+        ResolutionCache {
+            tracks: Mutex::new(tracks),
+            albums: Mutex::new(albums),
+            refresh,
+            path,
+            writer,
+        }
+    }
+
+    pub fn track(&self, id: Id) -> Option<Track> {
+        if self.refresh {
+            return None;
+        }
+        self.tracks.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn album(&self, id: Id) -> Option<AlbumInfo> {
+        if self.refresh {
+            return None;
+        }
+        self.albums.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn insert_track(&self, track: &Track) {
+        self.tracks.lock().unwrap().insert(track.id, track.clone());
+        self.record(&CachedLookup::Track(Box::new(track.clone())));
+    }
+
+    pub fn insert_album(&self, id: Id, album: &AlbumInfo) {
+        self.albums.lock().unwrap().insert(id, album.clone());
+        self.record(&CachedLookup::Album(id, album.clone()));
+    }
+
+    fn record(&self, lookup: &CachedLookup) {
+        let content = serde_json::to_string(lookup).expect("Lookup should be serializable.");
+        self.writer.append(&self.path, content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+    use crate::downloader::tests::track;
+
+    #[test]
+    fn lookups_are_kept_across_sessions() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-lookups");
+        let _ = std::fs::remove_dir_all(&dir);
+        let (progress_tx, _progress_rx) = unbounded();
+        let writer = HistoryWriter::spawn(progress_tx);
+
+        let path = dir.join("lookups.jsonl");
+        let cache = ResolutionCache::open_at(path.clone(), false, writer.clone());
+        cache.insert_track(&track(1, 1, 1));
+        let album = AlbumInfo {
+            title: String::from("Album"),
+            artist: String::from("Artist"),
+            genre: None,
+            track_ids: vec![1],
+        };
+        cache.insert_album(2, &album);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(&path).map_or(0, |x| x.lines().count()) < 2
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let cache = ResolutionCache::open_at(path.clone(), false, writer.clone());
+        assert_eq!(Some(1), cache.track(1).map(|x| x.id));
+        assert_eq!(Some(vec![1]), cache.album(2).map(|x| x.track_ids));
+        assert!(cache.track(3).is_none());
+
+        let cache = ResolutionCache::open_at(path, true, writer);
+        assert!(cache.track(1).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
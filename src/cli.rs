@@ -0,0 +1,416 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use color_eyre::eyre::{eyre, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::{Config, LookupCache, LyricsOutput};
+use crate::downloader::{
+    is_barcode, is_short_link, parse_album_request, parse_deezer_input, DownloadProgress,
+    DownloadRequest, Downloader, RequestKind,
+};
+use crate::Event;
+
+static USAGE: &str = "Usage: deeznuts-downloader [--output-format tui|json] [--song ID]... \
+    [--album ID]... [--playlist ID]... [--requests-from PATH|-] [--verify DIR] [--repair DIR] [--import PATH] [--lookup-cache off|on|refresh]";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Interactive terminal interface.
+    #[default]
+    Tui,
+    /// Download the requested songs and albums, printing the progress as JSON lines.
+    Json,
+}
+
+/// Where requests are read from while the TUI runs, one per line.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RequestsSource {
+    Stdin,
+    /// A file or named pipe.
+    Path(PathBuf),
+}
+
+impl std::fmt::Display for RequestsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestsSource::Stdin => write!(f, "stdin"),
+            RequestsSource::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub output_format: OutputFormat,
+    pub requests: Vec<DownloadRequest>,
+    /// Highest number of workers to benchmark the requests with, left out of the usage
+    /// since it is only meant to tune the concurrency.
+    pub benchmark: Option<u64>,
+    pub requests_from: Option<RequestsSource>,
+    /// Library to check against Deezer instead of downloading.
+    pub verify: Option<PathBuf>,
+    /// Download again the tracks the library check finds missing, corrupt or upgradable.
+    pub repair: bool,
+    /// Playlist of "Artist - Title" lines to match to Deezer tracks and download.
+    pub import: Option<PathBuf>,
+    /// Overrides the configured lookup cache, e.g. to refresh it once.
+    pub lookup_cache: Option<LookupCache>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or(eyre!("Missing value for {}\n{}", arg, USAGE))?;
+            match arg.as_str() {
+                "--output-format" => {
+                    parsed.output_format = match value.as_str() {
+                        "tui" => OutputFormat::Tui,
+                        "json" => OutputFormat::Json,
+                        _ => return Err(eyre!("Unknown output format {}\n{}", value, USAGE)),
+                    }
+                }
+                "--song" => parsed
+                    .requests
+                    .push(DownloadRequest::Song(parse_id(&value)?)),
+                "--album" => parsed
+                    .requests
+                    .push(DownloadRequest::Album(parse_id(&value)?)),
+                "--playlist" => parsed
+                    .requests
+                    .push(DownloadRequest::Playlist(parse_id(&value)?)),
+                "--benchmark" => parsed.benchmark = Some(parse_id(&value)?),
+                "--requests-from" => {
+                    parsed.requests_from = Some(match value.as_str() {
+                        "-" => RequestsSource::Stdin,
+                        _ => RequestsSource::Path(PathBuf::from(value)),
+                    })
+                }
+                "--lookup-cache" => {
+                    parsed.lookup_cache = Some(match value.as_str() {
+                        "off" => LookupCache::Off,
+                        "on" => LookupCache::On,
+                        "refresh" => LookupCache::Refresh,
+                        _ => return Err(eyre!("Unknown lookup cache {}\n{}", value, USAGE)),
+                    })
+                }
+                "--import" => parsed.import = Some(PathBuf::from(value)),
+                "--verify" => parsed.verify = Some(PathBuf::from(value)),
+                "--repair" => {
+                    parsed.verify = Some(PathBuf::from(value));
+                    parsed.repair = true;
+                }
+                _ => return Err(eyre!("Unknown argument {}\n{}", arg, USAGE)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn parse_id(value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|_| eyre!("Invalid id {}\n{}", value, USAGE))
+}
+
+/// Parse a request read from a line, written as `song ID`, `album ID`, `album ID:3-7`,
+/// `playlist ID`, `barcode UPC`, a Deezer link or just a song id.
+pub fn parse_request_line(line: &str) -> Option<DownloadRequest> {
+    let mut words = line.split_whitespace();
+    match (words.next()?, words.next(), words.next()) {
+        ("song", Some(id), None) => id.parse().ok().map(DownloadRequest::Song),
+        ("album", Some(id), None) => parse_album_request(id),
+        ("playlist", Some(id), None) => id.parse().ok().map(DownloadRequest::Playlist),
+        ("barcode", Some(upc), None) if is_barcode(upc) => {
+            Some(DownloadRequest::AlbumBarcode(upc.to_string()))
+        }
+        (input, None, None) => match parse_deezer_input(input) {
+            Some((kind, id)) => Some(kind.request(id)),
+            None if is_short_link(input) => Some(DownloadRequest::ShortLink(input.to_string())),
+            None => input.parse().ok().map(DownloadRequest::Song),
+        },
+        _ => None,
+    }
+}
+
+/// Read requests line by line until the end of the source, sending them to the TUI.
+pub async fn read_requests(source: RequestsSource, event_tx: UnboundedSender<Event>) {
+    let result = match &source {
+        RequestsSource::Stdin => send_requests(BufReader::new(tokio::io::stdin()), &event_tx).await,
+        RequestsSource::Path(path) => match tokio::fs::File::open(path).await {
+            Ok(file) => send_requests(BufReader::new(file), &event_tx).await,
+            Err(err) => Err(err),
+        },
+    };
+
+    let error = result.err().map(|err| format!("{}: {}", source, err));
+    // The TUI may already be closed, nothing is left to read the requests then
+    let _ = event_tx.send(Event::RequestsEnded(error));
+}
+
+async fn send_requests(
+    reader: impl AsyncBufRead + Unpin,
+    event_tx: &UnboundedSender<Event>,
+) -> std::io::Result<()> {
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = parse_request_line(&line).ok_or(line);
+        if event_tx.send(Event::ExternalRequest(request)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Download the requests without the TUI, printing each progress event as a JSON line on
+/// stdout so that another program can follow them. Returns once every request is handled.
+pub async fn run_json(config: Config, requests: Vec<DownloadRequest>) -> Result<()> {
+    // Nobody is there to answer a prompt
+    let downloader = Downloader::new(Config {
+        ask_on_collision: false,
+        confirm_batch_above: None,
+        ..config
+    });
+    let mut stdout = std::io::stdout().lock();
+    handle_requests(&downloader, requests, |progress| {
+        serde_json::to_writer(&mut stdout, progress)?;
+        writeln!(stdout)?;
+        Ok(())
+    })
+}
+
+/// Download the requests with 1 to `max_workers` workers, printing how long each run took
+/// and how much of it was spent looking up the tracks, to help pick the concurrency and
+/// the lookup concurrency. The songs are written to a temporary directory, removed after
+/// each run.
+pub async fn run_benchmark(
+    config: Config,
+    requests: Vec<DownloadRequest>,
+    max_workers: u64,
+) -> Result<()> {
+    if requests.is_empty() {
+        return Err(eyre!(
+            "Nothing to benchmark, request songs or albums\n{}",
+            USAGE
+        ));
+    }
+
+    for workers in 1..=max_workers {
+        let dir = std::env::temp_dir().join(format!("deeznuts-downloader-benchmark-{}", workers));
+        std::fs::create_dir_all(&dir)?;
+
+        let downloader = Downloader::with_download_dir(
+            Config {
+                concurrency: workers,
+                upgrade_existing: false,
+                ask_on_collision: false,
+                post_download_command: None,
+                mp3_copy: false,
+                confirm_batch_above: None,
+                // Only the songs are written, to a directory removed afterwards
+                sidecar_cover: None,
+                album_metadata: None,
+                cache_covers: false,
+                lyrics: LyricsOutput::Off,
+                lookup_cache: LookupCache::Off,
+                record_history: false,
+                sync_playlists: false,
+                ..config.clone()
+            },
+            dir.clone(),
+        );
+
+        let start = Instant::now();
+        let (mut songs, mut failures, mut size) = (0, 0, 0);
+        // When the last track was queued, every request being looked up by then
+        let mut resolved = start.elapsed();
+        let result = handle_requests(&downloader, requests.clone(), |progress| {
+            match progress {
+                DownloadProgress::Queue(_) => resolved = start.elapsed(),
+                DownloadProgress::Finish(_, path) => {
+                    songs += 1;
+                    size += std::fs::metadata(path).map_or(0, |x| x.len());
+                }
+                DownloadProgress::DownloadError(_, _) => failures += 1,
+                _ => {}
+            }
+            Ok(())
+        });
+        let elapsed = start.elapsed().as_secs_f64();
+        std::fs::remove_dir_all(&dir)?;
+        result?;
+
+        let megabytes = size as f64 / 1024.0 / 1024.0;
+        println!(
+            "{} workers: {} songs ({:.1} MB) in {:.1}s, {:.2} MB/s, {} failed, looked up in {:.1}s with {} lookups at a time",
+            workers,
+            songs,
+            megabytes,
+            elapsed,
+            megabytes / elapsed,
+            failures,
+            resolved.as_secs_f64(),
+            config.lookup_concurrency
+        );
+    }
+
+    Ok(())
+}
+
+/// Request downloads and hand each progress event to `on_progress` until every request
+/// is handled.
+pub(crate) fn handle_requests(
+    downloader: &Downloader,
+    requests: Vec<DownloadRequest>,
+    mut on_progress: impl FnMut(&DownloadProgress) -> Result<()>,
+) -> Result<()> {
+    let mut pending_requests = requests.len();
+    let mut pending_tracks = 0;
+    for request in requests {
+        downloader.request_download(request);
+    }
+
+    while pending_requests > 0 || pending_tracks > 0 {
+        let progress = tokio::task::block_in_place(|| downloader.progress_rx.recv())?;
+        on_progress(&progress)?;
+
+        match progress {
+            DownloadProgress::Queue(item) => {
+                pending_tracks += 1;
+                if item.kind == RequestKind::Song {
+                    pending_requests -= 1;
+                }
+            }
+            DownloadProgress::Finish(_, _)
+            | DownloadProgress::DownloadError(_, _)
+            | DownloadProgress::Cancelled(_)
+            | DownloadProgress::Skipped(_, _) => pending_tracks -= 1,
+            DownloadProgress::SongNotFoundError(_)
+            | DownloadProgress::AlbumNotFoundError(_)
+            | DownloadProgress::PlaylistNotFoundError(_)
+            | DownloadProgress::AlbumAlreadyRequested(_)
+            | DownloadProgress::TrackRangeOutOfBounds(_, _, _)
+            | DownloadProgress::BarcodeNotFoundError(_)
+            | DownloadProgress::ShortLinkError(_)
+            | DownloadProgress::GroupFinished(_) => pending_requests -= 1,
+            DownloadProgress::NoWorkers => return Err(eyre!("No download worker could start")),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn parses_json_requests() {
+        let parsed = args(&["--output-format", "json", "--song", "1", "--album", "2"]).unwrap();
+
+        assert_eq!(OutputFormat::Json, parsed.output_format);
+        assert!(matches!(
+            parsed.requests.as_slice(),
+            [DownloadRequest::Song(1), DownloadRequest::Album(2)]
+        ));
+    }
+
+    #[test]
+    fn parses_benchmark() {
+        let parsed = args(&["--benchmark", "8", "--song", "1"]).unwrap();
+
+        assert_eq!(Some(8), parsed.benchmark);
+        assert_eq!(OutputFormat::Tui, parsed.output_format);
+    }
+
+    #[test]
+    fn parses_lookup_cache() {
+        let parsed = args(&["--lookup-cache", "refresh"]).unwrap();
+        assert_eq!(Some(LookupCache::Refresh), parsed.lookup_cache);
+        assert!(args(&["--lookup-cache", "always"]).is_err());
+    }
+
+    #[test]
+    fn parses_library_checks() {
+        let parsed = args(&["--verify", "/music"]).unwrap();
+        assert_eq!(Some(PathBuf::from("/music")), parsed.verify);
+        assert!(!parsed.repair);
+
+        let parsed = args(&["--repair", "/music"]).unwrap();
+        assert_eq!(Some(PathBuf::from("/music")), parsed.verify);
+        assert!(parsed.repair);
+    }
+
+    #[test]
+    fn parses_request_lines() {
+        assert!(matches!(
+            parse_request_line("3135556"),
+            Some(DownloadRequest::Song(3135556))
+        ));
+        assert!(matches!(
+            parse_request_line(" album 302127 "),
+            Some(DownloadRequest::Album(302127))
+        ));
+        assert!(matches!(
+            parse_request_line("barcode 724384960650"),
+            Some(DownloadRequest::AlbumBarcode(upc)) if upc == "724384960650"
+        ));
+        assert!(matches!(
+            parse_request_line("https://www.deezer.com/album/302127"),
+            Some(DownloadRequest::Album(302127))
+        ));
+        assert!(matches!(
+            parse_request_line("https://deezer.page.link/4kVQjwJhCcYkuPVe9"),
+            Some(DownloadRequest::ShortLink(link)) if link == "https://deezer.page.link/4kVQjwJhCcYkuPVe9"
+        ));
+        assert!(matches!(
+            parse_request_line("playlist 1"),
+            Some(DownloadRequest::Playlist(1))
+        ));
+        assert!(parse_request_line("song 1 2").is_none());
+        assert!(parse_request_line("barcode abc").is_none());
+    }
+
+    #[tokio::test]
+    async fn sends_requests_until_eof() {
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let input: &[u8] = b"1\n\nfoo\n";
+
+        send_requests(input, &event_tx).await.unwrap();
+
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(Event::ExternalRequest(Ok(DownloadRequest::Song(1))))
+        ));
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(Event::ExternalRequest(Err(line))) if line == "foo"
+        ));
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_args() {
+        assert!(args(&["--song"]).is_err());
+        assert!(args(&["--song", "abc"]).is_err());
+        assert!(args(&["--output-format", "xml"]).is_err());
+        assert!(args(&["--verbose", "1"]).is_err());
+    }
+}
@@ -0,0 +1,981 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveTime;
+use color_eyre::eyre::{eyre, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use directories::ProjectDirs;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::downloader::{set_user_agent, RequestKind, DOWNLOAD_THREADS, LOOKUP_CONCURRENCY};
+
+/// Size of the album cover art, as provided by Deezer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSize {
+    Small,
+    Medium,
+    #[default]
+    Big,
+}
+
+impl CoverSize {
+    /// Sizes to try in order when the cover for this one is missing, closest first.
+    pub fn fallback_order(self) -> [CoverSize; 3] {
+        match self {
+            CoverSize::Small => [CoverSize::Small, CoverSize::Medium, CoverSize::Big],
+            CoverSize::Medium => [CoverSize::Medium, CoverSize::Big, CoverSize::Small],
+            CoverSize::Big => [CoverSize::Big, CoverSize::Medium, CoverSize::Small],
+        }
+    }
+}
+
+/// Album cover saved as an image file next to the songs of the album.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SidecarCover {
+    pub size: CoverSize,
+    /// Size in pixels, overriding [SidecarCover::size] when Deezer has the cover at this
+    /// size.
+    pub resolution: Option<u32>,
+    /// Name of the image, `cover.jpg` by default, where `{artist}` and `{album}` are
+    /// replaced with the album's.
+    pub file_name: String,
+}
+
+impl Default for SidecarCover {
+    fn default() -> Self {
+        Self {
+            size: CoverSize::Big,
+            resolution: None,
+            file_name: String::from("cover.jpg"),
+        }
+    }
+}
+
+/// Format of the album metadata file written next to the songs of an album.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumMetadataFormat {
+    /// An `album.json` file.
+    Json,
+    /// An `album.nfo` file, as read by Kodi and Jellyfin.
+    Nfo,
+}
+
+/// Audio format songs are downloaded in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    #[default]
+    Mp3_128,
+    Mp3_320,
+    Flac,
+}
+
+impl Quality {
+    /// Bitrate of the format, that of uncompressed CD audio for lossless ones.
+    pub fn kbps(self) -> u32 {
+        match self {
+            Quality::Mp3_128 => 128,
+            Quality::Mp3_320 => 320,
+            Quality::Flac => 1411,
+        }
+    }
+
+    /// Extension of the files of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Quality::Mp3_128 | Quality::Mp3_320 => "mp3",
+            Quality::Flac => "flac",
+        }
+    }
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Mp3_128 => write!(f, "MP3 128kbps"),
+            Quality::Mp3_320 => write!(f, "MP3 320kbps"),
+            Quality::Flac => write!(f, "FLAC"),
+        }
+    }
+}
+
+/// Quality to download songs in, depending on the kind of request they come from. Either
+/// one quality for every request, like `quality = "flac"`, or a table of both.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(from = "QualitySetting")]
+pub struct QualityConfig {
+    pub song: Quality,
+    pub album: Quality,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QualitySetting {
+    All(Quality),
+    PerRequest {
+        #[serde(default)]
+        song: Quality,
+        #[serde(default)]
+        album: Quality,
+    },
+}
+
+impl From<QualitySetting> for QualityConfig {
+    fn from(setting: QualitySetting) -> Self {
+        match setting {
+            QualitySetting::All(quality) => QualityConfig::all(quality),
+            QualitySetting::PerRequest { song, album } => QualityConfig { song, album },
+        }
+    }
+}
+
+impl QualityConfig {
+    pub fn all(quality: Quality) -> Self {
+        QualityConfig {
+            song: quality,
+            album: quality,
+        }
+    }
+
+    pub fn for_request(&self, kind: RequestKind) -> Quality {
+        match kind {
+            // The tracks of a playlist come from different albums, like songs
+            RequestKind::Song | RequestKind::Playlist => self.song,
+            RequestKind::Album => self.album,
+        }
+    }
+}
+
+/// What to do with the featured artists of an artist name like "Main feat. Other".
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeaturedArtists {
+    /// Leave the artist name as it is.
+    #[default]
+    Keep,
+    /// Only keep the main artist, the featured ones are written to a separate tag.
+    SeparateTag,
+    /// Only keep the main artist, the featured ones are added to the title.
+    InTitle,
+}
+
+/// How much of the release date of a track its tag records.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseDateTag {
+    /// Only the year, which every player shows.
+    #[default]
+    Year,
+    /// The full date, down to the day when Deezer knows it.
+    FullDate,
+}
+
+/// Where the lyrics of a track go, the synchronized ones when Deezer has them, the plain
+/// ones otherwise.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LyricsOutput {
+    #[default]
+    Off,
+    /// In the tag, synchronized ones in the LRC format that most players read from it.
+    Embed,
+    /// Synchronized ones in an `.lrc` file next to the song, plain ones in the tag.
+    Sidecar,
+}
+
+/// Which tracks to download depending on whether their lyrics are explicit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExplicitContent {
+    #[default]
+    All,
+    /// Skip the tracks with explicit lyrics.
+    CleanOnly,
+    /// Skip the tracks without explicit lyrics.
+    ExplicitOnly,
+}
+
+impl ExplicitContent {
+    /// Whether a track with or without explicit lyrics is downloaded.
+    pub fn allows(self, explicit: bool) -> bool {
+        match self {
+            ExplicitContent::All => true,
+            ExplicitContent::CleanOnly => !explicit,
+            ExplicitContent::ExplicitOnly => explicit,
+        }
+    }
+}
+
+/// What to do when the file of a song already exists and was downloaded from the same
+/// track, known from its Deezer id tag. Only the tags can differ then. Files written
+/// without [Config::tag_deezer_ids] have no such tag and are always downloaded again.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SameTrackExisting {
+    /// Download the song again, replacing the file.
+    #[default]
+    Redownload,
+    /// Only write the tags of the song to the existing file.
+    UpdateTags,
+    /// Keep the existing file as it is.
+    Skip,
+}
+
+/// Whether looked up tracks and albums are kept to resolve later requests without the API.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupCache {
+    #[default]
+    Off,
+    /// Use the lookups of this and previous sessions, only looking up the others.
+    On,
+    /// Look everything up again, replacing the kept lookups.
+    Refresh,
+}
+
+/// Whether the songs of an album of several discs are split by disc.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscFolders {
+    /// Write every song of the album to the same directory.
+    #[default]
+    Flat,
+    /// Write the songs of each disc to a `Disc N` subdirectory of a directory named after
+    /// the album, which also gets its cover and metadata. Albums of a single disc are left
+    /// flat.
+    PerDisc,
+}
+
+/// What to do when an album is requested again while it is still being looked up or
+/// downloaded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAlbums {
+    /// Leave the album out, reporting that it was already requested.
+    #[default]
+    Skip,
+    /// Queue every track of the album again.
+    Download,
+}
+
+/// ID3 version of the tags written, the text encoding follows from it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagVersion {
+    /// Text is encoded as UTF-16, for older players that don't support ID3v2.4.
+    Id3v23,
+    /// Text is encoded as UTF-8.
+    #[default]
+    Id3v24,
+}
+
+impl From<TagVersion> for id3::Version {
+    fn from(version: TagVersion) -> Self {
+        match version {
+            TagVersion::Id3v23 => id3::Version::Id3v23,
+            TagVersion::Id3v24 => id3::Version::Id3v24,
+        }
+    }
+}
+
+/// Time of day downloads are limited to, e.g. for metered connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct DownloadWindow {
+    #[serde(deserialize_with = "deserialize_time")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub end: NaiveTime,
+}
+
+impl DownloadWindow {
+    /// Whether a time of day is in the window, which may span midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Deserialize a time of day written as `HH:MM`.
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M").map_err(serde::de::Error::custom)
+}
+
+/// Templates of the names of the downloaded files, without extension, depending on the
+/// kind of request the songs come from. `{artist}`, `{title}`, `{album}`,
+/// `{track_number}`, `{disc_number}` and `{id}` are replaced with the song's.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FileNameConfig {
+    pub song: String,
+    pub album: String,
+}
+
+impl Default for FileNameConfig {
+    fn default() -> Self {
+        Self {
+            song: String::from("{artist} - {title}"),
+            album: String::from("{artist} - {title}"),
+        }
+    }
+}
+
+impl FileNameConfig {
+    pub fn for_request(&self, kind: RequestKind) -> &str {
+        match kind {
+            RequestKind::Song | RequestKind::Playlist => &self.song,
+            RequestKind::Album => &self.album,
+        }
+    }
+}
+
+/// How entries are written in the log pane.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogFormat {
+    /// Text before each message, where `{level}` is replaced with `Success`, `Error` or
+    /// `Info`.
+    pub prefix: String,
+    /// Show the time each entry was logged at.
+    pub timestamps: bool,
+    /// Show the id of the track entries are about.
+    pub track_ids: bool,
+    /// Show the entries about the tracks of an album under the one about the album being
+    /// queued, instead of in the order they were logged.
+    pub group_requests: bool,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self {
+            prefix: String::from("[{level}] "),
+            timestamps: false,
+            track_ids: false,
+            group_requests: false,
+        }
+    }
+}
+
+/// A key with its modifiers, written like `Tab`, `Shift+Tab`, `Ctrl+N` or `F2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        let code = match key.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        code == self.code && without_implied_shift(code, key.modifiers) == self.modifiers
+    }
+}
+
+/// Drop Shift from the modifiers of the keys it changes, since terminals don't agree on
+/// whether to report it with them.
+fn without_implied_shift(code: KeyCode, modifiers: KeyModifiers) -> KeyModifiers {
+    match code {
+        KeyCode::BackTab | KeyCode::Char(_) => modifiers - KeyModifiers::SHIFT,
+        _ => modifiers,
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut parts: Vec<&str> = value.split('+').collect();
+        let key = parts.pop().unwrap_or_default();
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in parts {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("unknown modifier {} in {}", modifier, value)),
+            };
+        }
+
+        let code = match key.to_lowercase().as_str() {
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "space" => KeyCode::Char(' '),
+            "insert" => KeyCode::Insert,
+            "delete" => KeyCode::Delete,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            lower => match (lower.strip_prefix('f'), lower.chars().count()) {
+                (_, 1) => KeyCode::Char(lower.chars().next().unwrap_or_default()),
+                (Some(number), _) => match number.parse() {
+                    Ok(number @ 1..=12) => KeyCode::F(number),
+                    _ => return Err(format!("unknown key {}", value)),
+                },
+                _ => return Err(format!("unknown key {}", value)),
+            },
+        };
+
+        Ok(KeyBinding {
+            code,
+            modifiers: without_implied_shift(code, modifiers),
+        })
+    }
+}
+
+impl Display for KeyBinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+
+        match self.code {
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::BackTab => write!(f, "Shift+Tab"),
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            KeyCode::F(number) => write!(f, "F{}", number),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Keys of the actions that can be remapped.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    /// Move the focus to the next pane.
+    pub cycle_focus: KeyBinding,
+    /// Switch to the next input mode.
+    pub cycle_input_mode: KeyBinding,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            cycle_focus: KeyBinding {
+                code: KeyCode::Tab,
+                modifiers: KeyModifiers::NONE,
+            },
+            cycle_input_mode: KeyBinding {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::CONTROL,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Size of the covers embedded in the songs.
+    pub cover_size: CoverSize,
+    /// Size in pixels of the embedded covers, overriding [Config::cover_size] when Deezer
+    /// has the cover at this size.
+    pub cover_resolution: Option<u32>,
+    /// Keep the covers of the albums being downloaded in the cache directory until they are
+    /// done, so that an album resumed in a later session doesn't fetch its cover again.
+    pub cache_covers: bool,
+    /// Album cover saved next to the songs, sized independently of the embedded one.
+    pub sidecar_cover: Option<SidecarCover>,
+    /// Album metadata file written next to the songs of each album once it is done, for
+    /// the media servers that read them.
+    pub album_metadata: Option<AlbumMetadataFormat>,
+    /// Quality songs are downloaded in, for songs and playlists and for albums. Only
+    /// `mp3_128` can be downloaded without an [arl](Config::arl), the others depend on the
+    /// subscription of the account.
+    pub quality: QualityConfig,
+    /// Qualities tried in order when the requested one isn't available to the account,
+    /// the download fails when none of them is.
+    pub fallback_chain: Vec<Quality>,
+    /// `arl` cookie of a logged in Deezer account, which songs are downloaded with.
+    /// Anonymous sessions only get 128kbps MP3s.
+    pub arl: Option<String>,
+    pub tag_version: TagVersion,
+    /// Number of songs downloaded at the same time.
+    pub concurrency: u64,
+    /// Number of tracks, albums or playlists looked up at the same time, shared by every
+    /// request.
+    pub lookup_concurrency: usize,
+    pub keys: KeyBindings,
+    pub log_format: LogFormat,
+    /// Also log queued and started tracks, which can be toggled while running.
+    pub verbose_logs: bool,
+    /// Quit once every queued download is over and nothing is being typed.
+    pub exit_when_done: bool,
+    /// Move the queue selection from the last item to the first one and back.
+    pub wrap_queue_selection: bool,
+    pub file_name: FileNameConfig,
+    /// Longest file name of a song in bytes, extension included. Titles are cut short
+    /// with an ellipsis to fit, since longer names fail to be written on most systems.
+    pub max_file_name_length: usize,
+    pub disc_folders: DiscFolders,
+    /// Tag songs with the Deezer ids of their track, album and artist.
+    pub tag_deezer_ids: bool,
+    /// Tag songs with the artists Deezer credits for them and their role, composers
+    /// going to the composer field.
+    pub tag_contributors: bool,
+    /// Tags written to every song after those of its track, by name: `COMMENT`, an ID3
+    /// text frame like `TIT1` for the grouping, or any other name for a custom tag.
+    pub static_tags: BTreeMap<String, String>,
+    /// Skip songs whose file already exists in at least the quality they would be
+    /// downloaded in. Other songs are downloaded as usual, a worse existing file being
+    /// handled like any other file name collision.
+    pub upgrade_existing: bool,
+    /// Ask for a new file name in the TUI when the one of a song is taken by an existing
+    /// file, ignoring case, showing the size and bitrate of both. The existing file is
+    /// overwritten otherwise, which is only logged.
+    pub ask_on_collision: bool,
+    pub same_track_existing: SameTrackExisting,
+    pub featured_artists: FeaturedArtists,
+    pub release_date: ReleaseDateTag,
+    pub lyrics: LyricsOutput,
+    pub explicit_content: ExplicitContent,
+    /// Ask before queuing an album or playlist of more tracks than this in the TUI,
+    /// showing an estimate of its size. They are always queued right away when `None`.
+    pub confirm_batch_above: Option<usize>,
+    pub duplicate_albums: DuplicateAlbums,
+    pub lookup_cache: LookupCache,
+    /// Record finished downloads to the history file, which the recent downloads are
+    /// read from.
+    pub record_history: bool,
+    /// Only queue the tracks of a requested playlist that weren't downloaded from it
+    /// before, recording the downloaded ones to the data directory.
+    pub sync_playlists: bool,
+    /// Directory songs are downloaded to, the user's download directory when `None`.
+    pub download_dir: Option<PathBuf>,
+    /// Local directory songs are written to before being moved to the download
+    /// directory, useful when the latter is on a slow network share.
+    pub temp_dir: Option<PathBuf>,
+    /// Number of times a song or album that wasn't found is looked up again, since
+    /// Deezer sometimes transiently reports existing ones as missing.
+    pub not_found_retries: u8,
+    /// Number of downloads failing in a row after which the downloads are paused, as it
+    /// usually means the network dropped. `0` never pauses them.
+    pub pause_after_failures: u8,
+    /// Megabytes written in a session after which downloads are paused. Only what the app
+    /// writes is counted, the free space left on the disk isn't checked.
+    pub max_session_mb: Option<u64>,
+    /// Downloads only start during this window when set, requests are queued meanwhile.
+    pub download_window: Option<DownloadWindow>,
+    /// Two-letter code of the country whose catalog tracks are checked against, so that
+    /// tracks missing from it are reported as such. Deezer itself resolves tracks for the
+    /// country requests come from, which the API doesn't allow overriding.
+    pub market: Option<String>,
+    /// User-Agent of the requests the app makes itself: barcode lookups, import searches,
+    /// the proxy check, the sidecar and resized covers and the songs. Tracks, albums and
+    /// playlists are looked up by the Deezer client, which keeps its own since it doesn't
+    /// allow changing it.
+    pub user_agent: Option<String>,
+    /// Proxy for every request, the `HTTP_PROXY`/`HTTPS_PROXY` environment variables are
+    /// used when there is none.
+    pub proxy: Option<String>,
+    /// Command run after each download, as a program followed by its arguments, where
+    /// `{path}`, `{artist}` and `{title}` are replaced by those of the song, e.g. to
+    /// import it into another library. It runs without a shell but can do anything the
+    /// user can, only set one that is trusted.
+    pub post_download_command: Option<Vec<String>>,
+    /// Also write a 320kbps MP3 copy of the songs downloaded in FLAC, transcoded with
+    /// ffmpeg. It is skipped when ffmpeg isn't installed.
+    pub mp3_copy: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cover_size: CoverSize::default(),
+            cover_resolution: None,
+            cache_covers: false,
+            sidecar_cover: None,
+            album_metadata: None,
+            quality: QualityConfig::default(),
+            fallback_chain: Vec::new(),
+            arl: None,
+            tag_version: TagVersion::default(),
+            concurrency: DOWNLOAD_THREADS,
+            lookup_concurrency: LOOKUP_CONCURRENCY,
+            keys: KeyBindings::default(),
+            log_format: LogFormat::default(),
+            verbose_logs: false,
+            exit_when_done: false,
+            wrap_queue_selection: false,
+            file_name: FileNameConfig::default(),
+            max_file_name_length: 255,
+            disc_folders: DiscFolders::default(),
+            tag_deezer_ids: false,
+            tag_contributors: false,
+            static_tags: BTreeMap::new(),
+            upgrade_existing: false,
+            ask_on_collision: false,
+            same_track_existing: SameTrackExisting::default(),
+            featured_artists: FeaturedArtists::default(),
+            release_date: ReleaseDateTag::default(),
+            lyrics: LyricsOutput::default(),
+            explicit_content: ExplicitContent::default(),
+            confirm_batch_above: Some(50),
+            duplicate_albums: DuplicateAlbums::default(),
+            lookup_cache: LookupCache::default(),
+            record_history: true,
+            sync_playlists: true,
+            download_dir: None,
+            temp_dir: None,
+            not_found_retries: 0,
+            pause_after_failures: 10,
+            max_session_mb: None,
+            download_window: None,
+            market: None,
+            user_agent: None,
+            proxy: None,
+            post_download_command: None,
+            mp3_copy: false,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file from the user's config directory, falling back to the
+    /// default config when there is none.
+    pub fn load() -> Result<Config> {
+        let Some(project_dirs) = ProjectDirs::from("", "", "deeznuts-downloader") else {
+            return Ok(Config::default());
+        };
+
+        let path = project_dirs.config_dir().join("config.toml");
+        let config: Config = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|err| eyre!("Invalid config file {}: {}", path.display(), err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(err) => {
+                return Err(eyre!(
+                    "Unable to read config file {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+
+        if config
+            .log_format
+            .prefix
+            .replace("{level}", "")
+            .contains('{')
+        {
+            return Err(eyre!(
+                "Invalid log prefix {}, only {{level}} can be replaced",
+                config.log_format.prefix
+            ));
+        }
+        if config.lookup_concurrency == 0 {
+            return Err(eyre!(
+                "Invalid lookup concurrency 0, expected a number of lookups above 0"
+            ));
+        }
+        if let Some(market) = &config.market {
+            if market.len() != 2 || !market.chars().all(|x| x.is_ascii_alphabetic()) {
+                return Err(eyre!(
+                    "Invalid market {}, expected a two-letter country code",
+                    market
+                ));
+            }
+        }
+        if let Some(user_agent) = &config.user_agent {
+            if !is_valid_user_agent(user_agent) {
+                return Err(eyre!(
+                    "Invalid user agent {:?}, expected printable ASCII characters",
+                    user_agent
+                ));
+            }
+        }
+        if let Some(proxy) = &config.proxy {
+            reqwest::Proxy::all(proxy).map_err(|err| eyre!("Invalid proxy {}: {}", proxy, err))?;
+        }
+        if let Some(dir) = &config.download_dir {
+            check_download_dir(dir)?;
+        }
+        check_quality(config.quality.song, config.arl.as_deref())?;
+        check_quality(config.quality.album, config.arl.as_deref())?;
+        for (name, value) in &config.static_tags {
+            check_static_tag(name, value)?;
+        }
+        if let Some(command) = &config.post_download_command {
+            if command.first().is_none_or(|x| x.trim().is_empty()) {
+                return Err(eyre!(
+                    "Invalid post download command, expected a program followed by its arguments"
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Override the config with the `DEEZNUTS_*` environment variables that are set, for
+    /// deployments without a config file. Command line arguments are applied after them
+    /// and take precedence.
+    pub fn apply_env(&mut self) -> Result<()> {
+        self.apply_env_vars(|name| std::env::var(name).ok())
+    }
+
+    fn apply_env_vars(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(value) = var("DEEZNUTS_CONCURRENCY") {
+            self.concurrency = value.trim().parse().ok().filter(|x| *x > 0).ok_or(eyre!(
+                "Invalid DEEZNUTS_CONCURRENCY {:?}, expected a number of songs above 0",
+                value
+            ))?;
+        }
+        if let Some(value) = var("DEEZNUTS_ARL") {
+            self.arl = Some(value.trim().to_string()).filter(|x| !x.is_empty());
+        }
+        if let Some(value) = var("DEEZNUTS_QUALITY") {
+            let quality = match value.trim().to_lowercase().as_str() {
+                "mp3_128" => Quality::Mp3_128,
+                "mp3_320" => Quality::Mp3_320,
+                "flac" => Quality::Flac,
+                _ => {
+                    return Err(eyre!(
+                        "Invalid DEEZNUTS_QUALITY {:?}, expected mp3_128, mp3_320 or flac",
+                        value
+                    ))
+                }
+            };
+            check_quality(quality, self.arl.as_deref())?;
+            self.quality = QualityConfig::all(quality);
+        }
+        if let Some(value) = var("DEEZNUTS_DOWNLOAD_DIR") {
+            let dir = PathBuf::from(value);
+            check_download_dir(&dir)?;
+            self.download_dir = Some(dir);
+        }
+        Ok(())
+    }
+
+    /// Make every HTTP client use the configured proxy. Neither the Deezer client nor the
+    /// downloader allow giving one, but both read it from the environment, which must
+    /// only be changed before any other thread is started.
+    pub fn apply_proxy(&self) {
+        if let Some(proxy) = &self.proxy {
+            std::env::set_var("HTTP_PROXY", proxy);
+            std::env::set_var("HTTPS_PROXY", proxy);
+        }
+    }
+
+    /// Make the requests the app makes itself identify with the configured User-Agent,
+    /// see [Config::user_agent]. It must be applied before any of them is made.
+    pub fn apply_user_agent(&self) -> Result<()> {
+        match &self.user_agent {
+            Some(user_agent) => set_user_agent(user_agent),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check that songs can be requested in a quality, the ones above 128kbps need an account.
+/// Whether the account's subscription allows it is only known once logged in.
+fn check_quality(quality: Quality, arl: Option<&str>) -> Result<()> {
+    if quality == Quality::Mp3_128 || arl.is_some() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Quality {} needs the arl of a Deezer account, only mp3_128 can be downloaded without one",
+            quality
+        ))
+    }
+}
+
+fn check_download_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Invalid download directory {}, it isn't an existing directory",
+            dir.display()
+        ))
+    }
+}
+
+fn check_static_tag(name: &str, value: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|x| x.is_ascii_graphic() || x == ' ') {
+        return Err(eyre!(
+            "Invalid static tag name {:?}, expected printable ASCII characters",
+            name
+        ));
+    }
+    if value.trim().is_empty() || value.chars().any(char::is_control) {
+        return Err(eyre!(
+            "Invalid value {:?} for static tag {}, expected some text on a single line",
+            value,
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a User-Agent can be sent as a header value as is.
+fn is_valid_user_agent(user_agent: &str) -> bool {
+    !user_agent.trim().is_empty()
+        && user_agent
+            .chars()
+            .all(|x| x.is_ascii() && !x.is_ascii_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_static_tags() {
+        assert!(check_static_tag("COMMENT", "Downloaded via deeznuts").is_ok());
+        assert!(check_static_tag("COLLECTION", "Box sets").is_ok());
+        assert!(check_static_tag("", "Box sets").is_err());
+        assert!(check_static_tag("COLLÉCTION", "Box sets").is_err());
+        assert!(check_static_tag("COMMENT", " ").is_err());
+        assert!(check_static_tag("COMMENT", "Line\nbreak").is_err());
+    }
+
+    #[test]
+    fn environment_overrides_config() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        let mut config = Config::default();
+        config
+            .apply_env_vars(env(&[
+                ("DEEZNUTS_CONCURRENCY", "8"),
+                ("DEEZNUTS_QUALITY", "MP3_128"),
+            ]))
+            .unwrap();
+        assert_eq!(8, config.concurrency);
+        assert_eq!(QualityConfig::all(Quality::Mp3_128), config.quality);
+        assert_eq!(None, config.download_dir);
+
+        let mut config = Config::default();
+        assert!(config
+            .apply_env_vars(env(&[("DEEZNUTS_CONCURRENCY", "0")]))
+            .is_err());
+        assert!(config
+            .apply_env_vars(env(&[("DEEZNUTS_QUALITY", "mp3")]))
+            .is_err());
+        assert!(config
+            .apply_env_vars(env(&[("DEEZNUTS_QUALITY", "flac")]))
+            .is_err());
+        config
+            .apply_env_vars(env(&[
+                ("DEEZNUTS_ARL", "cookie"),
+                ("DEEZNUTS_QUALITY", "flac"),
+            ]))
+            .unwrap();
+        assert_eq!(QualityConfig::all(Quality::Flac), config.quality);
+        assert!(config
+            .apply_env_vars(env(&[("DEEZNUTS_DOWNLOAD_DIR", "/nonexistent/music")]))
+            .is_err());
+        assert_eq!(DOWNLOAD_THREADS, config.concurrency);
+    }
+
+    #[test]
+    fn validates_user_agent() {
+        assert!(is_valid_user_agent(
+            "deeznuts-downloader/1.0 (+admin@example.com)"
+        ));
+        assert!(!is_valid_user_agent(" "));
+        assert!(!is_valid_user_agent("agent\r\nX-Injected: 1"));
+        assert!(!is_valid_user_agent("agént"));
+    }
+
+    #[test]
+    fn parses_cover_size() {
+        let config: Config = toml::from_str("cover_size = \"small\"").unwrap();
+        assert_eq!(CoverSize::Small, config.cover_size);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(CoverSize::Big, config.cover_size);
+    }
+
+    #[test]
+    fn parses_quality_per_request() {
+        let config: Config = toml::from_str("[quality]\nalbum = \"flac\"").unwrap();
+        assert_eq!(
+            Quality::Flac,
+            config.quality.for_request(RequestKind::Album)
+        );
+        assert_eq!(
+            Quality::Mp3_128,
+            config.quality.for_request(RequestKind::Playlist)
+        );
+
+        let config: Config = toml::from_str("quality = \"mp3_320\"").unwrap();
+        assert_eq!(QualityConfig::all(Quality::Mp3_320), config.quality);
+    }
+
+    #[test]
+    fn parses_lyrics_output() {
+        assert_eq!(LyricsOutput::Off, Config::default().lyrics);
+        let config: Config = toml::from_str("lyrics = \"sidecar\"").unwrap();
+        assert_eq!(LyricsOutput::Sidecar, config.lyrics);
+    }
+
+    #[test]
+    fn download_window_spans_midnight() {
+        let config: Config =
+            toml::from_str("[download_window]\nstart = \"23:00\"\nend = \"07:00\"").unwrap();
+        let window = config.download_window.unwrap();
+        let time = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+
+        assert!(window.contains(time(1)));
+        assert!(window.contains(time(23)));
+        assert!(!window.contains(time(7)));
+        assert!(!window.contains(time(12)));
+    }
+
+    #[test]
+    fn parses_key_bindings() {
+        use crossterm::event::KeyEventKind;
+
+        let config: Config =
+            toml::from_str("[keys]\ncycle_focus = \"Shift+Tab\"\ncycle_input_mode = \"ctrl+m\"")
+                .unwrap();
+        let key = |code, modifiers| KeyEvent::new_with_kind(code, modifiers, KeyEventKind::Press);
+
+        assert!(config
+            .keys
+            .cycle_focus
+            .matches(&key(KeyCode::BackTab, KeyModifiers::SHIFT)));
+        assert!(!config
+            .keys
+            .cycle_focus
+            .matches(&key(KeyCode::Tab, KeyModifiers::NONE)));
+        assert!(config.keys.cycle_input_mode.matches(&key(
+            KeyCode::Char('M'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT
+        )));
+        assert_eq!("Shift+Tab", config.keys.cycle_focus.to_string());
+        assert_eq!("Ctrl+M", config.keys.cycle_input_mode.to_string());
+
+        assert!(toml::from_str::<Config>("[keys]\ncycle_focus = \"Hyper+Tab\"").is_err());
+        assert!(toml::from_str::<Config>("[keys]\ncycle_focus = \"F13\"").is_err());
+    }
+
+    #[test]
+    fn parses_tag_version() {
+        let config: Config = toml::from_str("tag_version = \"id3v23\"").unwrap();
+        assert_eq!(TagVersion::Id3v23, config.tag_version);
+    }
+}
@@ -1,232 +1,4911 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::Display;
+use std::fs::File;
+use std::future::Future;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
+use async_trait::async_trait;
+use chrono::{Local, NaiveTime};
 use color_eyre::eyre::{eyre, Result};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use deezer::{models::Track, DeezerClient};
-use deezer_downloader::{
-    song::{Album, Artist},
-    Downloader as DeezerDownloader, Song, SongMetadata,
+use directories::{ProjectDirs, UserDirs};
+use futures::future::join_all;
+use futures::{stream, StreamExt};
+use id3::frame::{Comment, Lyrics, Picture, PictureType};
+use id3::{Tag, Timestamp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::{oneshot, watch, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::cache::ResolutionCache;
+use crate::config::{
+    AlbumMetadataFormat, Config, CoverSize, DiscFolders, DownloadWindow, DuplicateAlbums,
+    ExplicitContent, FeaturedArtists, LookupCache, LyricsOutput, Quality, QualityConfig,
+    ReleaseDateTag, SameTrackExisting, SidecarCover, TagVersion,
 };
-use directories::UserDirs;
-use futures::{future::join_all, TryFutureExt};
+use crate::flac;
+use crate::history::HistoryWriter;
+use crate::media::{Decryptor, MediaSession, SongLyrics, CHUNK_SIZE};
+use crate::sync::PlaylistSync;
 
-static DOWNLOAD_THREADS: u64 = 4;
+/// Default number of songs downloaded at the same time.
+pub static DOWNLOAD_THREADS: u64 = 4;
 
-type Id = u64;
+/// Client of the requests made besides the Deezer client's and the media sessions', which
+/// use their own.
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
 
-#[derive(Debug)]
-pub enum DownloadRequest {
-    Album(Id),
-    Song(Id),
+/// User-Agent the requests the app makes itself identify with, when configured.
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+
+/// Delay before looking up again something that wasn't found, multiplied by the attempt.
+static NOT_FOUND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Interval at which the configured download window is checked for opening or closing.
+static DOWNLOAD_WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of bytes after the tag of an MP3 file searched for its first frame.
+static MP3_FRAME_SEARCH_LEN: u64 = 64 * 1024;
+
+/// Default number of tracks, albums or playlists looked up at the same time.
+pub static LOOKUP_CONCURRENCY: usize = 8;
+
+/// Number of albums whose resized cover is remembered.
+static COVER_CACHE_SIZE: usize = 32;
+
+/// Number of times a worker tries to initialize before giving up.
+static WORKER_INIT_ATTEMPTS: u32 = 5;
+
+/// Delay before a worker tries to initialize again, doubled after each attempt.
+static WORKER_INIT_DELAY: Duration = Duration::from_secs(1);
+
+pub type Id = u64;
+
+pub type GroupId = u64;
+
+/// Where tracks are looked up before being downloaded.
+#[async_trait]
+pub trait TrackSource: Send + Sync {
+    async fn track(&self, id: Id) -> Result<Option<Track>>;
+
+    async fn album_info(&self, id: Id) -> Result<Option<AlbumInfo>>;
+
+    /// Find the id of the album with the given UPC/EAN barcode.
+    async fn album_id_by_upc(&self, upc: &str) -> Result<Option<Id>>;
+
+    async fn playlist_info(&self, id: Id) -> Result<Option<PlaylistInfo>>;
 }
 
-#[derive(Debug)]
-pub enum DownloadProgress {
-    Queue(Track),
-    Start(Track),
-    Finish(Track),
-    DownloadError(Track),
-    SongNotFoundError(Id),
-    AlbumNotFoundError(Id),
+#[async_trait]
+impl TrackSource for DeezerClient {
+    async fn track(&self, id: Id) -> Result<Option<Track>> {
+        Ok(DeezerClient::track(self, id).await?)
+    }
+
+    async fn album_info(&self, id: Id) -> Result<Option<AlbumInfo>> {
+        let album = DeezerClient::album(self, id).await?;
+
+        Ok(album.map(|album| AlbumInfo {
+            title: album.title.clone(),
+            artist: album.artist.name.clone(),
+            genre: album.genres.first().map(|genre| genre.name.clone()),
+            track_ids: album.tracks.iter().map(|track| track.id).collect(),
+        }))
+    }
+
+    async fn album_id_by_upc(&self, upc: &str) -> Result<Option<Id>> {
+        // Not exposed by the client, a missing album is reported as an error object
+        #[derive(serde::Deserialize)]
+        struct UpcLookup {
+            id: Option<Id>,
+        }
+
+        let url = format!("https://api.deezer.com/album/upc:{}", upc);
+        let lookup: UpcLookup = http_client().get(url).send().await?.json().await?;
+        Ok(lookup.id)
+    }
+
+    async fn playlist_info(&self, id: Id) -> Result<Option<PlaylistInfo>> {
+        let playlist = DeezerClient::playlist(self, id).await?;
+
+        Ok(playlist.map(|playlist| PlaylistInfo {
+            title: playlist.title.clone(),
+            track_ids: playlist.tracks.iter().map(|track| track.id).collect(),
+        }))
+    }
 }
 
-#[derive(Debug)]
-pub enum DownloadStatus {
-    Downloading,
-    Inactive,
+/// Deezer client answering from the lookup cache first, when there is one.
+#[derive(Debug, Clone)]
+struct CachedSource {
+    client: Arc<DeezerClient>,
+    cache: Option<Arc<ResolutionCache>>,
+    /// Shared by every request, so that many of them don't all hit Deezer at once.
+    lookups: Arc<Semaphore>,
 }
 
-impl Display for DownloadStatus {
+#[async_trait]
+impl TrackSource for CachedSource {
+    async fn track(&self, id: Id) -> Result<Option<Track>> {
+        if let Some(track) = self.cache.as_ref().and_then(|x| x.track(id)) {
+            return Ok(Some(track));
+        }
+
+        let _permit = self.lookups.acquire().await?;
+        let track = self.client.track(id).await?;
+        if let (Some(cache), Some(track)) = (&self.cache, &track) {
+            cache.insert_track(track);
+        }
+        Ok(track)
+    }
+
+    async fn album_info(&self, id: Id) -> Result<Option<AlbumInfo>> {
+        if let Some(album) = self.cache.as_ref().and_then(|x| x.album(id)) {
+            return Ok(Some(album));
+        }
+
+        let _permit = self.lookups.acquire().await?;
+        let album = self.client.album_info(id).await?;
+        if let (Some(cache), Some(album)) = (&self.cache, &album) {
+            cache.insert_album(id, album);
+        }
+        Ok(album)
+    }
+
+    async fn album_id_by_upc(&self, upc: &str) -> Result<Option<Id>> {
+        let _permit = self.lookups.acquire().await?;
+        self.client.album_id_by_upc(upc).await
+    }
+
+    /// Playlists change over time, they are always looked up.
+    async fn playlist_info(&self, id: Id) -> Result<Option<PlaylistInfo>> {
+        let _permit = self.lookups.acquire().await?;
+        self.client.playlist_info(id).await
+    }
+}
+
+/// Lets a single source be shared by every lookup task.
+#[async_trait]
+impl<T: TrackSource> TrackSource for Arc<T> {
+    async fn track(&self, id: Id) -> Result<Option<Track>> {
+        self.as_ref().track(id).await
+    }
+
+    async fn album_info(&self, id: Id) -> Result<Option<AlbumInfo>> {
+        self.as_ref().album_info(id).await
+    }
+
+    async fn album_id_by_upc(&self, upc: &str) -> Result<Option<Id>> {
+        self.as_ref().album_id_by_upc(upc).await
+    }
+
+    async fn playlist_info(&self, id: Id) -> Result<Option<PlaylistInfo>> {
+        self.as_ref().playlist_info(id).await
+    }
+}
+
+/// What is needed from an album to queue its tracks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlbumInfo {
+    pub title: String,
+    pub artist: String,
+    pub genre: Option<String>,
+    /// Ids of the tracks, in album order.
+    pub track_ids: Vec<Id>,
+}
+
+/// What is needed from a playlist to queue its tracks.
+#[derive(Debug, Clone)]
+pub struct PlaylistInfo {
+    pub title: String,
+    /// Ids of the tracks, in playlist order.
+    pub track_ids: Vec<Id>,
+}
+
+/// Kind of request a track was queued from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestKind {
+    #[default]
+    Song,
+    Album,
+    Playlist,
+}
+
+/// A track to download, along with what is known of the request it comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadItem {
+    pub track: Track,
+    pub kind: RequestKind,
+    pub album_artist: Option<String>,
+    /// Main genre of the track's album.
+    pub genre: Option<String>,
+    /// Number of tracks and discs of the album, when the track was queued as part of it.
+    pub album_totals: Option<(u32, u32)>,
+    /// Group of tracks requested together this one is part of, e.g. an album.
+    pub group: Option<GroupId>,
+    /// Playlist the track was queued from, recorded as synced once downloaded.
+    #[serde(default)]
+    pub playlist: Option<Id>,
+}
+
+impl DownloadItem {
+    pub fn new(track: Track) -> Self {
+        Self {
+            track,
+            kind: RequestKind::Song,
+            album_artist: None,
+            genre: None,
+            album_totals: None,
+            group: None,
+            playlist: None,
+        }
+    }
+
+    /// Key ordering items by album, then by their position in it.
+    pub fn album_order(&self) -> (&str, Id, u64, u64) {
+        (
+            &self.track.album.title,
+            self.track.album.id,
+            self.track.disk_number,
+            self.track.track_position,
+        )
+    }
+}
+
+/// Size and approximate bitrate of an audio file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FileInfo {
+    pub size: u64,
+    pub kbps: u64,
+}
+
+impl FileInfo {
+    fn new(size: u64, duration_secs: u64) -> Self {
+        Self {
+            size,
+            kbps: (size * 8 / 1000)
+                .checked_div(duration_secs)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Display for FileInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        write!(
+            f,
+            "{:.1} MB, ~{} kbps",
+            self.size as f64 / 1024.0 / 1024.0,
+            self.kbps
+        )
     }
 }
 
+/// Outcome of a group of tracks, once they have all been handled.
+#[derive(Debug, Serialize)]
+pub struct GroupSummary {
+    pub id: GroupId,
+    pub name: String,
+    pub total: usize,
+    pub downloaded: usize,
+    pub failed: usize,
+    /// Tracks that can't be downloaded where the user is, left out rather than failed.
+    pub unavailable: usize,
+}
+
 #[derive(Debug)]
-pub struct Downloader {
-    pub progress_rx: Receiver<DownloadProgress>,
-    progress_tx: Sender<DownloadProgress>,
-    download_tx: Sender<Track>,
+struct Group {
+    summary: GroupSummary,
+    /// Album the group was requested as, forgotten once the group finishes.
+    album: Option<Id>,
+    /// Number of tracks that are done, whatever their outcome.
+    handled: usize,
+    /// Tracks written so far, with their file.
+    written: Vec<(DownloadItem, PathBuf)>,
 }
 
-impl Downloader {
-    pub fn new() -> Self {
-        let (download_tx, download_rx) = unbounded::<Track>();
-        let (progress_tx, progress_rx) = unbounded();
+/// Cover of an album embedded in its songs, fetched by the first of its tracks that
+/// needs it while the others wait for it. `None` when it couldn't be fetched.
+type SharedCover = Arc<tokio::sync::OnceCell<Option<Arc<Vec<u8>>>>>;
 
-        for _ in 0..DOWNLOAD_THREADS {
-            let _download_rx = download_rx.clone();
-            let _progress_tx = progress_tx.clone();
+/// Covers embedded in the songs by album id, so that the tracks of an album fetch it once
+/// between them. Albums are forgotten once their group is done, or the oldest first when
+/// room is needed. With [Config::cache_covers], covers are also kept in the cache
+/// directory until their album is done, for it to be resumed in a later session.
+#[derive(Debug, Default)]
+struct CoverCache {
+    covers: VecDeque<(Id, SharedCover)>,
+    /// Directory covers are kept in, when kept on disk.
+    dir: Option<PathBuf>,
+}
 
-            tokio::spawn(async move {
-                let downloader = DeezerDownloader::new().await.unwrap();
-                while let Ok(track) = _download_rx.recv() {
-                    _progress_tx
-                        .send(DownloadProgress::Start(track.clone()))
-                        .unwrap();
+/// Directory covers are kept in, `None` if no cache directory was found.
+fn cover_cache_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "deeznuts-downloader").map(|dirs| dirs.cache_dir().join("covers"))
+}
 
-                    let result = download_song_from_track(track.clone(), &downloader).await;
-                    let progress = match result {
-                        Ok(_) => DownloadProgress::Finish(track),
-                        Err(_) => DownloadProgress::DownloadError(track),
-                    };
+impl CoverCache {
+    fn new(dir: Option<PathBuf>) -> Self {
+        CoverCache {
+            covers: VecDeque::new(),
+            dir,
+        }
+    }
 
-                    _progress_tx.send(progress).unwrap();
-                }
-            });
+    fn remove(&mut self, album: Id) {
+        self.covers.retain(|(id, _)| *id != album);
+        if let Some(path) = self.disk_path(album) {
+            let _ = std::fs::remove_file(path);
         }
+    }
 
-        Downloader {
-            download_tx,
-            progress_tx,
-            progress_rx,
+    /// Cover of an album, fetched or not yet.
+    fn get(&mut self, album: Id) -> SharedCover {
+        if let Some((_, cover)) = self.covers.iter().find(|(id, _)| *id == album) {
+            return cover.clone();
+        }
+        if self.covers.len() == COVER_CACHE_SIZE {
+            self.covers.pop_front();
         }
+        let cover = SharedCover::default();
+        self.covers.push_back((album, cover.clone()));
+        cover
     }
 
-    pub fn request_download(&self, request: DownloadRequest) {
-        match request {
-            DownloadRequest::Song(id) => {
-                let _progress_tx = self.progress_tx.clone();
-                let _download_tx = self.download_tx.clone();
+    /// File the cover of an album is kept in, when kept on disk.
+    fn disk_path(&self, album: Id) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("{}.jpg", album)))
+    }
+}
 
-                tokio::spawn(download_song(id, _progress_tx, _download_tx));
-            }
-            DownloadRequest::Album(id) => {
-                let _progress_tx = self.progress_tx.clone();
-                let _download_tx = self.download_tx.clone();
+/// Keeps count of the outcome of the tracks of each group.
+#[derive(Debug, Default)]
+struct Groups {
+    next_id: GroupId,
+    groups: HashMap<GroupId, Group>,
+    requested_albums: Option<RequestedAlbums>,
+}
 
-                tokio::spawn(download_album(id, _progress_tx, _download_tx));
-            }
-        };
+type SharedGroups = Arc<Mutex<Groups>>;
+
+/// How requested songs and albums are looked up and queued.
+#[derive(Debug, Clone, Copy)]
+struct RequestOptions {
+    /// Number of times a song or album that wasn't found is looked up again.
+    not_found_retries: u8,
+    explicit_content: ExplicitContent,
+    /// Number of tracks above which an album waits to be confirmed instead of being queued.
+    confirm_batch_above: Option<usize>,
+    /// Quality songs are requested in, to estimate the size of albums and playlists. The
+    /// account may only allow a lower one, which is known once they are downloaded.
+    quality: QualityConfig,
+    /// Positions of the tracks of a requested album to queue, all of them when `None`.
+    track_range: Option<TrackRange>,
+    /// Number of tracks or albums of a playlist looked up at the same time.
+    lookup_concurrency: usize,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        Self {
+            not_found_retries: 0,
+            explicit_content: ExplicitContent::default(),
+            confirm_batch_above: None,
+            quality: QualityConfig::default(),
+            track_range: None,
+            lookup_concurrency: LOOKUP_CONCURRENCY,
+        }
     }
 }
 
-async fn download_song(id: u64, progress_tx: Sender<DownloadProgress>, download_tx: Sender<Track>) {
-    let client = DeezerClient::new();
-    let maybe_track = client.track(id).await;
+/// A queued track, along with the token of the downloads it was queued with.
+type QueuedItem = (DownloadItem, CancellationToken);
 
-    // Check if the song was found AND is readable
-    match maybe_track {
-        Ok(Some(track)) if track.readable => {
-            progress_tx
-                .send(DownloadProgress::Queue(track.clone()))
-                .expect("Channel should be open.");
-            download_tx.send(track).expect("Channel should be open.");
+/// Where the tracks of a request are queued, unless the downloads were cancelled since the
+/// request was made.
+#[derive(Debug, Clone)]
+struct RequestQueue {
+    download_tx: async_channel::Sender<QueuedItem>,
+    /// Token of the downloads when the request was made.
+    cancellation_token: CancellationToken,
+    /// Token of the current downloads, locked while queuing so that cancelling them
+    /// can't miss a track.
+    current_token: Arc<Mutex<CancellationToken>>,
+}
+
+impl RequestQueue {
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_token.is_cancelled()
+    }
+
+    /// Queue a track for download, giving it back if the request was cancelled.
+    fn send(&self, item: DownloadItem) -> Option<DownloadItem> {
+        let _current_token = self.current_token.lock().unwrap();
+        if self.is_cancelled() {
+            return Some(item);
         }
-        _ => {
-            progress_tx
-                .send(DownloadProgress::SongNotFoundError(id))
-                .expect("Channel should be open.");
+        self.download_tx
+            .try_send((item, self.cancellation_token.clone()))
+            .expect("Channel should be open.");
+        None
+    }
+}
+
+/// Albums requested this session, being looked up or queued.
+type RequestedAlbums = Arc<Mutex<HashSet<Id>>>;
+
+fn forget_album(requested_albums: &Option<RequestedAlbums>, id: Id) {
+    if let Some(requested_albums) = requested_albums {
+        requested_albums.lock().unwrap().remove(&id);
+    }
+}
+
+impl Groups {
+    fn add(&mut self, name: String, total: usize, album: Option<Id>) -> GroupId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let summary = GroupSummary {
+            id,
+            name,
+            total,
+            downloaded: 0,
+            failed: 0,
+            unavailable: 0,
+        };
+        self.groups.insert(
+            id,
+            Group {
+                summary,
+                album,
+                handled: 0,
+                written: Vec::new(),
+            },
+        );
+
+        id
+    }
+
+    /// Record the outcome of one of the group's tracks, giving back the group once all of
+    /// them are done.
+    fn record(&mut self, item: &DownloadItem, progress: &DownloadProgress) -> Option<Group> {
+        let id = item.group?;
+        let group = self.groups.get_mut(&id)?;
+
+        match progress {
+            DownloadProgress::Finish(_, path) => {
+                group.summary.downloaded += 1;
+                group.written.push((item.clone(), path.clone()));
+            }
+            DownloadProgress::Skipped(_, SkipReason::Unreadable)
+            | DownloadProgress::DownloadError(_, DownloadError::UnavailableInMarket(_)) => {
+                group.summary.unavailable += 1
+            }
+            DownloadProgress::DownloadError(_, _) => group.summary.failed += 1,
+            _ => {}
+        }
+
+        group.handled += 1;
+        if group.handled < group.summary.total {
+            return None;
+        }
+
+        self.finish(id)
+    }
+
+    /// Remove a group whose tracks are all done, so that its album can be requested again.
+    fn finish(&mut self, id: GroupId) -> Option<Group> {
+        let group = self.groups.remove(&id)?;
+        if let Some(album) = group.album {
+            forget_album(&self.requested_albums, album);
         }
+        Some(group)
     }
 }
 
-async fn download_album(
-    id: u64,
-    progress_tx: Sender<DownloadProgress>,
-    download_tx: Sender<Track>,
-) {
-    let client = DeezerClient::new();
-    let maybe_album = client.album(id).await;
+#[derive(Debug, Clone)]
+pub enum DownloadRequest {
+    Album(Id),
+    Song(Id),
+    /// An album, from its UPC/EAN barcode.
+    AlbumBarcode(String),
+    /// Some tracks of an album, by their position in it.
+    AlbumTracks(Id, TrackRange),
+    Playlist(Id),
+    /// A shared link such as `https://deezer.page.link/...`, only telling what it points to
+    /// once followed.
+    ShortLink(String),
+}
 
-    if let Ok(Some(album)) = maybe_album {
-        let mut futures = Vec::new();
+/// Positions of tracks in an album, from 1 and inclusive, up to the last track when the
+/// end is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TrackRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
 
-        for (index, album_track) in album.tracks.iter().enumerate() {
-            // Add current loop index to Track; we're doing this istead of using
-            // the default value because it starts over when an album has multiple CDs
-            let track = album_track.get_full().and_then(move |mut x| async move {
-                x.track_position_in_album = (index + 1) as u64;
-                Ok(x)
-            });
+impl TrackRange {
+    fn contains(&self, position: u64) -> bool {
+        self.start <= position && self.end.is_none_or(|end| position <= end)
+    }
+
+    /// Whether every position of the range is in an album of that many tracks.
+    fn fits(&self, total: usize) -> bool {
+        self.start <= total as u64 && self.end.is_none_or(|end| end <= total as u64)
+    }
+}
 
-            futures.push(async {
-                let track = track.await.expect("Track should always be available.");
+impl FromStr for TrackRange {
+    type Err = ();
 
-                progress_tx
-                    .send(DownloadProgress::Queue(track.clone()))
-                    .expect("Channel should be open.");
-                download_tx.send(track).expect("Channel should be open.");
-            });
+    /// Parse `3-7`, `3-` or a single position like `3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = match s.split_once('-') {
+            Some((start, "")) => (start.parse().map_err(|_| ())?, None),
+            Some((start, end)) => (
+                start.parse().map_err(|_| ())?,
+                Some(end.parse().map_err(|_| ())?),
+            ),
+            None => {
+                let position = s.parse().map_err(|_| ())?;
+                (position, Some(position))
+            }
+        };
+
+        if start == 0 || end.is_some_and(|end| end < start) {
+            return Err(());
         }
+        Ok(TrackRange { start, end })
+    }
+}
 
-        join_all(futures).await;
-    } else {
-        progress_tx
-            .send(DownloadProgress::AlbumNotFoundError(id))
-            .expect("Channel should be open.");
+impl Display for TrackRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) if end == self.start => write!(f, "{}", self.start),
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
     }
 }
 
-async fn download_song_from_track(track: Track, downloader: &DeezerDownloader) -> Result<()> {
-    let id = track.id;
-    let mut song = match Song::download_from_metadata(metadata_from_track(&track), downloader).await
-    {
-        Ok(it) => it,
-        Err(_) => return Err(eyre!(format!("Song with id {} not found.", id))),
-    };
+#[derive(Debug, Serialize)]
+pub enum DownloadProgress {
+    Queue(DownloadItem),
+    Start(Track),
+    Finish(Track, PathBuf),
+    /// Percentage of the download of the track done, from 0 to 100.
+    Progress(Track, u8),
+    /// The existing file of the track, of the given bitrate, is replaced by a download
+    /// of better quality.
+    Upgrade(Track, u32, Quality),
+    /// The track is downloaded in a lower quality than the requested one.
+    QualityDowngrade(Track, Quality, Quality),
+    /// The download of the track failed in the first quality, it is tried again in the
+    /// second one, the next of the fallback chain.
+    QualityRetry(Track, Quality, Quality, DownloadError),
+    /// An existing file is being replaced by the track, with their respective infos. It
+    /// is only asked first with [Config::ask_on_collision].
+    Overwrite(Track, FileInfo, FileInfo),
+    DownloadError(Track, DownloadError),
+    Cancelled(Track),
+    Skipped(Track, SkipReason),
+    SongNotFoundError(Id),
+    AlbumNotFoundError(Id),
+    /// A track of an album couldn't be found, with the ids of the album and the track.
+    AlbumTrackNotFoundError(Id, Id),
+    BarcodeNotFoundError(String),
+    /// A short link didn't lead to a track, album or playlist.
+    ShortLinkError(String),
+    PlaylistNotFoundError(Id),
+    /// A track of a playlist couldn't be found, with the ids of the playlist and the track.
+    PlaylistTrackNotFoundError(Id, Id),
+    /// A playlist synced before was requested again, with its title and the number of
+    /// tracks added since, the only ones queued.
+    PlaylistSynced(String, usize),
+    /// Nothing was found with the id, it is being looked up again for the given attempt.
+    NotFoundRetry(Id, u8),
+    /// The full track list of an album, resolved without being queued.
+    AlbumTracks(Id, Vec<DownloadItem>),
+    /// The album is still being looked up or downloaded, it isn't queued again.
+    AlbumAlreadyRequested(Id),
+    /// The range of tracks requested goes past the given number of tracks of the album.
+    TrackRangeOutOfBounds(Id, TrackRange, usize),
+    /// Number of tracks of a batch left out by the explicit content filter.
+    ContentFiltered(usize),
+    /// A group of tracks is being queued, with its name and number of tracks.
+    GroupStarted(GroupId, String, usize),
+    GroupFinished(GroupSummary),
+    /// Downloads were paused after failing the given number of times in a row.
+    Paused(u8),
+    /// Downloads were paused once the given number of bytes were written this session.
+    SessionLimitReached(u64),
+    /// The worker of the given index started downloading the track, or is idle again when
+    /// there is none.
+    WorkerStatus(usize, Option<Track>),
+    /// A worker couldn't initialize and won't download anything.
+    WorkerInitError(String),
+    /// None of the workers could initialize, nothing will be downloaded.
+    NoWorkers,
+    /// Deezer couldn't be reached through the configured proxy.
+    ProxyError(String),
+    /// The command run after the download of the track failed, with its error output.
+    PostDownloadCommandError(Track, String),
+    /// The album cover of the track couldn't be saved next to it.
+    SidecarCoverError(Track, String),
+    /// The synchronized lyrics of the track couldn't be saved next to it.
+    LyricsError(Track, String),
+    /// Percentage of the MP3 copy of the track done, from 0 to 100.
+    Mp3CopyProgress(Track, u8),
+    /// The MP3 copy of the track was written to the given file.
+    Mp3CopyFinish(Track, PathBuf),
+    /// The MP3 copy of the track couldn't be written.
+    Mp3CopyError(Track, String),
+    /// No MP3 copy of the track was written, ffmpeg isn't installed.
+    Mp3CopySkipped(Track),
+    /// The metadata file of the album with the given title couldn't be written.
+    AlbumMetadataError(String, String),
+    /// A line couldn't be written to the history or another shared file.
+    HistoryError(String),
+    /// The file the track would be written to is taken by the given file, possibly with
+    /// another case, with the infos of the existing file and of the download. The download
+    /// waits until the prompt is answered.
+    Collision(Track, PathBuf, PathBuf, FileInfo, FileInfo, CollisionPrompt),
+    /// An album or playlist has more tracks than can be queued without confirming it,
+    /// queue them with [Downloader::confirm_batch] once confirmed.
+    LargeBatch(LargeBatch),
+}
+
+/// Reports progress whether or not anyone still listens, which stops being the case
+/// while the app shuts down, so that workers finishing meanwhile don't panic.
+trait ProgressReporter {
+    fn report(&self, progress: DownloadProgress);
+}
 
-    song.tag.set_track(track.track_position_in_album as u32);
+impl ProgressReporter for Sender<DownloadProgress> {
+    fn report(&self, progress: DownloadProgress) {
+        let _ = self.send(progress);
+    }
+}
 
-    write_song_to_file(song)?;
+/// What to do with a song whose file name is already taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollisionChoice {
+    Overwrite,
+    Skip,
+    /// Write the song under this file name instead, in the same directory.
+    Rename(String),
+}
 
-    Ok(())
+/// Tracks of an album or playlist waiting to be confirmed before being queued.
+#[derive(Debug, Clone, Serialize)]
+pub struct LargeBatch {
+    /// Album the tracks were requested as, `None` for a playlist.
+    pub album: Option<Id>,
+    /// Name of the group the tracks are queued under.
+    pub name: String,
+    pub items: Vec<DownloadItem>,
+    pub quality: Quality,
+    /// Estimated size of the downloads in bytes.
+    pub size: u64,
 }
 
-/// Write a [Song] to the download directory.
-///
-/// TODO: Allow the target directory to be given.
-fn write_song_to_file(song: Song) -> Result<()> {
-    let Some(user_dirs) = UserDirs::new() else {
-        return Ok(());
-    };
+/// Answer to a file name collision, awaited by the download of the song.
+#[derive(Debug, Clone)]
+pub struct CollisionPrompt(Arc<Mutex<Option<oneshot::Sender<CollisionChoice>>>>);
 
-    if let Some(download_dirs) = user_dirs.download_dir() {
-        let song_title = format!(
-            "{} - {}.mp3",
-            song.tag.artist().unwrap_or_default(),
-            song.tag.title().unwrap_or_default()
-        );
-        let song_title = replace_illegal_characters(&song_title);
+impl CollisionPrompt {
+    pub(crate) fn new() -> (Self, oneshot::Receiver<CollisionChoice>) {
+        let (choice_tx, choice_rx) = oneshot::channel();
+        (Self(Arc::new(Mutex::new(Some(choice_tx)))), choice_rx)
+    }
 
-        song.write_to_file(download_dirs.join(song_title))
-            .map_err(|_| eyre!("An error occured while writing the file."))?;
+    /// Let the download go on, only the first answer counts.
+    pub fn answer(&self, choice: CollisionChoice) {
+        if let Some(choice_tx) = self.0.lock().unwrap().take() {
+            // The download may have been cancelled meanwhile
+            let _ = choice_tx.send(choice);
+        }
     }
+}
 
-    Ok(())
+impl Serialize for CollisionPrompt {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
 }
 
-/// Replaces illegal characters for a Windows file.
-fn replace_illegal_characters(str: &str) -> String {
-    static ILLEGAL_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// Why the download of a track failed.
+#[derive(Debug, Error, Serialize)]
+pub enum DownloadError {
+    #[error("not available in an allowed quality")]
+    Unavailable,
+    #[error("not available in market {0}")]
+    UnavailableInMarket(String),
+    #[error("network error, {0}")]
+    Network(String),
+    /// The song couldn't be written, with what was being done.
+    #[error("{0}, {1}")]
+    Io(
+        &'static str,
+        #[source]
+        #[serde(serialize_with = "serialize_error")]
+        std::io::Error,
+    ),
+    #[error("unable to write the tags, {0}")]
+    Tags(String),
+    #[error("no download directory could be found")]
+    NoDownloadDir,
+    #[error("timed out")]
+    Timeout,
+    #[error("{0}")]
+    Other(String),
+}
 
-    str.chars()
-        .filter(|char| !ILLEGAL_CHARACTERS.contains(char))
-        .collect()
+/// Serialize an error as its message.
+fn serialize_error<S: serde::Serializer>(
+    err: &impl std::error::Error,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(err)
 }
 
-fn metadata_from_track(track: &Track) -> SongMetadata {
-    SongMetadata {
-        id: track.id,
-        title: track.title.clone(),
-        artist: Artist {
-            id: track.artist.id.clone(),
-            name: track.artist.name.clone(),
-        },
-        album: Album {
-            id: track.album.id.clone(),
-            title: track.album.title.clone(),
-            cover_small: track.album.cover_small.clone(),
-            cover_medium: track.album.cover_medium.clone(),
-            cover_big: track.album.cover_big.clone(),
-        },
-        release_date: Some(track.release_date.clone()),
+/// Classify an error of a download by looking through its chain of sources for a network
+/// error.
+fn download_error(err: &(dyn std::error::Error + 'static)) -> DownloadError {
+    let chain = || std::iter::successors(Some(err), |err| err.source());
+    if let Some(err) = chain().find_map(|err| err.downcast_ref::<reqwest::Error>()) {
+        if err.is_timeout() {
+            return DownloadError::Timeout;
+        }
+        return DownloadError::Network(err.to_string());
     }
+    DownloadError::Other(err.to_string())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Serialize)]
+pub enum SkipReason {
+    AlreadyDownloading,
+    /// The existing file, of the given bitrate, is at least as good as the download.
+    NotAnUpgrade(u32),
+    /// The file name of the song was taken and it was chosen to keep the existing file.
+    FileNameTaken,
+    /// The existing file was downloaded from the same track.
+    SameTrack,
+    /// The existing file was downloaded from the same track, only its tags were written.
+    TagsUpdated,
+    /// The explicit content filter leaves out tracks with explicit lyrics.
+    Explicit,
+    /// The explicit content filter leaves out tracks without explicit lyrics.
+    NotExplicit,
+    /// Deezer doesn't allow reading the track, usually because of the user's region.
+    Unreadable,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::AlreadyDownloading => write!(f, "it is already being downloaded"),
+            SkipReason::NotAnUpgrade(kbps) => {
+                write!(f, "the existing file is already ~{} kbps", kbps)
+            }
+            SkipReason::FileNameTaken => write!(f, "its file name is taken"),
+            SkipReason::SameTrack => write!(f, "the existing file is the same track"),
+            SkipReason::TagsUpdated => {
+                write!(
+                    f,
+                    "the existing file is the same track, its tags were updated"
+                )
+            }
+            SkipReason::Explicit => write!(f, "its lyrics are explicit"),
+            SkipReason::NotExplicit => write!(f, "its lyrics aren't explicit"),
+            SkipReason::Unreadable => write!(f, "it is unavailable in your region"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DownloadStatus {
+    Downloading,
+    Inactive,
+    Completed,
+    Failed,
+}
+
+impl Display for DownloadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Pauses the downloads once too many of them failed in a row, which usually means the
+/// network dropped or the session expired, so the rest of the queue isn't marked failed.
+#[derive(Debug)]
+struct FailureValve {
+    threshold: u8,
+    consecutive_failures: Mutex<u8>,
+    paused: watch::Sender<bool>,
+}
+
+impl FailureValve {
+    fn new(threshold: u8) -> Self {
+        Self {
+            threshold,
+            consecutive_failures: Mutex::new(0),
+            paused: watch::channel(false).0,
+        }
+    }
+
+    /// Record the outcome of a download, returning the number of failures in a row if
+    /// it just paused the downloads.
+    fn record(&self, progress: &DownloadProgress) -> Option<u8> {
+        let mut failures = self.consecutive_failures.lock().unwrap();
+        match progress {
+            DownloadProgress::Finish(_, _) => *failures = 0,
+            // Says nothing about the network, a whole album may be missing from the market
+            DownloadProgress::DownloadError(_, DownloadError::UnavailableInMarket(_)) => {
+                return None
+            }
+            DownloadProgress::DownloadError(_, _) => *failures = failures.saturating_add(1),
+            _ => return None,
+        }
+
+        if self.threshold > 0 && *failures == self.threshold {
+            self.paused.send_replace(true);
+            return Some(*failures);
+        }
+        None
+    }
+
+    fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Pause the downloads for another reason than failures.
+    fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    fn resume(&self) {
+        *self.consecutive_failures.lock().unwrap() = 0;
+        self.paused.send_replace(false);
+    }
+
+    async fn wait_resumed(&self) {
+        // The sender is kept alive by `self`, so waiting can't fail
+        let _ = self.paused.subscribe().wait_for(|paused| !paused).await;
+    }
+}
+
+#[derive(Debug)]
+pub struct Downloader {
+    pub progress_rx: Receiver<DownloadProgress>,
+    progress_tx: Sender<DownloadProgress>,
+    download_tx: async_channel::Sender<QueuedItem>,
+    download_rx: async_channel::Receiver<QueuedItem>,
+    /// Token of the current batch of downloads, replaced each time it is cancelled. It is
+    /// locked while tracks are added to the queue or drained from it.
+    cancellation_token: Arc<Mutex<CancellationToken>>,
+    groups: SharedGroups,
+    /// Number of requests whose tracks are still being looked up.
+    pending_requests: Arc<Mutex<usize>>,
+    failure_valve: Arc<FailureValve>,
+    /// Directory songs are written to, `None` if none could be found.
+    download_dir: Arc<Mutex<Option<PathBuf>>>,
+    download_window: Option<DownloadWindow>,
+    /// Whether the current time is in the download window, if there is one.
+    window_open: Arc<watch::Sender<bool>>,
+    /// Whether each worker may take new tracks, so that one can be paused on its own.
+    worker_gates: Vec<watch::Sender<bool>>,
+    /// Number of bytes written since the start of the session or the last resume.
+    session_size: Arc<Mutex<u64>>,
+    request_options: RequestOptions,
+    /// Albums requested this session, `None` when requesting them again is allowed.
+    requested_albums: Option<RequestedAlbums>,
+    workers: u64,
+    /// Client shared by every lookup, so that its connections are reused.
+    client: Arc<DeezerClient>,
+    source: CachedSource,
+    /// Tracks already downloaded from each playlist, `None` when they aren't recorded.
+    playlist_sync: Option<Arc<PlaylistSync>>,
+}
+
+impl Downloader {
+    pub fn new(config: Config) -> Self {
+        // Awaited by the workers, so that waiting for a track doesn't block a runtime thread
+        let (download_tx, download_rx) = async_channel::unbounded::<QueuedItem>();
+        let (progress_tx, progress_rx) = unbounded();
+        let cancellation_token = Arc::new(Mutex::new(CancellationToken::new()));
+        // Ids of the tracks being downloaded, so two workers never write the same file
+        let in_flight = Arc::new(Mutex::new(HashSet::<Id>::new()));
+        let requested_albums =
+            (config.duplicate_albums == DuplicateAlbums::Skip).then(RequestedAlbums::default);
+        let groups = Arc::new(Mutex::new(Groups {
+            requested_albums: requested_albums.clone(),
+            ..Groups::default()
+        }));
+        let failure_valve = Arc::new(FailureValve::new(config.pause_after_failures));
+        let download_dir = Arc::new(Mutex::new(
+            config.download_dir.clone().or_else(default_download_dir),
+        ));
+        let failed_workers = Arc::new(Mutex::new(0));
+        let window_open = Arc::new(watch::channel(true).0);
+        let session_size = Arc::new(Mutex::new(0));
+        let workers = config.concurrency.max(1);
+        let session_limit = config.max_session_mb.map(|x| x * 1024 * 1024);
+        let history = HistoryWriter::spawn(progress_tx.clone());
+        let cover_dir = config.cache_covers.then(cover_cache_dir).flatten();
+        let cover_cache = Arc::new(Mutex::new(CoverCache::new(cover_dir)));
+        let client = Arc::new(DeezerClient::new());
+        let cache = match config.lookup_cache {
+            LookupCache::Off => None,
+            LookupCache::On => ResolutionCache::open(false, history.clone()).map(Arc::new),
+            LookupCache::Refresh => ResolutionCache::open(true, history.clone()).map(Arc::new),
+        };
+        let playlist_sync = match config.sync_playlists {
+            true => PlaylistSync::open(history.clone()).map(Arc::new),
+            false => None,
+        };
+        if let Some(window) = config.download_window {
+            tokio::spawn(update_download_window(window, window_open.clone()));
+        }
+        if config.proxy.is_some() {
+            tokio::spawn(check_proxy(progress_tx.clone()));
+        }
+
+        let worker_gates = (0..workers)
+            .map(|_| watch::channel(true).0)
+            .collect::<Vec<_>>();
+
+        for (worker, gate) in worker_gates.iter().enumerate() {
+            let _download_rx = download_rx.clone();
+            let mut _gate = gate.subscribe();
+            let _progress_tx = progress_tx.clone();
+            let _config = config.clone();
+            let _in_flight = in_flight.clone();
+            let _groups = groups.clone();
+            let _failure_valve = failure_valve.clone();
+            let _download_dir = download_dir.clone();
+            let _failed_workers = failed_workers.clone();
+            let mut _window_open = window_open.subscribe();
+            let _session_size = session_size.clone();
+            let _history = history.clone();
+            let _cover_cache = cover_cache.clone();
+            let _playlist_sync = playlist_sync.clone();
+
+            tokio::spawn(async move {
+                let session = match init_session(_config.arl.as_deref()).await {
+                    Ok(session) => session,
+                    Err(err) => {
+                        let mut failed_workers = _failed_workers.lock().unwrap();
+                        *failed_workers += 1;
+                        _progress_tx.report(DownloadProgress::WorkerInitError(err.to_string()));
+                        if *failed_workers == workers {
+                            _progress_tx.report(DownloadProgress::NoWorkers);
+                        }
+                        return;
+                    }
+                };
+
+                loop {
+                    _failure_valve.wait_resumed().await;
+                    // The sender is kept alive by the downloader, so waiting can't fail
+                    let _ = _window_open.wait_for(|open| *open).await;
+                    let _ = _gate.wait_for(|running| *running).await;
+                    let (item, cancellation_token) = tokio::select! {
+                        queued = _download_rx.recv() => match queued {
+                            Ok(queued) => queued,
+                            Err(_) => break,
+                        },
+                        // Paused while waiting for a track, it is left in the queue
+                        _ = _gate.wait_for(|running| !*running) => continue,
+                    };
+                    let download_dir = _download_dir.lock().unwrap().clone();
+                    let track = item.track.clone();
+
+                    // Cancelled while taking it
+                    if cancellation_token.is_cancelled() {
+                        let progress = DownloadProgress::Cancelled(track);
+                        send_outcome(&item, progress, &_groups, &_progress_tx);
+                        continue;
+                    }
+
+                    if !_in_flight.lock().unwrap().insert(track.id) {
+                        let progress =
+                            DownloadProgress::Skipped(track, SkipReason::AlreadyDownloading);
+                        send_outcome(&item, progress, &_groups, &_progress_tx);
+                        continue;
+                    }
+
+                    _progress_tx
+                        .report(DownloadProgress::WorkerStatus(worker, Some(track.clone())));
+                    _progress_tx.report(DownloadProgress::Start(track.clone()));
+
+                    let progress = tokio::select! {
+                        result = download_song_from_item(&item, &session, download_dir.as_deref(), &_config, &_cover_cache, &_progress_tx) => {
+                            match result {
+                                Ok(Downloaded::Written(path)) => DownloadProgress::Finish(track, path),
+                                Ok(Downloaded::Skipped(reason)) => DownloadProgress::Skipped(track, reason),
+                                Err(err) => DownloadProgress::DownloadError(track, err),
+                            }
+                        }
+                        _ = cancellation_token.cancelled() => DownloadProgress::Cancelled(track),
+                    };
+
+                    _in_flight.lock().unwrap().remove(&item.track.id);
+
+                    if let (Some(sync), Some(playlist)) = (&_playlist_sync, item.playlist) {
+                        if is_synced(&progress) {
+                            sync.insert(playlist, item.track.id);
+                        }
+                    }
+                    let paused_after = _failure_valve.record(&progress);
+                    let limit_reached = match &progress {
+                        DownloadProgress::Finish(track, path) => {
+                            if _config.record_history {
+                                _history.record(track, path);
+                            }
+                            if let Some(command) = &_config.post_download_command {
+                                tokio::spawn(run_post_download_command(
+                                    command.clone(),
+                                    track.clone(),
+                                    path.clone(),
+                                    _progress_tx.clone(),
+                                ));
+                            }
+                            if _config.mp3_copy && flac::is_flac(path) {
+                                tokio::spawn(write_mp3_copy(
+                                    track.clone(),
+                                    path.clone(),
+                                    _config.tag_version,
+                                    _progress_tx.clone(),
+                                ));
+                            }
+                            record_session_size(&_session_size, path, session_limit)
+                        }
+                        _ => None,
+                    };
+                    let written = send_outcome(&item, progress, &_groups, &_progress_tx);
+                    // Covers are kept for the other tracks of an album until it is done
+                    if item.group.is_none() {
+                        _cover_cache.lock().unwrap().remove(item.track.album.id);
+                    }
+                    if let Some(written) = &written {
+                        let mut cover_cache = _cover_cache.lock().unwrap();
+                        cover_cache.remove(item.track.album.id);
+                        written
+                            .iter()
+                            .for_each(|(x, _)| cover_cache.remove(x.track.album.id));
+                    }
+                    if let (Some(format), Some(written)) = (_config.album_metadata, written) {
+                        // The songs are there even without it, so this doesn't fail them
+                        if let Err(err) = write_album_metadata(&written, format, &_config) {
+                            _progress_tx.report(DownloadProgress::AlbumMetadataError(
+                                item.track.album.title.clone(),
+                                err.to_string(),
+                            ));
+                        }
+                    }
+                    _progress_tx.report(DownloadProgress::WorkerStatus(worker, None));
+                    if let Some(failures) = paused_after {
+                        _progress_tx.report(DownloadProgress::Paused(failures));
+                    }
+                    if let Some(size) = limit_reached {
+                        _failure_valve.pause();
+                        _progress_tx.report(DownloadProgress::SessionLimitReached(size));
+                    }
+                }
+            });
+        }
+
+        Downloader {
+            download_tx,
+            progress_tx,
+            progress_rx,
+            download_rx,
+            cancellation_token,
+            groups,
+            pending_requests: Arc::new(Mutex::new(0)),
+            failure_valve,
+            download_dir,
+            download_window: config.download_window,
+            window_open,
+            session_size,
+            request_options: RequestOptions {
+                not_found_retries: config.not_found_retries,
+                explicit_content: config.explicit_content,
+                confirm_batch_above: config.confirm_batch_above,
+                quality: config.quality,
+                track_range: None,
+                lookup_concurrency: config.lookup_concurrency,
+            },
+            requested_albums,
+            worker_gates,
+            workers,
+            source: CachedSource {
+                client: client.clone(),
+                cache,
+                lookups: Arc::new(Semaphore::new(config.lookup_concurrency)),
+            },
+            client,
+            playlist_sync,
+        }
+    }
+
+    /// Client used to look up tracks and albums.
+    pub fn client(&self) -> Arc<DeezerClient> {
+        self.client.clone()
+    }
+
+    /// Number of songs downloaded at the same time.
+    pub fn workers(&self) -> u64 {
+        self.workers
+    }
+
+    /// Stop a worker from taking new tracks, or let it take them again, e.g. to tell
+    /// whether failures come from one of them. Its current track is still downloaded.
+    /// Returns whether it is now paused, `None` if there is no such worker.
+    pub fn toggle_worker_paused(&self, worker: usize) -> Option<bool> {
+        let gate = self.worker_gates.get(worker)?;
+        let paused = *gate.borrow();
+        gate.send_replace(!paused);
+        Some(paused)
+    }
+
+    pub fn is_worker_paused(&self, worker: usize) -> bool {
+        self.worker_gates.get(worker).is_some_and(|x| !*x.borrow())
+    }
+
+    /// Start a downloader writing songs to the given directory rather than the configured
+    /// one, it is created along with the first song.
+    pub fn with_download_dir(config: Config, dir: PathBuf) -> Self {
+        Self::new(Config {
+            download_dir: Some(dir),
+            ..config
+        })
+    }
+
+    pub fn download_dir(&self) -> Option<PathBuf> {
+        self.download_dir.lock().unwrap().clone()
+    }
+
+    /// Change the directory songs are downloaded to, if it exists and is writable. Songs
+    /// already being downloaded are still written to the previous one.
+    pub fn set_download_dir(&self, dir: PathBuf) -> Result<()> {
+        check_writable_dir(&dir)?;
+        *self.download_dir.lock().unwrap() = Some(dir);
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.failure_valve.is_paused()
+    }
+
+    /// Time at which downloads resume, when they are waiting for the download window.
+    pub fn scheduled_until(&self) -> Option<NaiveTime> {
+        let window = self.download_window?;
+        (!*self.window_open.borrow()).then_some(window.start)
+    }
+
+    /// Resume downloads paused after too many failures.
+    pub fn resume(&self) {
+        *self.session_size.lock().unwrap() = 0;
+        self.failure_valve.resume();
+    }
+
+    /// Drop every queued track and cancel the ones being downloaded, along with the tracks
+    /// of the requests still being looked up. The workers then wait for new tracks as
+    /// usual.
+    pub fn cancel_all(&self) {
+        let mut cancellation_token = self.cancellation_token.lock().unwrap();
+
+        while let Ok((item, _)) = self.download_rx.try_recv() {
+            let progress = DownloadProgress::Cancelled(item.track.clone());
+            send_outcome(&item, progress, &self.groups, &self.progress_tx);
+        }
+
+        cancellation_token.cancel();
+        *cancellation_token = CancellationToken::new();
+        if let Some(requested_albums) = &self.requested_albums {
+            requested_albums.lock().unwrap().clear();
+        }
+    }
+
+    /// Forget that an album was requested, once its tracks were declined.
+    pub fn forget_album_request(&self, id: Id) {
+        forget_album(&self.requested_albums, id);
+    }
+
+    /// Queue the tracks of an album or playlist that waited for confirmation.
+    pub fn confirm_batch(&self, batch: LargeBatch) {
+        queue_group(
+            batch.name,
+            batch.items,
+            batch.album,
+            RequestOptions {
+                confirm_batch_above: None,
+                ..self.request_options
+            },
+            &self.groups,
+            &self.progress_tx,
+            &self.request_queue(),
+        );
+    }
+
+    /// Reorder the queued tracks by album then by their position in it, so that they are
+    /// written in a predictable sequence. Tracks being downloaded are left alone. Returns
+    /// the ids of the queued tracks in their new order.
+    pub fn sort_queue(&self) -> Vec<Id> {
+        // Nothing is queued or taken meanwhile, the order can't change under the sort
+        let _cancellation_token = self.cancellation_token.lock().unwrap();
+        let mut items = std::iter::from_fn(|| self.download_rx.try_recv().ok()).collect::<Vec<_>>();
+        items.sort_by(|(a, _), (b, _)| a.album_order().cmp(&b.album_order()));
+
+        let order = items.iter().map(|(x, _)| x.track.id).collect();
+        for item in items {
+            self.download_tx
+                .try_send(item)
+                .expect("Channel should be open.");
+        }
+        order
+    }
+
+    /// Queue of a new request, whose tracks are cancelled along with the current downloads.
+    fn request_queue(&self) -> RequestQueue {
+        RequestQueue {
+            download_tx: self.download_tx.clone(),
+            cancellation_token: self.cancellation_token.lock().unwrap().clone(),
+            current_token: self.cancellation_token.clone(),
+        }
+    }
+
+    /// Look up a request in the background, counting it as pending until its tracks are
+    /// queued or it fails.
+    fn spawn_request(&self, request: impl Future<Output = ()> + Send + 'static) {
+        let pending_requests = self.pending_requests.clone();
+        *pending_requests.lock().unwrap() += 1;
+
+        tokio::spawn(async move {
+            request.await;
+            *pending_requests.lock().unwrap() -= 1;
+        });
+    }
+
+    /// Whether some requests are still being looked up. Their tracks are all reported
+    /// before they stop being pending.
+    pub fn has_pending_requests(&self) -> bool {
+        *self.pending_requests.lock().unwrap() > 0
+    }
+
+    pub fn request_download(&self, request: DownloadRequest) {
+        match request {
+            DownloadRequest::Song(id) => {
+                let _progress_tx = self.progress_tx.clone();
+                let _queue = self.request_queue();
+                let _groups = self.groups.clone();
+
+                self.spawn_request(download_song(
+                    id,
+                    self.source.clone(),
+                    self.request_options,
+                    _groups,
+                    _progress_tx,
+                    _queue,
+                ));
+            }
+            DownloadRequest::Album(id) => {
+                let _progress_tx = self.progress_tx.clone();
+                let _queue = self.request_queue();
+                let _groups = self.groups.clone();
+
+                self.spawn_request(download_album(
+                    id,
+                    self.source.clone(),
+                    self.request_options,
+                    _groups,
+                    self.requested_albums.clone(),
+                    _progress_tx,
+                    _queue,
+                ));
+            }
+            DownloadRequest::AlbumTracks(id, range) => {
+                let _progress_tx = self.progress_tx.clone();
+                let _queue = self.request_queue();
+                let _groups = self.groups.clone();
+
+                // Other tracks of the album may be requested later, it isn't remembered
+                self.spawn_request(download_album(
+                    id,
+                    self.source.clone(),
+                    RequestOptions {
+                        track_range: Some(range),
+                        ..self.request_options
+                    },
+                    _groups,
+                    None,
+                    _progress_tx,
+                    _queue,
+                ));
+            }
+            DownloadRequest::AlbumBarcode(upc) => {
+                let _progress_tx = self.progress_tx.clone();
+                let _queue = self.request_queue();
+                let _groups = self.groups.clone();
+
+                self.spawn_request(download_album_by_barcode(
+                    upc,
+                    self.source.clone(),
+                    self.request_options,
+                    _groups,
+                    self.requested_albums.clone(),
+                    _progress_tx,
+                    _queue,
+                ));
+            }
+            DownloadRequest::Playlist(id) => {
+                let _progress_tx = self.progress_tx.clone();
+                let _queue = self.request_queue();
+                let _groups = self.groups.clone();
+
+                self.spawn_request(download_playlist(
+                    id,
+                    self.source.clone(),
+                    self.request_options,
+                    _groups,
+                    self.playlist_sync.clone(),
+                    _progress_tx,
+                    _queue,
+                ));
+            }
+            DownloadRequest::ShortLink(link) => {
+                let _progress_tx = self.progress_tx.clone();
+                let _queue = self.request_queue();
+                let _groups = self.groups.clone();
+
+                self.spawn_request(download_short_link(
+                    link,
+                    self.source.clone(),
+                    self.request_options,
+                    _groups,
+                    self.playlist_sync.clone(),
+                    _progress_tx,
+                    _queue,
+                ));
+            }
+        };
+    }
+
+    /// Resolve the tracks of an album without queuing them, the result is sent as a
+    /// [DownloadProgress::AlbumTracks].
+    pub fn request_album_tracks(&self, id: Id) {
+        let _progress_tx = self.progress_tx.clone();
+
+        self.spawn_request(resolve_album_tracks(
+            id,
+            self.source.clone(),
+            self.request_options.not_found_retries,
+            _progress_tx,
+        ));
+    }
+
+    /// Queue already resolved tracks for download, grouped under the given name if any.
+    pub fn enqueue_items(&self, mut items: Vec<DownloadItem>, group_name: Option<String>) {
+        if let Some(name) = group_name {
+            let group = self
+                .groups
+                .lock()
+                .unwrap()
+                .add(name.clone(), items.len(), None);
+            items.iter_mut().for_each(|x| x.group = Some(group));
+            self.progress_tx
+                .report(DownloadProgress::GroupStarted(group, name, items.len()));
+        }
+
+        let filtered = queue_items(
+            items,
+            self.request_options.explicit_content,
+            &self.groups,
+            &self.progress_tx,
+            &self.request_queue(),
+        );
+        if filtered > 0 {
+            self.progress_tx
+                .report(DownloadProgress::ContentFiltered(filtered));
+        }
+    }
+}
+
+async fn download_song(
+    id: u64,
+    source: impl TrackSource,
+    options: RequestOptions,
+    groups: SharedGroups,
+    progress_tx: Sender<DownloadProgress>,
+    queue: RequestQueue,
+) {
+    let retries = options.not_found_retries;
+    let maybe_track = retry_not_found(id, retries, &progress_tx, || source.track(id)).await;
+
+    // Check if the song was found AND is readable
+    match maybe_track {
+        Ok(Some(track)) if track.readable => {
+            // Only albums have a genre
+            let genre = match source.album_info(track.album.id).await {
+                Ok(Some(album)) => album.genre,
+                _ => None,
+            };
+            let item = DownloadItem {
+                genre,
+                ..DownloadItem::new(track)
+            };
+            queue_items(
+                vec![item],
+                options.explicit_content,
+                &groups,
+                &progress_tx,
+                &queue,
+            );
+        }
+        _ => {
+            progress_tx.report(DownloadProgress::SongNotFoundError(id));
+        }
+    }
+}
+
+async fn download_album(
+    id: u64,
+    source: impl TrackSource,
+    options: RequestOptions,
+    groups: SharedGroups,
+    requested_albums: Option<RequestedAlbums>,
+    progress_tx: Sender<DownloadProgress>,
+    queue: RequestQueue,
+) {
+    if let Some(requested_albums) = &requested_albums {
+        if !requested_albums.lock().unwrap().insert(id) {
+            progress_tx.report(DownloadProgress::AlbumAlreadyRequested(id));
+            return;
+        }
+    }
+
+    let retries = options.not_found_retries;
+    if let Some((album, mut items)) = resolve_album(id, &source, retries, &progress_tx).await {
+        let mut name = format!("Album \"{}\"", album.title);
+        if let Some(range) = options.track_range {
+            let total = album.track_ids.len();
+            if !range.fits(total) {
+                forget_album(&requested_albums, id);
+                progress_tx.report(DownloadProgress::TrackRangeOutOfBounds(id, range, total));
+                return;
+            }
+            items.retain(|x| range.contains(x.track.track_position_in_album));
+            name = format!("{} (tracks {})", name, range);
+        }
+
+        queue_group(
+            name,
+            items,
+            Some(id),
+            options,
+            &groups,
+            &progress_tx,
+            &queue,
+        );
+    } else {
+        // It may be found when requested again
+        forget_album(&requested_albums, id);
+        progress_tx.report(DownloadProgress::AlbumNotFoundError(id));
+    }
+}
+
+async fn download_playlist(
+    id: u64,
+    source: impl TrackSource,
+    options: RequestOptions,
+    groups: SharedGroups,
+    playlist_sync: Option<Arc<PlaylistSync>>,
+    progress_tx: Sender<DownloadProgress>,
+    queue: RequestQueue,
+) {
+    let retries = options.not_found_retries;
+    let playlist = retry_not_found(id, retries, &progress_tx, || source.playlist_info(id))
+        .await
+        .ok()
+        .flatten();
+    let Some(playlist) = playlist else {
+        progress_tx.report(DownloadProgress::PlaylistNotFoundError(id));
+        return;
+    };
+
+    // Only the tracks added since the playlist was last synced are queued
+    let synced = playlist_sync.map(|x| x.synced(id)).unwrap_or_default();
+    let track_ids = playlist
+        .track_ids
+        .iter()
+        .copied()
+        .filter(|x| !synced.contains(x))
+        .collect::<Vec<_>>();
+    if !synced.is_empty() {
+        progress_tx.report(DownloadProgress::PlaylistSynced(
+            playlist.title.clone(),
+            track_ids.len(),
+        ));
+    }
+
+    // Playlists can have thousands of tracks, they aren't all looked up at once
+    let (source, progress_tx_ref) = (&source, &progress_tx);
+    let tracks = stream::iter(track_ids)
+        .map(|track_id| async move {
+            let track = source.track(track_id).await.ok().flatten();
+            if track.is_none() {
+                progress_tx_ref.report(DownloadProgress::PlaylistTrackNotFoundError(id, track_id));
+            }
+            track
+        })
+        .buffered(options.lookup_concurrency)
+        // Missing tracks are skipped, the others keep their order in the playlist
+        .filter_map(|x| async move { x })
+        .collect::<Vec<_>>()
+        .await;
+
+    // Tracks of the same album share its lookup
+    let album_ids = tracks.iter().map(|x| x.album.id).collect::<HashSet<_>>();
+    let albums = stream::iter(album_ids)
+        .map(|album_id| async move {
+            let album = source.album_info(album_id).await.ok().flatten();
+            (album_id, album)
+        })
+        .buffer_unordered(options.lookup_concurrency)
+        .collect::<HashMap<_, _>>()
+        .await;
+
+    let items = tracks
+        .into_iter()
+        .map(|mut track| {
+            // The tags are about the album of the track, not its place in the playlist
+            let album = albums.get(&track.album.id).and_then(Option::as_ref);
+            if let Some(index) = album.and_then(|x| x.track_ids.iter().position(|x| *x == track.id))
+            {
+                track.track_position_in_album = (index + 1) as u64;
+            }
+            DownloadItem {
+                kind: RequestKind::Playlist,
+                genre: album.and_then(|x| x.genre.clone()),
+                playlist: Some(id),
+                ..DownloadItem::new(track)
+            }
+        })
+        .collect::<Vec<_>>();
+    let name = format!("Playlist \"{}\"", playlist.title);
+    queue_group(name, items, None, options, &groups, &progress_tx, &queue);
+}
+
+/// Whether the track of an outcome is in the download directory, so that it is synced
+/// with the playlist it was queued from.
+fn is_synced(progress: &DownloadProgress) -> bool {
+    matches!(
+        progress,
+        DownloadProgress::Finish(_, _)
+            | DownloadProgress::Skipped(
+                _,
+                SkipReason::NotAnUpgrade(_) | SkipReason::SameTrack | SkipReason::TagsUpdated
+            )
+    )
+}
+
+/// Queue resolved tracks for download under a new group of the given name, unless there
+/// are too many of them to be queued without confirmation.
+fn queue_group(
+    name: String,
+    mut items: Vec<DownloadItem>,
+    album: Option<Id>,
+    options: RequestOptions,
+    groups: &SharedGroups,
+    progress_tx: &Sender<DownloadProgress>,
+    queue: &RequestQueue,
+) {
+    if queue.is_cancelled() {
+        if let Some(album) = album {
+            forget_album(&groups.lock().unwrap().requested_albums, album);
+        }
+        return;
+    }
+    if options.confirm_batch_above.is_some_and(|x| items.len() > x) {
+        let kind = match album {
+            Some(_) => RequestKind::Album,
+            None => RequestKind::Playlist,
+        };
+        let quality = options.quality.for_request(kind);
+        progress_tx.report(DownloadProgress::LargeBatch(LargeBatch {
+            album,
+            size: estimated_size(&items, quality),
+            name,
+            items,
+            quality,
+        }));
+        return;
+    }
+    let group = groups.lock().unwrap().add(name.clone(), items.len(), album);
+    items.iter_mut().for_each(|x| x.group = Some(group));
+    progress_tx.report(DownloadProgress::GroupStarted(group, name, items.len()));
+
+    // No track will finish it, e.g. a playlist whose tracks were all removed
+    if items.is_empty() {
+        if let Some(group) = groups.lock().unwrap().finish(group) {
+            progress_tx.report(DownloadProgress::GroupFinished(group.summary));
+        }
+        return;
+    }
+
+    let filtered = queue_items(items, options.explicit_content, groups, progress_tx, queue);
+    if filtered > 0 {
+        progress_tx.report(DownloadProgress::ContentFiltered(filtered));
+    }
+}
+
+/// Estimated size in bytes of the songs of tracks downloaded in a quality.
+fn estimated_size(items: &[DownloadItem], quality: Quality) -> u64 {
+    let duration = items.iter().map(|x| x.track.duration).sum::<u64>();
+    duration * quality.kbps() as u64 * 1000 / 8
+}
+
+/// Queue resolved tracks for download, skipping right away the unreadable ones and the
+/// ones the explicit content filter leaves out. Returns how many the filter left out.
+fn queue_items(
+    items: Vec<DownloadItem>,
+    explicit_content: ExplicitContent,
+    groups: &SharedGroups,
+    progress_tx: &Sender<DownloadProgress>,
+    queue: &RequestQueue,
+) -> usize {
+    let mut filtered = 0;
+    for item in items {
+        if queue.is_cancelled() {
+            let progress = DownloadProgress::Cancelled(item.track.clone());
+            send_outcome(&item, progress, groups, progress_tx);
+            continue;
+        }
+        progress_tx.report(DownloadProgress::Queue(item.clone()));
+
+        let explicit = item.track.explicit_lyrics;
+        // The rest of an album is still downloaded, its summary tells how many were left out
+        let reason = if !item.track.readable {
+            SkipReason::Unreadable
+        } else if explicit_content.allows(explicit) {
+            if let Some(item) = queue.send(item) {
+                let progress = DownloadProgress::Cancelled(item.track.clone());
+                send_outcome(&item, progress, groups, progress_tx);
+            }
+            continue;
+        } else {
+            filtered += 1;
+            match explicit {
+                true => SkipReason::Explicit,
+                false => SkipReason::NotExplicit,
+            }
+        };
+        let progress = DownloadProgress::Skipped(item.track.clone(), reason);
+        send_outcome(&item, progress, groups, progress_tx);
+    }
+    filtered
+}
+
+async fn resolve_album_tracks(
+    id: u64,
+    source: impl TrackSource,
+    retries: u8,
+    progress_tx: Sender<DownloadProgress>,
+) {
+    let progress = match resolve_album(id, &source, retries, &progress_tx).await {
+        Some((_, items)) => DownloadProgress::AlbumTracks(id, items),
+        None => DownloadProgress::AlbumNotFoundError(id),
+    };
+
+    progress_tx.report(progress);
+}
+
+/// Get an album along with every one of its tracks, in album order.
+async fn resolve_album(
+    id: u64,
+    source: &impl TrackSource,
+    retries: u8,
+    progress_tx: &Sender<DownloadProgress>,
+) -> Option<(AlbumInfo, Vec<DownloadItem>)> {
+    let album = retry_not_found(id, retries, progress_tx, || source.album_info(id))
+        .await
+        .ok()
+        .flatten()?;
+
+    let futures = album
+        .track_ids
+        .iter()
+        .copied()
+        .enumerate()
+        .map(|(index, track_id)| async move {
+            let Some(mut track) = source.track(track_id).await.ok().flatten() else {
+                progress_tx.report(DownloadProgress::AlbumTrackNotFoundError(id, track_id));
+                return None;
+            };
+
+            // Add current loop index to Track; we're doing this istead of using
+            // the default value because it starts over when an album has multiple CDs
+            track.track_position_in_album = (index + 1) as u64;
+            Some(track)
+        });
+
+    // Missing tracks are skipped, the others keep their position in the album
+    let tracks = join_all(futures)
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let album_artist = album_artist(&album.artist, &tracks);
+    let total_discs = tracks.iter().map(|x| x.disk_number).max().unwrap_or(1);
+    let album_totals = (album.track_ids.len() as u32, total_discs as u32);
+
+    let items = tracks
+        .into_iter()
+        .map(|track| DownloadItem {
+            kind: RequestKind::Album,
+            album_artist: Some(album_artist.clone()),
+            album_totals: Some(album_totals),
+            genre: album.genre.clone(),
+            ..DownloadItem::new(track)
+        })
+        .collect();
+
+    Some((album, items))
+}
+
+/// Add the size of a written file to the session size, returning the latter if it just
+/// reached the limit.
+fn record_session_size(session_size: &Mutex<u64>, path: &Path, limit: Option<u64>) -> Option<u64> {
+    let written = std::fs::metadata(path).map(|x| x.len()).unwrap_or_default();
+    let mut session_size = session_size.lock().unwrap();
+    let previous = *session_size;
+    *session_size += written;
+
+    let limit = limit?;
+    (previous < limit && *session_size >= limit).then_some(*session_size)
+}
+
+/// Identify the requests of [http_client] and of the clients of [client_builder] with a
+/// User-Agent. Only the app's own requests go through them, and it can only be set before
+/// the first of them.
+pub fn set_user_agent(user_agent: &str) -> Result<()> {
+    let client = reqwest::Client::builder().user_agent(user_agent).build()?;
+    HTTP_CLIENT
+        .set(client)
+        .map_err(|_| eyre!("The User-Agent must be set before any request is made"))?;
+    // Set along with the client, which would have failed already
+    let _ = USER_AGENT.set(user_agent.to_string());
+    Ok(())
+}
+
+pub(crate) fn http_client() -> &'static reqwest::Client {
+    HTTP_CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// Builder of the clients that can't be shared, identifying with the configured
+/// User-Agent.
+pub(crate) fn client_builder() -> reqwest::ClientBuilder {
+    match USER_AGENT.get() {
+        Some(user_agent) => reqwest::Client::builder().user_agent(user_agent),
+        None => reqwest::Client::builder(),
+    }
+}
+
+/// Check that Deezer can be reached through the proxy, so a wrong one is reported right
+/// away instead of every download failing.
+async fn check_proxy(progress_tx: Sender<DownloadProgress>) {
+    let result = http_client()
+        .get("https://api.deezer.com/infos")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    if let Err(err) = result {
+        progress_tx.report(DownloadProgress::ProxyError(err.to_string()));
+    }
+}
+
+/// Open and close the download window as time passes.
+async fn update_download_window(window: DownloadWindow, window_open: Arc<watch::Sender<bool>>) {
+    loop {
+        window_open.send_replace(window.contains(Local::now().time()));
+        tokio::time::sleep(DOWNLOAD_WINDOW_CHECK_INTERVAL).await;
+    }
+}
+
+/// Open the media session of a worker, retrying with an increasing delay as it fails
+/// when there is no network yet.
+async fn init_session(arl: Option<&str>) -> Result<MediaSession> {
+    let mut delay = WORKER_INIT_DELAY;
+
+    for _ in 1..WORKER_INIT_ATTEMPTS {
+        if let Ok(session) = MediaSession::new(arl).await {
+            return Ok(session);
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+
+    MediaSession::new(arl)
+        .await
+        .map_err(|err| eyre!("Unable to initialize a download worker: {}", err))
+}
+
+async fn download_album_by_barcode(
+    upc: String,
+    source: impl TrackSource,
+    options: RequestOptions,
+    groups: SharedGroups,
+    requested_albums: Option<RequestedAlbums>,
+    progress_tx: Sender<DownloadProgress>,
+    queue: RequestQueue,
+) {
+    // Deezer stores some 12 digit UPCs as their 13 digit EAN equivalent, and the other way around
+    let mut candidates = vec![upc.clone()];
+    match upc.len() {
+        12 => candidates.push(format!("0{}", upc)),
+        13 if upc.starts_with('0') => candidates.push(upc[1..].to_string()),
+        _ => {}
+    }
+
+    for candidate in candidates {
+        if let Ok(Some(id)) = source.album_id_by_upc(&candidate).await {
+            download_album(
+                id,
+                source,
+                options,
+                groups,
+                requested_albums,
+                progress_tx,
+                queue,
+            )
+            .await;
+            return;
+        }
+    }
+
+    progress_tx.report(DownloadProgress::BarcodeNotFoundError(upc));
+}
+
+async fn download_short_link(
+    link: String,
+    source: impl TrackSource,
+    options: RequestOptions,
+    groups: SharedGroups,
+    playlist_sync: Option<Arc<PlaylistSync>>,
+    progress_tx: Sender<DownloadProgress>,
+    queue: RequestQueue,
+) {
+    // The redirects are followed by the client, the link they end on says what it is
+    let target = match http_client().get(&link).send().await {
+        Ok(response) => parse_deezer_input(response.url().as_str()),
+        Err(_) => None,
+    };
+
+    match target {
+        Some((LinkKind::Track, id)) => {
+            download_song(id, source, options, groups, progress_tx, queue).await
+        }
+        Some((LinkKind::Album, id)) => {
+            let requested_albums = groups.lock().unwrap().requested_albums.clone();
+            download_album(
+                id,
+                source,
+                options,
+                groups,
+                requested_albums,
+                progress_tx,
+                queue,
+            )
+            .await
+        }
+        Some((LinkKind::Playlist, id)) => {
+            download_playlist(
+                id,
+                source,
+                options,
+                groups,
+                playlist_sync,
+                progress_tx,
+                queue,
+            )
+            .await
+        }
+        None => progress_tx.report(DownloadProgress::ShortLinkError(link)),
+    }
+}
+
+/// What a Deezer link points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Track,
+    Album,
+    Playlist,
+}
+
+impl LinkKind {
+    /// Request for what the link points to.
+    pub fn request(self, id: Id) -> DownloadRequest {
+        match self {
+            LinkKind::Track => DownloadRequest::Song(id),
+            LinkKind::Album => DownloadRequest::Album(id),
+            LinkKind::Playlist => DownloadRequest::Playlist(id),
+        }
+    }
+}
+
+/// Parse a Deezer link as in `https://www.deezer.com/fr/track/3135556?utm_source=x`, with
+/// or without its scheme, `www` or language. Anything else, raw ids and short links
+/// included, is `None`.
+pub fn parse_deezer_input(input: &str) -> Option<(LinkKind, Id)> {
+    let input = input.trim();
+    let link = input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))
+        .unwrap_or(input);
+    let link = link.split(['?', '#']).next()?;
+    let mut segments = link.split('/').filter(|x| !x.is_empty());
+    if !matches!(segments.next()?, "deezer.com" | "www.deezer.com") {
+        return None;
+    }
+
+    let (kind, id) = match segments.collect::<Vec<_>>()[..] {
+        [kind, id] | [_, kind, id] => (kind, id),
+        _ => return None,
+    };
+    let kind = match kind {
+        "track" => LinkKind::Track,
+        "album" => LinkKind::Album,
+        "playlist" => LinkKind::Playlist,
+        _ => return None,
+    };
+    Some((kind, id.parse().ok()?))
+}
+
+/// Whether the input is a short link shared from Deezer, as in
+/// `https://deezer.page.link/XYZ` or `https://link.deezer.com/s/XYZ`, to be followed with
+/// a [DownloadRequest::ShortLink].
+pub fn is_short_link(input: &str) -> bool {
+    let input = input.trim();
+    let link = input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))
+        .unwrap_or(input);
+    match link.split_once('/') {
+        Some((host, path)) => {
+            matches!(host, "deezer.page.link" | "link.deezer.com")
+                && !path.trim_matches('/').is_empty()
+                && !input.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Parse an album id, optionally followed by the positions of the tracks to download as
+/// in `12345:3-7` or `12345:3-`.
+pub fn parse_album_request(input: &str) -> Option<DownloadRequest> {
+    match input.split_once(':') {
+        Some((id, range)) => Some(DownloadRequest::AlbumTracks(
+            id.parse().ok()?,
+            range.parse().ok()?,
+        )),
+        None => input.parse().ok().map(DownloadRequest::Album),
+    }
+}
+
+/// Whether some input looks like a UPC/EAN barcode.
+pub fn is_barcode(input: &str) -> bool {
+    matches!(input.len(), 8 | 12 | 13) && input.chars().all(|x| x.is_ascii_digit())
+}
+
+/// Run a lookup again while it finds nothing, at most `retries` times.
+async fn retry_not_found<T, F, Fut>(
+    id: Id,
+    retries: u8,
+    progress_tx: &Sender<DownloadProgress>,
+    lookup: F,
+) -> Result<Option<T>>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Option<T>>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match lookup().await {
+            Ok(None) if attempt < retries => {
+                attempt += 1;
+                progress_tx.report(DownloadProgress::NotFoundRetry(id, attempt));
+                tokio::time::sleep(NOT_FOUND_RETRY_DELAY * attempt as u32).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Send the final progress of an item, followed by the summary of its group if it was
+/// the last one of it.
+fn send_outcome(
+    item: &DownloadItem,
+    progress: DownloadProgress,
+    groups: &SharedGroups,
+    progress_tx: &Sender<DownloadProgress>,
+) -> Option<Vec<(DownloadItem, PathBuf)>> {
+    let group = groups.lock().unwrap().record(item, &progress);
+
+    progress_tx.report(progress);
+    let group = group?;
+    progress_tx.report(DownloadProgress::GroupFinished(group.summary));
+    Some(group.written)
+}
+
+/// Get the album artist of an album's tracks: their artist if they all share the same,
+/// the album's main artist otherwise, or "Various Artists" if it doesn't have one.
+fn album_artist(main_artist: &str, tracks: &[Track]) -> String {
+    match tracks.split_first() {
+        Some((first, rest)) if rest.iter().all(|x| x.artist.id == first.artist.id) => {
+            first.artist.name.clone()
+        }
+        _ if !main_artist.is_empty() => main_artist.to_string(),
+        _ => String::from("Various Artists"),
+    }
+}
+
+/// Download a song and write it to the download directory, returning the written file.
+async fn download_song_from_item(
+    item: &DownloadItem,
+    session: &MediaSession,
+    download_dir: Option<&Path>,
+    config: &Config,
+    cover_cache: &Mutex<CoverCache>,
+    progress_tx: &Sender<DownloadProgress>,
+) -> Result<Downloaded, DownloadError> {
+    let track = &item.track;
+    let id = track.id;
+    if let Some(market) = &config.market {
+        if !is_available_in(track, market) {
+            return Err(DownloadError::UnavailableInMarket(market.to_uppercase()));
+        }
+    }
+
+    let requested = config.quality.for_request(item.kind);
+    let mut qualities =
+        quality_attempts(requested, &config.fallback_chain, session.qualities()).into_iter();
+    let mut quality = qualities.next().ok_or(DownloadError::Unavailable)?;
+    if quality != requested {
+        progress_tx.report(DownloadProgress::QualityDowngrade(
+            track.clone(),
+            requested,
+            quality,
+        ));
+    }
+    let metadata = metadata_from_track(track, config.cover_size, config.featured_artists);
+    let existing = existing_path(&metadata, item, quality, download_dir, config);
+    let mut upgraded = None;
+    let mut upgraded_kbps = None;
+    if config.upgrade_existing {
+        if let Some(existing_kbps) = existing.as_deref().and_then(audio_bitrate) {
+            if existing_kbps >= quality.kbps() {
+                return Ok(Downloaded::Skipped(SkipReason::NotAnUpgrade(existing_kbps)));
+            }
+            progress_tx.report(DownloadProgress::Upgrade(
+                track.clone(),
+                existing_kbps,
+                quality,
+            ));
+            upgraded = existing.clone();
+            upgraded_kbps = Some(existing_kbps);
+        }
+    }
+    // Only the tags can differ from a file downloaded from the same track
+    let same_track = existing.filter(|path| {
+        upgraded.is_none()
+            && config.same_track_existing != SameTrackExisting::Redownload
+            && read_song_tag(path).is_ok_and(|tag| tagged_track_id(&tag) == Some(id))
+    });
+    if let Some(path) = same_track {
+        match config.same_track_existing {
+            SameTrackExisting::Redownload => {}
+            SameTrackExisting::UpdateTags => {
+                update_tags(&path, &metadata, item, config)
+                    .map_err(|err| DownloadError::Tags(err.to_string()))?;
+                return Ok(Downloaded::Skipped(SkipReason::TagsUpdated));
+            }
+            SameTrackExisting::Skip => return Ok(Downloaded::Skipped(SkipReason::SameTrack)),
+        }
+    }
+
+    let download_dir = download_dir.ok_or(DownloadError::NoDownloadDir)?;
+    let mut part = part_path(download_dir, item, quality, config);
+    // Lossless is sometimes listed without being downloadable, which isn't known before
+    // trying, so a failed download is tried again in the next quality of the chain
+    while let Err(err) = fetch_song(session, track, quality, &part, progress_tx).await {
+        let lower = qualities.next().filter(|_| is_quality_failure(&err));
+        // Kept to be resumed when the song is downloaded again, unless it won't be needed
+        if lower.is_some() || !is_resumable(&err) {
+            let _ = std::fs::remove_file(&part);
+        }
+        let Some(lower) = lower else {
+            return Err(err);
+        };
+        // Not worth replacing the existing file anymore
+        if let Some(existing_kbps) = upgraded_kbps.filter(|x| *x >= lower.kbps()) {
+            return Ok(Downloaded::Skipped(SkipReason::NotAnUpgrade(existing_kbps)));
+        }
+        progress_tx.report(DownloadProgress::QualityRetry(
+            track.clone(),
+            quality,
+            lower,
+            err,
+        ));
+        quality = lower;
+        part = part_path(download_dir, item, quality, config);
+    }
+
+    let (cover, disk_path) = {
+        let mut cover_cache = cover_cache.lock().unwrap();
+        let album = track.album.id;
+        (cover_cache.get(album), cover_cache.disk_path(album))
+    };
+    let cover = cover
+        .get_or_init(|| album_cover(&metadata.cover, config.cover_resolution, disk_path))
+        .await;
+    let mut tag = song_tag(&metadata, cover.as_deref().map(Vec::as_slice));
+    tag_song(&mut tag, item, config);
+    // Songs are downloaded without them when they can't be fetched
+    let lyrics = match config.lyrics {
+        LyricsOutput::Off => None,
+        _ => session.lyrics(id).await.ok().flatten(),
+    };
+    let lrc = lyrics.as_ref().and_then(|x| lrc(x, &metadata));
+    if let Some(lyrics) = &lyrics {
+        let text = match (config.lyrics, &lrc) {
+            (LyricsOutput::Embed, Some(lrc)) => lrc.clone(),
+            _ => lyrics.text.clone(),
+        };
+        if !text.trim().is_empty() {
+            tag.add_lyrics(Lyrics {
+                lang: String::from("eng"),
+                description: String::new(),
+                text,
+            });
+        }
+    }
+
+    let mut path = song_path(download_dir, item, &tag, quality, config)?;
+    if config.ask_on_collision {
+        while let Some(existing) = find_collision(&path) {
+            let (prompt, choice_rx) = CollisionPrompt::new();
+            // Shown so that a better existing file isn't replaced by mistake
+            let existing_size = std::fs::metadata(&existing).map_or(0, |x| x.len());
+            let new_size = std::fs::metadata(&part).map_or(0, |x| x.len());
+            progress_tx.report(DownloadProgress::Collision(
+                track.clone(),
+                path.clone(),
+                existing.clone(),
+                FileInfo::new(existing_size, track.duration),
+                FileInfo::new(new_size, track.duration),
+                prompt,
+            ));
+
+            // Nobody is left to answer once the app quits, the existing file is then kept
+            match choice_rx.await.unwrap_or(CollisionChoice::Skip) {
+                CollisionChoice::Overwrite => {
+                    path = existing;
+                    break;
+                }
+                CollisionChoice::Skip => {
+                    let _ = std::fs::remove_file(&part);
+                    return Ok(Downloaded::Skipped(SkipReason::FileNameTaken));
+                }
+                CollisionChoice::Rename(name) => {
+                    path = path.with_file_name(replace_illegal_characters(&name))
+                }
+            }
+        }
+    }
+
+    write_song_to_file(part, tag, item, &path, config, progress_tx).await?;
+    // An upgrade in another format is written next to the song it replaces
+    if let Some(upgraded) = upgraded.filter(|x| *x != path) {
+        let _ = std::fs::remove_file(upgraded);
+    }
+
+    // The song is there even without its cover, so this doesn't fail the download.
+    // Discs of an album share the cover, it goes to the album's directory.
+    if let Some(sidecar) = &config.sidecar_cover {
+        let album_dir = album_dir(download_dir, item, config);
+        if let Err(err) = write_sidecar_cover(item, sidecar, &album_dir).await {
+            progress_tx.report(DownloadProgress::SidecarCoverError(
+                track.clone(),
+                err.to_string(),
+            ));
+        }
+    }
+
+    if let Some(lrc) = lrc.filter(|_| config.lyrics == LyricsOutput::Sidecar) {
+        if let Err(err) = std::fs::write(path.with_extension("lrc"), lrc) {
+            progress_tx.report(DownloadProgress::LyricsError(
+                track.clone(),
+                err.to_string(),
+            ));
+        }
+    }
+
+    Ok(Downloaded::Written(path))
+}
+
+/// Synchronized lyrics in the LRC format, `None` when there are none.
+fn lrc(lyrics: &SongLyrics, metadata: &SongMetadata) -> Option<String> {
+    if lyrics.synced.is_empty() {
+        return None;
+    }
+
+    let mut lrc = format!(
+        "[ar:{}]\n[ti:{}]\n[al:{}]\n",
+        metadata.artist, metadata.title, metadata.album
+    );
+    for line in &lyrics.synced {
+        lrc.push_str(&format!("{}{}\n", line.timestamp, line.line));
+    }
+    Some(lrc)
+}
+
+/// Download the media of a track in a quality to its part file, decrypting it and
+/// reporting the progress as the bytes arrive. A part file left by an interrupted
+/// download is resumed from its last full chunk when the server allows it.
+async fn fetch_song(
+    session: &MediaSession,
+    track: &Track,
+    quality: Quality,
+    part_path: &Path,
+    progress_tx: &Sender<DownloadProgress>,
+) -> Result<(), DownloadError> {
+    let track_token = session
+        .track_token(track.id)
+        .await
+        .map_err(|err| download_error(&*err))?;
+    let url = session
+        .media_url(&track_token, quality)
+        .await
+        .map_err(|err| download_error(&*err))?
+        .ok_or(DownloadError::Unavailable)?;
+
+    // Chunks are decrypted on their own, so the download can only resume at one
+    let part_len = tokio::fs::metadata(part_path).await.map_or(0, |x| x.len());
+    let mut offset = part_len / CHUNK_SIZE as u64 * CHUNK_SIZE as u64;
+    let mut response = request_media(&url, offset).await?;
+    let resumed = response.status() == reqwest::StatusCode::PARTIAL_CONTENT
+        && content_range_total(&response).is_some_and(|total| part_len < total);
+    if offset > 0 && !resumed {
+        // Ranges aren't supported, or the part file is longer than the song: it was
+        // complete, maybe already tagged, and only a new download can be trusted
+        if response.status() != reqwest::StatusCode::OK {
+            response = request_media(&url, 0).await?;
+        }
+        offset = 0;
+    }
+    let mut response = response
+        .error_for_status()
+        .map_err(|err| download_error(&err))?;
+
+    if let Some(dir) = part_path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|err| DownloadError::Io("unable to create the directory", err))?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(offset == 0)
+        .open(part_path)
+        .await
+        .map_err(|err| DownloadError::Io("unable to write the file", err))?;
+    file.set_len(offset)
+        .await
+        .map_err(|err| DownloadError::Io("unable to write the file", err))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|err| DownloadError::Io("unable to write the file", err))?;
+
+    let total = response
+        .content_length()
+        .map(|x| x + offset)
+        .filter(|x| *x > 0);
+    let mut decryptor = Decryptor::new(track.id, (offset / CHUNK_SIZE as u64) as usize);
+    let (mut fetched, mut percent) = (offset, None);
+    while let Some(bytes) = response.chunk().await.map_err(|err| download_error(&err))? {
+        file.write_all(&decryptor.update(&bytes))
+            .await
+            .map_err(|err| DownloadError::Io("unable to write the file", err))?;
+        fetched += bytes.len() as u64;
+
+        let done = total.map(|total| (fetched * 100 / total).min(100) as u8);
+        if let Some(done) = done.filter(|x| Some(*x) != percent) {
+            percent = Some(done);
+            progress_tx.report(DownloadProgress::Progress(track.clone(), done));
+        }
+    }
+    file.write_all(&decryptor.finish())
+        .await
+        .map_err(|err| DownloadError::Io("unable to write the file", err))?;
+    file.flush()
+        .await
+        .map_err(|err| DownloadError::Io("unable to write the file", err))
+}
+
+/// Request the media of a song from a byte offset, the whole of it at 0.
+async fn request_media(url: &str, offset: u64) -> Result<reqwest::Response, DownloadError> {
+    let mut request = http_client().get(url);
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    }
+    request.send().await.map_err(|err| download_error(&err))
+}
+
+/// Size of the whole media of a partial response, from its `Content-Range` header like
+/// `bytes 2048-9999/10000`.
+fn content_range_total(response: &reqwest::Response) -> Option<u64> {
+    let range = response.headers().get(reqwest::header::CONTENT_RANGE)?;
+    parse_content_range_total(range.to_str().ok()?)
+}
+
+fn parse_content_range_total(range: &str) -> Option<u64> {
+    let (_, total) = range.strip_prefix("bytes ")?.split_once('/')?;
+    total.trim().parse().ok()
+}
+
+/// Whether a failed download can be resumed from its part file when tried again.
+fn is_resumable(err: &DownloadError) -> bool {
+    matches!(err, DownloadError::Network(_) | DownloadError::Timeout)
+}
+
+/// Get the cover of an album, resized to a resolution if Deezer serves it, the cover at
+/// the URL's size being kept otherwise. It is read from or written to a file when kept
+/// on disk. `None` if it can't be fetched, the songs being tagged without it then.
+async fn album_cover(
+    url: &str,
+    resolution: Option<u32>,
+    disk_path: Option<PathBuf>,
+) -> Option<Arc<Vec<u8>>> {
+    if let Some(cover) = disk_path.as_ref().and_then(|x| std::fs::read(x).ok()) {
+        return Some(Arc::new(cover));
+    }
+
+    let mut url = url.to_string();
+    if let Some(resolution) = resolution {
+        if let Some(resized) = resized_cover(&url, resolution).await {
+            url = resized;
+        }
+    }
+    let cover = fetch_cover(&url).await?;
+    // Only saves fetching it again, so this doesn't fail the download
+    if let Some(path) = disk_path {
+        let partial_path = path.with_extension("jpg.part");
+        let _ = std::fs::create_dir_all(path.parent().unwrap_or(&path))
+            .and_then(|_| std::fs::write(&partial_path, &cover))
+            .and_then(|_| std::fs::rename(&partial_path, &path));
+    }
+    Some(Arc::new(cover))
+}
+
+/// Get the image of a cover, `None` if it can't be fetched.
+async fn fetch_cover(url: &str) -> Option<Vec<u8>> {
+    if url.is_empty() {
+        return None;
+    }
+
+    let response = http_client().get(url).send().await.ok()?;
+    let cover = response.error_for_status().ok()?.bytes().await.ok()?;
+    Some(cover.to_vec())
+}
+
+/// Tag of a downloaded song, before what [tag_song] adds to it.
+fn song_tag(metadata: &SongMetadata, cover: Option<&[u8]>) -> Tag {
+    let mut tag = Tag::new();
+    tag.set_title(metadata.title.clone());
+    tag.set_artist(metadata.artist.clone());
+    tag.set_album(metadata.album.clone());
+    if let Some(cover) = cover {
+        tag.add_picture(Picture {
+            mime_type: String::from("image/jpeg"),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data: cover.to_vec(),
+        });
+    }
+    tag
+}
+
+/// Run the configured command for a downloaded song, reporting it if it fails. Workers
+/// don't wait for it to end.
+async fn run_post_download_command(
+    command: Vec<String>,
+    track: Track,
+    path: PathBuf,
+    progress_tx: Sender<DownloadProgress>,
+) {
+    let command = command
+        .iter()
+        .map(|x| fill_command_arg(x, &track, &path))
+        .collect::<Vec<_>>();
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+
+    // Its output is captured, it would be drawn over the TUI otherwise
+    let error = match tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => return,
+        Ok(output) => format!(
+            "{} ({})",
+            String::from_utf8_lossy(&output.stderr).trim(),
+            output.status
+        ),
+        Err(err) => format!("{}: {}", program, err),
+    };
+    progress_tx.report(DownloadProgress::PostDownloadCommandError(track, error));
+}
+
+/// Transcode a FLAC song to an MP3 copy next to it with ffmpeg, tagged like the song,
+/// reporting its progress. Workers don't wait for it to end.
+async fn write_mp3_copy(
+    track: Track,
+    path: PathBuf,
+    tag_version: TagVersion,
+    progress_tx: Sender<DownloadProgress>,
+) {
+    let mp3_path = path.with_extension("mp3");
+    let part_path = path.with_extension("mp3.part");
+    let mut child = match tokio::process::Command::new("ffmpeg")
+        .args(["-nostdin", "-y", "-nostats", "-loglevel", "error", "-i"])
+        .arg(&path)
+        // The tag is copied afterwards, ffmpeg would leave some of it out
+        .args(["-map", "0:a", "-map_metadata", "-1"])
+        .args(["-codec:a", "libmp3lame", "-b:a", "320k", "-f", "mp3"])
+        .args(["-progress", "pipe:1"])
+        .arg(&part_path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            progress_tx.report(DownloadProgress::Mp3CopySkipped(track));
+            return;
+        }
+        Err(err) => {
+            progress_tx.report(DownloadProgress::Mp3CopyError(track, err.to_string()));
+            return;
+        }
+    };
+
+    if let Some(stdout) = child.stdout.take() {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut last = None;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let percent = transcode_progress(&line, track.duration);
+            if percent.is_some() && percent != last {
+                last = percent;
+                progress_tx.report(DownloadProgress::Mp3CopyProgress(
+                    track.clone(),
+                    percent.unwrap_or_default(),
+                ));
+            }
+        }
+    }
+
+    let result = match child.wait_with_output().await {
+        Ok(output) if output.status.success() => read_song_tag(&path)
+            .and_then(|tag| write_song_tag(&part_path, &tag, tag_version))
+            .map_err(|err| err.to_string())
+            .and_then(|_| move_file(&part_path, &mp3_path).map_err(|err| err.to_string())),
+        Ok(output) => Err(format!(
+            "{} ({})",
+            String::from_utf8_lossy(&output.stderr).trim(),
+            output.status
+        )),
+        Err(err) => Err(err.to_string()),
+    };
+    match result {
+        Ok(()) => progress_tx.report(DownloadProgress::Mp3CopyFinish(track, mp3_path)),
+        Err(err) => {
+            let _ = std::fs::remove_file(&part_path);
+            progress_tx.report(DownloadProgress::Mp3CopyError(track, err));
+        }
+    }
+}
+
+/// Percentage of a transcode done from a line of the `-progress` output of ffmpeg, `None`
+/// for the other lines.
+fn transcode_progress(line: &str, duration_secs: u64) -> Option<u8> {
+    let micros = line
+        .strip_prefix("out_time_us=")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    let percent = micros.checked_div(duration_secs * 10_000)?;
+    Some(percent.min(100) as u8)
+}
+
+/// Argument of the post download command with the placeholders of a song replaced.
+fn fill_command_arg(arg: &str, track: &Track, path: &Path) -> String {
+    arg.replace("{path}", &path.to_string_lossy())
+        .replace("{artist}", &track.artist.name)
+        .replace("{title}", &track.title)
+}
+
+/// Save the album cover of a song in a directory, unless another song of the album
+/// already did.
+async fn write_sidecar_cover(
+    item: &DownloadItem,
+    sidecar: &SidecarCover,
+    dir: &Path,
+) -> Result<()> {
+    let path = dir.join(sidecar_cover_file_name(item, &sidecar.file_name));
+    if path.exists() {
+        return Ok(());
+    }
+
+    let mut url = cover_url(&item.track, sidecar.size);
+    if let Some(resolution) = sidecar.resolution {
+        if let Some(resized) = resized_cover(&url, resolution).await {
+            url = resized;
+        }
+    }
+    let cover = http_client()
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    // Written under another name first so that a cover being written is never mistaken
+    // for a saved one by another song of the album
+    let partial_path = path.with_extension(format!("{}.part", item.track.id));
+    std::fs::write(&partial_path, cover)?;
+    std::fs::rename(&partial_path, &path)?;
+    Ok(())
+}
+
+/// Album metadata file of a media server, for the written songs of an album.
+#[derive(Debug, Serialize)]
+struct AlbumMetadata<'a> {
+    title: &'a str,
+    artist: &'a str,
+    year: Option<&'a str>,
+    genre: Option<&'a str>,
+    /// File name of the sidecar cover, or URL of the cover when there is none.
+    cover: String,
+    tracks: Vec<AlbumMetadataTrack<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct AlbumMetadataTrack<'a> {
+    disc: u64,
+    position: u64,
+    title: &'a str,
+    artist: &'a str,
+    /// Duration in seconds.
+    duration: u64,
+    file: Option<&'a str>,
+}
+
+/// Write the metadata file of an album next to its songs, once all of them are done.
+/// Groups of songs that don't come from an album are left alone.
+fn write_album_metadata(
+    written: &[(DownloadItem, PathBuf)],
+    format: AlbumMetadataFormat,
+    config: &Config,
+) -> Result<()> {
+    let Some(((first, path), _)) = written.split_first() else {
+        return Ok(());
+    };
+    let (Some(mut dir), RequestKind::Album) = (path.parent(), first.kind) else {
+        return Ok(());
+    };
+    // The metadata of an album split by disc covers every disc
+    if album_folder(first, config).is_some() {
+        dir = dir.parent().unwrap_or(dir);
+    }
+
+    let cover = match &config.sidecar_cover {
+        Some(sidecar) => sidecar_cover_file_name(first, &sidecar.file_name),
+        None => cover_url(&first.track, config.cover_size),
+    };
+    let metadata = album_metadata(written, dir, cover);
+    let (file_name, content) = match format {
+        AlbumMetadataFormat::Json => ("album.json", serde_json::to_string_pretty(&metadata)?),
+        AlbumMetadataFormat::Nfo => ("album.nfo", album_nfo(&metadata)),
+    };
+    std::fs::write(dir.join(file_name), content)?;
+    Ok(())
+}
+
+/// Metadata of an album written to `dir`, its files named relative to it.
+fn album_metadata<'a>(
+    written: &'a [(DownloadItem, PathBuf)],
+    dir: &Path,
+    cover: String,
+) -> AlbumMetadata<'a> {
+    let first = &written[0].0;
+    let mut tracks = written
+        .iter()
+        .map(|(item, path)| AlbumMetadataTrack {
+            disc: item.track.disk_number,
+            position: item.track.track_position,
+            title: &item.track.title,
+            artist: &item.track.artist.name,
+            duration: item.track.duration,
+            file: path.strip_prefix(dir).ok().and_then(|x| x.to_str()),
+        })
+        .collect::<Vec<_>>();
+    tracks.sort_by_key(|x| (x.disc, x.position));
+
+    AlbumMetadata {
+        title: &first.track.album.title,
+        artist: first
+            .album_artist
+            .as_deref()
+            .unwrap_or(&first.track.artist.name),
+        year: first.track.release_date.get(..4),
+        genre: first.genre.as_deref(),
+        cover,
+        tracks,
+    }
+}
+
+/// Album metadata in the NFO format of Kodi, also read by Jellyfin.
+fn album_nfo(metadata: &AlbumMetadata) -> String {
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    nfo.push_str("<album>\n");
+    nfo.push_str(&xml_element(1, "title", metadata.title));
+    nfo.push_str(&xml_element(1, "artistdesc", metadata.artist));
+    if let Some(year) = metadata.year {
+        nfo.push_str(&xml_element(1, "year", year));
+    }
+    if let Some(genre) = metadata.genre {
+        nfo.push_str(&xml_element(1, "genre", genre));
+    }
+    nfo.push_str(&xml_element(1, "thumb", &metadata.cover));
+    for track in &metadata.tracks {
+        let duration = format!("{}:{:02}", track.duration / 60, track.duration % 60);
+        nfo.push_str("  <track>\n");
+        nfo.push_str(&xml_element(2, "position", &track.position.to_string()));
+        nfo.push_str(&xml_element(2, "title", track.title));
+        nfo.push_str(&xml_element(2, "duration", &duration));
+        nfo.push_str("  </track>\n");
+    }
+    nfo.push_str("</album>\n");
+    nfo
+}
+
+/// An XML element on its own line, indented by two spaces per level.
+fn xml_element(level: usize, name: &str, value: &str) -> String {
+    format!(
+        "{}<{}>{}</{}>\n",
+        "  ".repeat(level),
+        name,
+        escape_xml(value),
+        name
+    )
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn sidecar_cover_file_name(item: &DownloadItem, template: &str) -> String {
+    let artist = item
+        .album_artist
+        .as_deref()
+        .unwrap_or(&item.track.artist.name);
+    let name = template
+        .replace("{artist}", artist)
+        .replace("{album}", &item.track.album.title);
+    replace_illegal_characters(&name)
+}
+
+/// Whether a track is in the catalog of a country, assumed when Deezer doesn't say.
+fn is_available_in(track: &Track, market: &str) -> bool {
+    track.available_countries.is_empty()
+        || track
+            .available_countries
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(market))
+}
+
+/// What became of a song downloaded without error.
+enum Downloaded {
+    Written(PathBuf),
+    /// Nothing was written, for the given reason.
+    Skipped(SkipReason),
+}
+
+/// The file taking the name of `path` in its directory, ignoring case since some file
+/// systems do.
+fn find_collision(path: &Path) -> Option<PathBuf> {
+    if path.exists() {
+        return Some(path.to_path_buf());
+    }
+
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    std::fs::read_dir(path.parent()?)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|x| {
+            x.file_name()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| x.to_lowercase() == name)
+        })
+}
+
+/// Add to the tag of a downloaded song what [song_tag] doesn't write.
+fn tag_song(tag: &mut Tag, item: &DownloadItem, config: &Config) {
+    let track = &item.track;
+    tag_from_item(tag, item);
+    if config.featured_artists == FeaturedArtists::SeparateTag {
+        if let (_, Some(featured)) = split_featured_artists(&track.artist.name) {
+            tag.add_extended_text("FEATURED_ARTISTS", featured);
+        }
+    }
+    if config.tag_deezer_ids {
+        tag_deezer_ids(tag, track);
+    }
+    if config.tag_contributors {
+        tag_contributors(tag, track);
+    }
+    tag_release_date(tag, &track.release_date, config.release_date);
+    tag_static(tag, &config.static_tags);
+}
+
+/// Tag a song with the static tags of the config, after those of its track so that
+/// they take precedence.
+fn tag_static(tag: &mut Tag, static_tags: &BTreeMap<String, String>) {
+    for (name, value) in static_tags {
+        match name.as_str() {
+            "COMMENT" => tag.add_comment(Comment {
+                lang: String::from("eng"),
+                description: String::new(),
+                text: value.clone(),
+            }),
+            name if is_text_frame_id(name) => tag.set_text(name, value.clone()),
+            name => tag.add_extended_text(name, value.clone()),
+        }
+    }
+}
+
+/// Whether a name is the id of a standard ID3 text frame, like `TIT1`.
+pub(crate) fn is_text_frame_id(name: &str) -> bool {
+    name.len() == 4
+        && name.starts_with('T')
+        && name != "TXXX"
+        && name
+            .chars()
+            .all(|x| x.is_ascii_uppercase() || x.is_ascii_digit())
+}
+
+/// Write the tags a song would be downloaded with to its existing file, leaving the
+/// audio and the other frames, like the cover, as they are.
+fn update_tags(
+    path: &Path,
+    metadata: &SongMetadata,
+    item: &DownloadItem,
+    config: &Config,
+) -> Result<()> {
+    let mut tag = read_song_tag(path)?;
+    tag.set_artist(metadata.artist.clone());
+    tag.set_title(metadata.title.clone());
+    tag.set_album(metadata.album.clone());
+    tag_song(&mut tag, item, config);
+    write_song_tag(path, &tag, config.tag_version)
+}
+
+/// Read the tag of a song, from its Vorbis comments for FLAC files.
+pub(crate) fn read_song_tag(path: &Path) -> Result<Tag> {
+    if flac::is_flac(path) {
+        flac::read_tag(path)
+    } else {
+        Ok(Tag::read_from_path(path)?)
+    }
+}
+
+/// Write the tag of a song, as Vorbis comments for FLAC files.
+fn write_song_tag(path: &Path, tag: &Tag, tag_version: TagVersion) -> Result<()> {
+    if flac::is_flac(path) {
+        flac::write_tag(path, tag)
+    } else {
+        Ok(tag.write_to_path(path, tag_version.into())?)
+    }
+}
+
+/// Id of the track a song was downloaded from, if it was tagged with it.
+fn tagged_track_id(tag: &Tag) -> Option<Id> {
+    tag.extended_texts()
+        .find(|x| x.description == "DEEZER_TRACK_ID")
+        .and_then(|x| x.value.parse().ok())
+}
+
+/// File a song would be written to in a quality, if it already exists. A song written in
+/// another format is found as well, so that it can be upgraded.
+fn existing_path(
+    metadata: &SongMetadata,
+    item: &DownloadItem,
+    quality: Quality,
+    download_dir: Option<&Path>,
+    config: &Config,
+) -> Option<PathBuf> {
+    // Songs are tagged with the artist and title of their metadata once downloaded
+    let mut tag = Tag::new();
+    tag.set_artist(metadata.artist.clone());
+    tag.set_title(metadata.title.clone());
+
+    let song_dir = song_dir(download_dir?, item, config);
+    let template = config.file_name.for_request(item.kind);
+    [quality, Quality::Mp3_128, Quality::Flac]
+        .into_iter()
+        .map(|quality| {
+            song_dir.join(replace_illegal_characters(&song_file_name(
+                &tag,
+                &item.track,
+                template,
+                quality,
+                config.max_file_name_length,
+            )))
+        })
+        .find(|x| x.exists())
+}
+
+/// Subdirectory of the download directory named after the album of an item, only for
+/// albums of several discs split by disc so that albums don't share their disc folders.
+fn album_folder(item: &DownloadItem, config: &Config) -> Option<String> {
+    let (_, total_discs) = item.album_totals?;
+    let album = &item.track.album;
+    (config.disc_folders == DiscFolders::PerDisc && total_discs > 1).then(|| {
+        match replace_illegal_characters(&album.title).trim() {
+            "" => album.id.to_string(),
+            title => title.to_string(),
+        }
+    })
+}
+
+/// Directory the songs of an item's album share with its cover and metadata.
+fn album_dir(download_dir: &Path, item: &DownloadItem, config: &Config) -> PathBuf {
+    match album_folder(item, config) {
+        Some(folder) => download_dir.join(folder),
+        None => download_dir.to_path_buf(),
+    }
+}
+
+/// Directory the song of an item is written to, a `Disc N` subdirectory of its album's
+/// directory when split by disc.
+fn song_dir(download_dir: &Path, item: &DownloadItem, config: &Config) -> PathBuf {
+    let album_dir = album_dir(download_dir, item, config);
+    match album_folder(item, config) {
+        Some(_) => album_dir.join(format!("Disc {}", item.track.disk_number)),
+        None => album_dir,
+    }
+}
+
+/// Bitrate in kbps of an audio file, read from its first MP3 frame header. FLAC files
+/// are reported with the bitrate of uncompressed CD audio.
+pub(crate) fn audio_bitrate(path: &Path) -> Option<u32> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0; 10];
+    file.read_exact(&mut header).ok()?;
+    if &header[..4] == b"fLaC" {
+        return Some(Quality::Flac.kbps());
+    }
+
+    // Frames start after the ID3v2 tag, whose size is stored as 7 bits per byte
+    let mut offset = 0;
+    if &header[..3] == b"ID3" {
+        let size = header[6..]
+            .iter()
+            .fold(0, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        offset = 10 + size + footer;
+    }
+
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut frames = Vec::new();
+    file.take(MP3_FRAME_SEARCH_LEN)
+        .read_to_end(&mut frames)
+        .ok()?;
+    frames.windows(4).find_map(mp3_frame_bitrate)
+}
+
+/// Bitrate of an MPEG layer III frame, from its header.
+fn mp3_frame_bitrate(header: &[u8]) -> Option<u32> {
+    static MPEG1_BITRATES: [u32; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    static MPEG2_BITRATES: [u32; 15] =
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+    if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+
+    // Version 3 is MPEG 1, 1 is reserved, and layer 1 is layer III
+    let version = (header[1] >> 3) & 0b11;
+    let layer = (header[1] >> 1) & 0b11;
+    let index = (header[2] >> 4) as usize;
+    if layer != 1 || version == 1 || index == 0 || index == 15 {
+        return None;
+    }
+
+    let bitrates = if version == 3 {
+        &MPEG1_BITRATES
+    } else {
+        &MPEG2_BITRATES
+    };
+    Some(bitrates[index])
+}
+
+/// Pick the quality to download in: the requested one if available, the first
+/// available one of the fallback chain otherwise.
+pub(crate) fn select_quality(
+    requested: Quality,
+    fallback_chain: &[Quality],
+    available: &[Quality],
+) -> Option<Quality> {
+    quality_attempts(requested, fallback_chain, available)
+        .first()
+        .copied()
+}
+
+/// Qualities a song is tried in until one succeeds, from the requested one down the
+/// fallback chain, leaving out the unavailable ones.
+fn quality_attempts(
+    requested: Quality,
+    fallback_chain: &[Quality],
+    available: &[Quality],
+) -> Vec<Quality> {
+    let mut attempts = Vec::new();
+    for quality in std::iter::once(&requested).chain(fallback_chain) {
+        if available.contains(quality) && !attempts.contains(quality) {
+            attempts.push(*quality);
+        }
+    }
+    attempts
+}
+
+/// Whether a download failed in a way another quality may not, unlike failing to write
+/// the song.
+fn is_quality_failure(err: &DownloadError) -> bool {
+    matches!(
+        err,
+        DownloadError::Unavailable
+            | DownloadError::Network(_)
+            | DownloadError::Timeout
+            | DownloadError::Other(_)
+    )
+}
+
+/// Add what is known of the request a track comes from to its tag.
+fn tag_from_item(tag: &mut Tag, item: &DownloadItem) {
+    tag.set_track(item.track.track_position_in_album as u32);
+    tag.set_disc(item.track.disk_number as u32);
+    if let Some((total_tracks, total_discs)) = item.album_totals {
+        tag.set_total_tracks(total_tracks);
+        tag.set_total_discs(total_discs);
+    }
+    if let Some(album_artist) = &item.album_artist {
+        tag.set_album_artist(album_artist);
+    }
+    if let Some(genre) = &item.genre {
+        tag.set_genre(genre);
+    }
+    // Same frame as iTunes, which most library managers read
+    if item.track.explicit_lyrics {
+        tag.add_extended_text("ITUNESADVISORY", "1");
+    }
+}
+
+/// Tag a song with the Deezer ids it was downloaded from, so it can be traced back to them.
+fn tag_deezer_ids(tag: &mut Tag, track: &Track) {
+    tag.add_extended_text("DEEZER_TRACK_ID", track.id.to_string());
+    tag.add_extended_text("DEEZER_ALBUM_ID", track.album.id.to_string());
+    tag.add_extended_text("DEEZER_ARTIST_ID", track.artist.id.to_string());
+}
+
+/// Tag a song with the contributors of its track, which come with the track lookup so
+/// this needs no extra request.
+fn tag_contributors(tag: &mut Tag, track: &Track) {
+    let (composers, others): (Vec<_>, Vec<_>) = track
+        .contributors
+        .iter()
+        .partition(|x| x.role.eq_ignore_ascii_case("composer"));
+
+    if !composers.is_empty() {
+        let names = composers
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        tag.set_text("TCOM", names.join("/"));
+    }
+    if !others.is_empty() {
+        let credits = others
+            .iter()
+            .map(|x| format!("{} ({})", x.name, x.role))
+            .collect::<Vec<_>>();
+        tag.add_extended_text("CONTRIBUTORS", credits.join("; "));
+    }
+}
+
+/// Tag a song with the year or full date it was released on. Nothing is written for
+/// dates Deezer doesn't know.
+fn tag_release_date(tag: &mut Tag, release_date: &str, format: ReleaseDateTag) {
+    let Some(date) = parse_release_date(release_date) else {
+        return;
+    };
+
+    match format {
+        ReleaseDateTag::Year => tag.set_year(date.year),
+        ReleaseDateTag::FullDate => tag.set_date_recorded(date),
+    }
+}
+
+/// Parse a release date as given by Deezer, either a full `YYYY-MM-DD` date, a year with
+/// or without its month, or empty and `0000-00-00` when unknown.
+fn parse_release_date(release_date: &str) -> Option<Timestamp> {
+    let mut parts = release_date.trim().split('-');
+    let year = parts.next()?.parse().ok().filter(|&x| x > 0)?;
+    let mut part = || parts.next().and_then(|x| x.parse().ok()).filter(|&x| x > 0);
+    let month = part();
+    let day = month.and(part());
+
+    Some(Timestamp {
+        year,
+        month,
+        day,
+        hour: None,
+        minute: None,
+        second: None,
+    })
+}
+
+/// Tag the downloaded song of a part file and move it to its path.
+async fn write_song_to_file(
+    part_path: PathBuf,
+    tag: Tag,
+    item: &DownloadItem,
+    path: &Path,
+    config: &Config,
+    progress_tx: &Sender<DownloadProgress>,
+) -> Result<(), DownloadError> {
+    if let Ok(existing) = std::fs::metadata(path) {
+        let duration = item.track.duration;
+        let new_size = std::fs::metadata(&part_path).map_or(0, |x| x.len());
+        progress_tx.report(DownloadProgress::Overwrite(
+            item.track.clone(),
+            FileInfo::new(existing.len(), duration),
+            FileInfo::new(new_size, duration),
+        ));
+    }
+
+    let path = path.to_path_buf();
+    let tag_version = config.tag_version;
+    // Tagging a large lossless song takes a while, it would hold up the other tasks
+    tokio::task::spawn_blocking(move || write_song(&part_path, &tag, &path, tag_version))
+        .await
+        .map_err(|err| DownloadError::Other(err.to_string()))?
+}
+
+/// Path a song is written to in the download directory, creating the directories it goes
+/// in when they don't exist yet.
+fn song_path(
+    download_dir: &Path,
+    item: &DownloadItem,
+    tag: &Tag,
+    quality: Quality,
+    config: &Config,
+) -> Result<PathBuf, DownloadError> {
+    let song_dir = song_dir(download_dir, item, config);
+    std::fs::create_dir_all(&song_dir)
+        .map_err(|err| DownloadError::Io("unable to create the directory", err))?;
+    let template = config.file_name.for_request(item.kind);
+    Ok(song_dir.join(replace_illegal_characters(&song_file_name(
+        tag,
+        &item.track,
+        template,
+        quality,
+        config.max_file_name_length,
+    ))))
+}
+
+/// File a song is downloaded to in a quality before being tagged and moved to its path,
+/// in the configured temporary directory if there is one, next to the song otherwise.
+/// Named after the track, as songs of different albums can share their file name, and the
+/// quality, so that an interrupted download is only resumed in the same one.
+fn part_path(
+    download_dir: &Path,
+    item: &DownloadItem,
+    quality: Quality,
+    config: &Config,
+) -> PathBuf {
+    let dir = match &config.temp_dir {
+        Some(temp_dir) => temp_dir.clone(),
+        None => song_dir(download_dir, item, config),
+    };
+    dir.join(format!("{}-{}.part", item.track.id, quality.kbps()))
+}
+
+/// Name of the file of a song from a template, filled from its tag or the track when the
+/// tag is missing fields. Falls back to the track id so the name is never empty. Names
+/// longer than `max_len` bytes get their title cut short, then their end.
+fn song_file_name(
+    tag: &Tag,
+    track: &Track,
+    template: &str,
+    quality: Quality,
+    max_len: usize,
+) -> String {
+    static ELLIPSIS: &str = "…";
+    let extension = format!(".{}", quality.extension());
+
+    let field = |tag_value: Option<&str>, track_value: &str| {
+        [tag_value.unwrap_or_default(), track_value]
+            .into_iter()
+            .map(str::trim)
+            .find(|x| !x.is_empty())
+            .map(str::to_string)
+    };
+
+    let Some(title) = field(tag.title(), &track.title) else {
+        return format!("{}{}", track.id, extension);
+    };
+    let artist =
+        field(tag.artist(), &track.artist.name).unwrap_or_else(|| String::from("Unknown Artist"));
+
+    let fill = |title: &str| {
+        template
+            .replace("{artist}", &artist)
+            .replace("{title}", title)
+            .replace("{album}", &track.album.title)
+            .replace(
+                "{track_number}",
+                &format!("{:02}", track.track_position_in_album),
+            )
+            .replace("{disc_number}", &track.disk_number.to_string())
+            .replace("{id}", &track.id.to_string())
+            .trim()
+            .to_string()
+    };
+
+    let max_len = max_len.saturating_sub(extension.len());
+    let mut name = fill(&title);
+    if name.len() > max_len {
+        let title_len = title
+            .len()
+            .saturating_sub(name.len() - max_len + ELLIPSIS.len());
+        let short_title = truncate_at_char(&title, title_len);
+        name = fill(&format!("{}{}", short_title.trim_end(), ELLIPSIS));
+        // The rest of the template may be too long on its own
+        name = truncate_at_char(&name, max_len).trim_end().to_string();
+    }
+
+    format!("{}{}", name, extension)
+}
+
+/// Longest start of a string that fits in `max_len` bytes, without splitting a character.
+fn truncate_at_char(str: &str, max_len: usize) -> &str {
+    let mut end = max_len.min(str.len());
+    while !str.is_char_boundary(end) {
+        end -= 1;
+    }
+    &str[..end]
+}
+
+/// Blocking part of [write_song_to_file]. The part file is removed when it fails.
+fn write_song(
+    part_path: &Path,
+    tag: &Tag,
+    path: &Path,
+    tag_version: TagVersion,
+) -> Result<(), DownloadError> {
+    let result = write_song_tag(part_path, tag, tag_version)
+        .map_err(|err| DownloadError::Tags(err.to_string()))
+        .and_then(|_| {
+            move_file(part_path, path)
+                .map_err(|err| DownloadError::Io("unable to move the file", err))
+        });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(part_path);
+    }
+    result
+}
+
+/// The user's download directory, if there is one.
+fn default_download_dir() -> Option<PathBuf> {
+    UserDirs::new()?.download_dir().map(Path::to_path_buf)
+}
+
+/// Check that songs can be written to a directory.
+fn check_writable_dir(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Err(eyre!("{} is not an existing directory.", dir.display()));
+    }
+
+    let probe = dir.join(".deeznuts-downloader-write-test");
+    File::create(&probe).map_err(|_| eyre!("{} is not writable.", dir.display()))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Move a file, copying it when it can't simply be renamed (e.g. across filesystems).
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    std::fs::copy(from, to)?;
+    File::open(to)?.sync_all()?;
+    std::fs::remove_file(from)
+}
+
+/// Replaces illegal characters for a Windows file.
+fn replace_illegal_characters(str: &str) -> String {
+    static ILLEGAL_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+    str.chars()
+        .filter(|char| !ILLEGAL_CHARACTERS.contains(char))
+        .collect()
+}
+
+/// Get the url of the album cover closest to the requested size that Deezer provides.
+fn cover_url(track: &Track, cover_size: CoverSize) -> String {
+    cover_size
+        .fallback_order()
+        .into_iter()
+        .map(|size| match size {
+            CoverSize::Small => &track.album.cover_small,
+            CoverSize::Medium => &track.album.cover_medium,
+            CoverSize::Big => &track.album.cover_big,
+        })
+        .find(|url| !url.is_empty())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Rewrite a Deezer cover URL, like `.../500x500-000000-80-0-0.jpg`, to request the
+/// cover at another size.
+fn resize_cover_url(url: &str, size: u32) -> Option<String> {
+    let (base, file) = url.rsplit_once('/')?;
+    let (dimensions, rest) = file.split_once('-')?;
+    let (width, height) = dimensions.split_once('x')?;
+    if width.parse::<u32>().is_err() || height.parse::<u32>().is_err() {
+        return None;
+    }
+
+    Some(format!("{}/{}x{}-{}", base, size, size, rest))
+}
+
+/// Get the URL of the cover at the given size, if Deezer serves it.
+async fn resized_cover(url: &str, size: u32) -> Option<String> {
+    let resized = resize_cover_url(url, size)?;
+    let response = http_client().head(&resized).send().await.ok()?;
+    let is_image = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.starts_with("image/"));
+
+    (response.status().is_success() && is_image).then_some(resized)
+}
+
+/// Split an artist name like "Main feat. Other" into the main and the featured artists.
+fn split_featured_artists(artist: &str) -> (&str, Option<&str>) {
+    static SEPARATORS: [&str; 6] = [
+        " (feat. ",
+        " (ft. ",
+        " (featuring ",
+        " feat. ",
+        " ft. ",
+        " featuring ",
+    ];
+
+    // ASCII lowercase keeps byte positions the same as in the original name
+    let lowercase = artist.to_ascii_lowercase();
+    let separator = SEPARATORS
+        .iter()
+        .filter_map(|separator| lowercase.find(separator).map(|pos| (pos, separator.len())))
+        .min();
+
+    match separator {
+        Some((pos, len)) => {
+            let featured = artist[pos + len..].trim_end_matches(')').trim();
+            (artist[..pos].trim(), Some(featured))
+        }
+        None => (artist, None),
+    }
+}
+
+/// What a song is tagged and named with, from its track.
+#[derive(Debug, Clone)]
+struct SongMetadata {
+    title: String,
+    artist: String,
+    album: String,
+    /// URL of the cover embedded in the song, in the configured size.
+    cover: String,
+}
+
+fn metadata_from_track(
+    track: &Track,
+    cover_size: CoverSize,
+    featured_artists: FeaturedArtists,
+) -> SongMetadata {
+    let (artist, title) = match (featured_artists, split_featured_artists(&track.artist.name)) {
+        (FeaturedArtists::SeparateTag, (main, Some(_))) => (main.to_string(), track.title.clone()),
+        (FeaturedArtists::InTitle, (main, Some(featured))) => (
+            main.to_string(),
+            format!("{} (feat. {})", track.title, featured),
+        ),
+        _ => (track.artist.name.clone(), track.title.clone()),
+    };
+
+    SongMetadata {
+        title,
+        artist,
+        album: track.album.title.clone(),
+        cover: cover_url(track, cover_size),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::media::SyncedLine;
+
+    /// Queue of a request that is never cancelled.
+    impl From<async_channel::Sender<QueuedItem>> for RequestQueue {
+        fn from(download_tx: async_channel::Sender<QueuedItem>) -> Self {
+            RequestQueue {
+                download_tx,
+                cancellation_token: CancellationToken::new(),
+                current_token: Arc::default(),
+            }
+        }
+    }
+
+    /// Tracks queued so far, in order.
+    fn queued(download_rx: &async_channel::Receiver<QueuedItem>) -> Vec<DownloadItem> {
+        std::iter::from_fn(|| download_rx.try_recv().ok())
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    #[derive(Default)]
+    struct MockSource {
+        tracks: HashMap<Id, Track>,
+        albums: HashMap<Id, AlbumInfo>,
+        barcodes: HashMap<String, Id>,
+        playlists: HashMap<Id, PlaylistInfo>,
+        /// Number of lookups reporting a track as missing before it is found.
+        misses: Mutex<u8>,
+    }
+
+    impl MockSource {
+        fn with_track(mut self, track: Track) -> Self {
+            self.tracks.insert(track.id, track);
+            self
+        }
+
+        fn with_album(mut self, id: Id, tracks: Vec<Track>) -> Self {
+            let album = AlbumInfo {
+                title: String::from("Album"),
+                artist: String::from("Artist"),
+                genre: Some(String::from("Pop")),
+                track_ids: tracks.iter().map(|x| x.id).collect(),
+            };
+            self.albums.insert(id, album);
+            tracks
+                .into_iter()
+                .fold(self, |source, track| source.with_track(track))
+        }
+
+        fn with_playlist(mut self, id: Id, track_ids: Vec<Id>) -> Self {
+            let playlist = PlaylistInfo {
+                title: String::from("Playlist"),
+                track_ids,
+            };
+            self.playlists.insert(id, playlist);
+            self
+        }
+    }
+
+    #[async_trait]
+    impl TrackSource for MockSource {
+        async fn track(&self, id: Id) -> Result<Option<Track>> {
+            let mut misses = self.misses.lock().unwrap();
+            if *misses > 0 {
+                *misses -= 1;
+                return Ok(None);
+            }
+
+            Ok(self.tracks.get(&id).cloned())
+        }
+
+        async fn album_info(&self, id: Id) -> Result<Option<AlbumInfo>> {
+            Ok(self.albums.get(&id).cloned())
+        }
+
+        async fn album_id_by_upc(&self, upc: &str) -> Result<Option<Id>> {
+            Ok(self.barcodes.get(upc).copied())
+        }
+
+        async fn playlist_info(&self, id: Id) -> Result<Option<PlaylistInfo>> {
+            Ok(self.playlists.get(&id).cloned())
+        }
+    }
+
+    /// Build a [Track] the same way the Deezer API would return it.
+    pub(crate) fn track(id: Id, track_position: u64, disk_number: u64) -> Track {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "readable": true,
+            "title": format!("Title {}", id),
+            "title_short": format!("Title {}", id),
+            "title_version": "",
+            "isrc": "",
+            "link": format!("https://www.deezer.com/track/{}", id),
+            "share": format!("https://www.deezer.com/track/{}", id),
+            "duration": 180,
+            "track_position": track_position,
+            "disk_number": disk_number,
+            "rank": 0,
+            "release_date": "2001-03-07",
+            "explicit_lyrics": false,
+            "explicit_content_lyrics": 0,
+            "explicit_content_cover": 0,
+            "preview": "",
+            "bpm": 0.0,
+            "gain": 0.0,
+            "available_countries": [],
+            "contributors": [],
+            "md5_image": "",
+            "artist": {
+                "id": 27,
+                "name": "Artist",
+                "link": "https://www.deezer.com/artist/27",
+                "share": "https://www.deezer.com/artist/27",
+                "picture": "",
+                "picture_small": "",
+                "picture_medium": "",
+                "picture_big": "",
+                "picture_xl": "",
+                "radio": true,
+                "tracklist": "",
+                "type": "artist"
+            },
+            "album": {
+                "id": 302127,
+                "title": "Album",
+                "link": "https://www.deezer.com/album/302127",
+                "cover": "",
+                "cover_small": "",
+                "cover_medium": "",
+                "cover_big": "",
+                "cover_xl": "",
+                "md5_image": "",
+                "release_date": "2001-03-07",
+                "tracklist": "",
+                "type": "album"
+            },
+            "type": "track"
+        }))
+        .expect("Track should be deserializable.")
+    }
+
+    #[tokio::test]
+    async fn album_tracks_are_indexed_across_discs() {
+        let source = MockSource::default()
+            .with_album(1, vec![track(10, 1, 1), track(11, 2, 1), track(12, 1, 2)]);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_album(
+            1,
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        let mut positions = queued(&download_rx)
+            .into_iter()
+            .map(|x| (x.track.id, x.track.track_position_in_album))
+            .collect::<Vec<_>>();
+        positions.sort();
+        assert_eq!(vec![(10, 1), (11, 2), (12, 3)], positions);
+
+        let queued = progress_rx
+            .try_iter()
+            .filter(|x| matches!(x, DownloadProgress::Queue(_)))
+            .count();
+        assert_eq!(3, queued);
+    }
+
+    #[tokio::test]
+    async fn album_tracks_are_tagged_with_totals() {
+        let source = MockSource::default()
+            .with_album(1, vec![track(10, 1, 1), track(11, 2, 1), track(12, 1, 2)]);
+        let (progress_tx, _progress_rx) = unbounded();
+
+        let (_, items) = resolve_album(1, &source, 0, &progress_tx).await.unwrap();
+        let mut tag = Tag::new();
+        tag_from_item(&mut tag, &items[2]);
+
+        assert_eq!(
+            (Some(3), Some(3), Some(2), Some(2)),
+            (
+                tag.track(),
+                tag.total_tracks(),
+                tag.disc(),
+                tag.total_discs()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_album_tracks_are_skipped() {
+        let mut source = MockSource::default()
+            .with_album(1, vec![track(10, 1, 1), track(11, 2, 1), track(12, 3, 1)]);
+        source.tracks.remove(&11);
+        let (progress_tx, progress_rx) = unbounded();
+
+        let (_, items) = resolve_album(1, &source, 0, &progress_tx).await.unwrap();
+
+        let positions = items
+            .iter()
+            .map(|x| (x.track.id, x.track.track_position_in_album))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(10, 1), (12, 3)], positions);
+        assert_eq!(Some((3, 1)), items[0].album_totals);
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::AlbumTrackNotFoundError(1, 11))
+        ));
+    }
+
+    #[tokio::test]
+    async fn explicit_tracks_are_flagged() {
+        let mut explicit = track(10, 1, 1);
+        explicit.explicit_lyrics = true;
+        let source = MockSource::default().with_album(1, vec![explicit, track(11, 2, 1)]);
+        let (progress_tx, _progress_rx) = unbounded();
+
+        let (_, items) = resolve_album(1, &source, 0, &progress_tx).await.unwrap();
+        let advisories = items
+            .iter()
+            .map(|item| {
+                let mut tag = Tag::new();
+                tag_from_item(&mut tag, item);
+                assert_eq!(Some("Pop"), tag.genre());
+                let explicit = tag
+                    .extended_texts()
+                    .any(|x| x.description == "ITUNESADVISORY" && x.value == "1");
+                explicit
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![true, false], advisories);
+    }
+
+    #[test]
+    fn classifies_download_errors() {
+        let other = std::io::Error::other("unexpected response");
+
+        assert!(
+            matches!(download_error(&other), DownloadError::Other(x) if x == "unexpected response")
+        );
+    }
+
+    #[test]
+    fn tags_deezer_ids() {
+        let mut tag = Tag::new();
+        tag_deezer_ids(&mut tag, &track(10, 1, 1));
+
+        let ids = tag
+            .extended_texts()
+            .map(|x| (x.description.as_str(), x.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("DEEZER_TRACK_ID", "10"),
+                ("DEEZER_ALBUM_ID", "302127"),
+                ("DEEZER_ARTIST_ID", "27")
+            ],
+            ids
+        );
+        assert_eq!(Some(10), tagged_track_id(&tag));
+        assert_eq!(None, tagged_track_id(&Tag::new()));
+    }
+
+    #[test]
+    fn tags_contributors() {
+        let mut track = track(10, 1, 1);
+        track.contributors = [
+            ("Artist", "Main"),
+            ("Writer", "Composer"),
+            ("Guest", "Featured"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(id, (name, role))| {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": name,
+                "link": "",
+                "share": "",
+                "picture": "",
+                "picture_small": "",
+                "picture_medium": "",
+                "picture_big": "",
+                "picture_xl": "",
+                "radio": false,
+                "tracklist": "",
+                "type": "artist",
+                "role": role,
+            }))
+            .unwrap()
+        })
+        .collect();
+
+        let mut tag = Tag::new();
+        tag_contributors(&mut tag, &track);
+
+        let credits = tag
+            .extended_texts()
+            .map(|x| (x.description.as_str(), x.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("CONTRIBUTORS", "Artist (Main); Guest (Featured)")],
+            credits
+        );
+    }
+
+    #[test]
+    fn tags_static_tags() {
+        let static_tags = BTreeMap::from([
+            (String::from("COLLECTION"), String::from("Box sets")),
+            (String::from("TIT1"), String::from("Grouping")),
+        ]);
+        let mut tag = Tag::new();
+        tag_static(&mut tag, &static_tags);
+
+        let custom = tag
+            .extended_texts()
+            .map(|x| (x.description.as_str(), x.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("COLLECTION", "Box sets")], custom);
+        assert!(is_text_frame_id("TIT1"));
+        assert!(!is_text_frame_id("TXXX"));
+        assert!(!is_text_frame_id("Tit1"));
+    }
+
+    #[test]
+    fn parses_release_dates() {
+        let date = parse_release_date("2001-05-14").unwrap();
+        assert_eq!((2001, Some(5), Some(14)), (date.year, date.month, date.day));
+        let date = parse_release_date("2001").unwrap();
+        assert_eq!((2001, None, None), (date.year, date.month, date.day));
+        assert!(parse_release_date("").is_none());
+        assert!(parse_release_date("0000-00-00").is_none());
+
+        let mut tag = Tag::new();
+        tag_release_date(&mut tag, "2001-05-14", ReleaseDateTag::Year);
+        assert_eq!(Some(2001), tag.year());
+    }
+
+    #[test]
+    fn detects_compilations() {
+        let tracks = vec![track(1, 1, 1), track(2, 2, 1)];
+        assert_eq!("Artist", album_artist("Main", &tracks));
+
+        let mut other = track(3, 3, 1);
+        other.artist.id = 28;
+        other.artist.name = String::from("Other");
+        let tracks = vec![track(1, 1, 1), track(2, 2, 1), other];
+        assert_eq!("Main", album_artist("Main", &tracks));
+        assert_eq!("Various Artists", album_artist("", &tracks));
+    }
+
+    #[test]
+    fn outcomes_are_dropped_once_nobody_listens() {
+        let (progress_tx, progress_rx) = unbounded();
+        drop(progress_rx);
+        let groups = SharedGroups::default();
+        let group = groups.lock().unwrap().add(String::from("Album"), 1, None);
+        let item = DownloadItem {
+            group: Some(group),
+            ..DownloadItem::new(track(1, 1, 1))
+        };
+
+        let progress = DownloadProgress::Finish(track(1, 1, 1), PathBuf::from("1.mp3"));
+        assert!(send_outcome(&item, progress, &groups, &progress_tx).is_some());
+    }
+
+    #[test]
+    fn summarizes_group_once_done() {
+        let requested_albums = RequestedAlbums::default();
+        requested_albums.lock().unwrap().insert(302127);
+        let mut groups = Groups {
+            requested_albums: Some(requested_albums.clone()),
+            ..Groups::default()
+        };
+        let group = groups.add(String::from("Album"), 3, Some(302127));
+
+        let item = DownloadItem {
+            group: Some(group),
+            ..DownloadItem::new(track(1, 1, 1))
+        };
+
+        let finish = DownloadProgress::Finish(track(1, 1, 1), PathBuf::from("1.mp3"));
+        assert!(groups.record(&item, &finish).is_none());
+        let error = DownloadProgress::DownloadError(track(2, 2, 1), DownloadError::Timeout);
+        assert!(groups.record(&item, &error).is_none());
+        let cancelled = DownloadProgress::Cancelled(track(3, 3, 1));
+        assert!(!requested_albums.lock().unwrap().is_empty());
+        let finished = groups.record(&item, &cancelled).unwrap();
+        // The album can be requested again
+        assert!(requested_albums.lock().unwrap().is_empty());
+
+        let summary = finished.summary;
+        assert_eq!(
+            (3, 1, 1),
+            (summary.total, summary.downloaded, summary.failed)
+        );
+        assert_eq!(
+            vec![PathBuf::from("1.mp3")],
+            finished
+                .written
+                .into_iter()
+                .map(|x| x.1)
+                .collect::<Vec<_>>()
+        );
+        assert!(groups.record(&item, &finish).is_none());
+    }
+
+    #[test]
+    fn writes_album_nfo() {
+        let written = [(2, 1), (1, 1)]
+            .map(|(position, disk)| {
+                let mut track = track(position, position, disk);
+                track.album.title = String::from("Rock & Roll");
+                let item = DownloadItem {
+                    album_artist: Some(String::from("Artist")),
+                    ..DownloadItem::new(track)
+                };
+                (item, PathBuf::from(format!("/music/{}.mp3", position)))
+            })
+            .to_vec();
+
+        let metadata = album_metadata(&written, Path::new("/music"), String::from("cover.jpg"));
+        let nfo = album_nfo(&metadata);
+
+        assert!(nfo.contains("  <title>Rock &amp; Roll</title>\n"));
+        assert!(nfo.contains("  <artistdesc>Artist</artistdesc>\n"));
+        assert!(nfo.contains("  <thumb>cover.jpg</thumb>\n"));
+        let first = nfo.find("<title>Title 1</title>").unwrap();
+        let second = nfo.find("<title>Title 2</title>").unwrap();
+        assert!(first < second);
+    }
+
+    #[test]
+    fn splits_albums_by_disc() {
+        let config = Config {
+            disc_folders: DiscFolders::PerDisc,
+            ..Config::default()
+        };
+        let item = |disk, total_discs| DownloadItem {
+            album_totals: Some((10, total_discs)),
+            ..DownloadItem::new(track(1, 1, disk))
+        };
+        let dir = Path::new("/music");
+
+        assert_eq!(
+            dir.join("Album/Disc 2"),
+            song_dir(dir, &item(2, 2), &config)
+        );
+        assert_eq!(dir.join("Album"), album_dir(dir, &item(2, 2), &config));
+        assert_eq!(dir, song_dir(dir, &item(1, 1), &config));
+        assert_eq!(
+            dir,
+            song_dir(dir, &DownloadItem::new(track(1, 1, 2)), &config)
+        );
+        assert_eq!(dir, song_dir(dir, &item(2, 2), &Config::default()));
+
+        let written = vec![
+            (item(1, 2), dir.join("Disc 1/1.mp3")),
+            (item(2, 2), dir.join("Disc 2/1.mp3")),
+        ];
+        let metadata = album_metadata(&written, dir, String::from("cover.jpg"));
+        let files = metadata.tracks.iter().map(|x| x.file).collect::<Vec<_>>();
+        assert_eq!(vec![Some("Disc 1/1.mp3"), Some("Disc 2/1.mp3")], files);
+    }
+
+    #[test]
+    fn pauses_after_consecutive_failures() {
+        let valve = FailureValve::new(2);
+        let error = DownloadProgress::DownloadError(track(1, 1, 1), DownloadError::Timeout);
+        let finish = DownloadProgress::Finish(track(2, 2, 1), PathBuf::from("2.mp3"));
+
+        assert!(valve.record(&error).is_none());
+        assert!(valve.record(&finish).is_none());
+        assert!(valve.record(&error).is_none());
+        assert_eq!(Some(2), valve.record(&error));
+        assert!(valve.is_paused());
+
+        valve.resume();
+        assert!(!valve.is_paused());
+        assert!(valve.record(&error).is_none());
+    }
+
+    #[tokio::test]
+    async fn retries_missing_song() {
+        let source = MockSource {
+            misses: Mutex::new(1),
+            ..MockSource::default().with_track(track(1, 1, 1))
+        };
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_song(
+            1,
+            source,
+            RequestOptions {
+                not_found_retries: 1,
+                ..RequestOptions::default()
+            },
+            SharedGroups::default(),
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::NotFoundRetry(1, 1))
+        ));
+        assert_eq!(1, download_rx.try_recv().unwrap().0.track.id);
+    }
+
+    #[tokio::test]
+    async fn explicit_tracks_are_filtered_out() {
+        let mut explicit = track(10, 1, 1);
+        explicit.explicit_lyrics = true;
+        let source = MockSource::default().with_album(1, vec![explicit, track(11, 2, 1)]);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_album(
+            1,
+            source,
+            RequestOptions {
+                explicit_content: ExplicitContent::CleanOnly,
+                ..RequestOptions::default()
+            },
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        let ids = queued(&download_rx)
+            .into_iter()
+            .map(|x| x.track.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![11], ids);
+        let progress = progress_rx.try_iter().collect::<Vec<_>>();
+        assert!(progress.iter().any(|x| matches!(
+            x,
+            DownloadProgress::Skipped(track, SkipReason::Explicit) if track.id == 10
+        )));
+        assert!(progress
+            .iter()
+            .any(|x| matches!(x, DownloadProgress::ContentFiltered(1))));
+    }
+
+    #[tokio::test]
+    async fn unreadable_album_tracks_are_summarized() {
+        let mut unreadable = track(11, 2, 1);
+        unreadable.readable = false;
+        let source = MockSource::default().with_album(1, vec![track(10, 1, 1), unreadable]);
+        let groups = SharedGroups::default();
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_album(
+            1,
+            source,
+            RequestOptions::default(),
+            groups.clone(),
+            None,
+            progress_tx.clone(),
+            download_tx.into(),
+        )
+        .await;
+
+        let (item, _) = download_rx.try_recv().unwrap();
+        assert_eq!(10, item.track.id);
+        assert!(download_rx.try_recv().is_err());
+        let progress = DownloadProgress::Finish(item.track.clone(), PathBuf::from("10.mp3"));
+        send_outcome(&item, progress, &groups, &progress_tx);
+
+        let summary = progress_rx
+            .try_iter()
+            .find_map(|x| match x {
+                DownloadProgress::GroupFinished(summary) => Some(summary),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            (2, 1, 0, 1),
+            (
+                summary.total,
+                summary.downloaded,
+                summary.failed,
+                summary.unavailable
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_failed_post_download_commands() {
+        let path = Path::new("/music/Artist - Title 1.mp3");
+        assert_eq!(
+            "/music/Artist - Title 1.mp3 by Artist",
+            fill_command_arg("{path} by {artist}", &track(1, 1, 1), path)
+        );
+
+        let (progress_tx, progress_rx) = unbounded();
+        let command = vec![
+            String::from("/nonexistent/deeznuts-hook"),
+            String::from("{title}"),
+        ];
+        run_post_download_command(command, track(1, 1, 1), path.to_path_buf(), progress_tx).await;
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::PostDownloadCommandError(track, _)) if track.id == 1
+        ));
+    }
+
+    #[test]
+    fn parses_transcode_progress() {
+        assert_eq!(Some(0), transcode_progress("out_time_us=0", 200));
+        assert_eq!(Some(42), transcode_progress("out_time_us=84000000", 200));
+        assert_eq!(Some(100), transcode_progress("out_time_us=200500000", 200));
+        assert_eq!(None, transcode_progress("out_time_us=N/A", 200));
+        assert_eq!(None, transcode_progress("bitrate=320.0kbits/s", 200));
+        assert_eq!(None, transcode_progress("out_time_us=84000000", 0));
+    }
+
+    #[tokio::test]
+    async fn reports_failed_mp3_copies() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-mp3-copy");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Artist - Title 1.flac");
+        std::fs::write(&path, b"fLaC but not really").unwrap();
+
+        let (progress_tx, progress_rx) = unbounded();
+        write_mp3_copy(track(1, 1, 1), path, TagVersion::default(), progress_tx).await;
+        // Whether ffmpeg is installed or not, no copy is written
+        assert!(matches!(
+            progress_rx.try_iter().last(),
+            Some(DownloadProgress::Mp3CopyError(track, _) | DownloadProgress::Mp3CopySkipped(track))
+                if track.id == 1
+        ));
+        assert!(!dir.join("Artist - Title 1.mp3").exists());
+        assert!(!dir.join("Artist - Title 1.mp3.part").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_album_track_ranges() {
+        let range = |start, end| TrackRange { start, end };
+        assert!(matches!(
+            parse_album_request("12345:3-7"),
+            Some(DownloadRequest::AlbumTracks(12345, x)) if x == range(3, Some(7))
+        ));
+        assert!(matches!(
+            parse_album_request("12345:3-"),
+            Some(DownloadRequest::AlbumTracks(12345, x)) if x == range(3, None)
+        ));
+        assert!(matches!(
+            parse_album_request("12345:3"),
+            Some(DownloadRequest::AlbumTracks(12345, x)) if x == range(3, Some(3))
+        ));
+        assert!(matches!(
+            parse_album_request("12345"),
+            Some(DownloadRequest::Album(12345))
+        ));
+        assert!(parse_album_request("12345:7-3").is_none());
+        assert!(parse_album_request("12345:0-3").is_none());
+        assert!(parse_album_request("12345:").is_none());
+        assert_eq!("3-", range(3, None).to_string());
+    }
+
+    #[test]
+    fn parses_deezer_links() {
+        assert_eq!(
+            Some((LinkKind::Track, 3135556)),
+            parse_deezer_input("https://www.deezer.com/track/3135556")
+        );
+        assert_eq!(
+            Some((LinkKind::Track, 3135556)),
+            parse_deezer_input("https://deezer.com/fr/track/3135556/")
+        );
+        assert_eq!(
+            Some((LinkKind::Album, 302127)),
+            parse_deezer_input("https://www.deezer.com/en/album/302127?utm_source=deezer")
+        );
+        assert_eq!(
+            Some((LinkKind::Album, 302127)),
+            parse_deezer_input("deezer.com/album/302127#tracks")
+        );
+        assert_eq!(
+            Some((LinkKind::Playlist, 908622995)),
+            parse_deezer_input("http://www.deezer.com/playlist/908622995")
+        );
+        assert_eq!(None, parse_deezer_input("3135556"));
+        assert_eq!(
+            None,
+            parse_deezer_input("https://example.com/track/3135556")
+        );
+        assert_eq!(None, parse_deezer_input("https://www.deezer.com/artist/27"));
+        assert_eq!(None, parse_deezer_input("https://www.deezer.com/track/abc"));
+        assert_eq!(
+            None,
+            parse_deezer_input("https://deezer.page.link/4kVQjwJhCcYkuPVe9")
+        );
+    }
+
+    #[test]
+    fn recognizes_short_links() {
+        assert!(is_short_link("https://deezer.page.link/4kVQjwJhCcYkuPVe9"));
+        assert!(is_short_link("link.deezer.com/s/30yPQJtc1Nh4ICF3KN5h1"));
+        assert!(!is_short_link("https://deezer.page.link/"));
+        assert!(!is_short_link("https://www.deezer.com/track/3135556"));
+        assert!(!is_short_link("3135556"));
+    }
+
+    #[tokio::test]
+    async fn only_queues_requested_album_tracks() {
+        let source = || {
+            let tracks = (1..=4).map(|x| track(10 + x, x, 1)).collect();
+            MockSource::default().with_album(1, tracks)
+        };
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+        let options = |start, end| RequestOptions {
+            track_range: Some(TrackRange { start, end }),
+            ..RequestOptions::default()
+        };
+
+        download_album(
+            1,
+            source(),
+            options(2, Some(3)),
+            SharedGroups::default(),
+            None,
+            progress_tx.clone(),
+            download_tx.clone().into(),
+        )
+        .await;
+        let ids = queued(&download_rx)
+            .into_iter()
+            .map(|x| x.track.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![12, 13], ids);
+
+        download_album(
+            1,
+            source(),
+            options(3, Some(5)),
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+        assert!(download_rx.try_recv().is_err());
+        assert!(progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::TrackRangeOutOfBounds(1, _, 4))));
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_queue_nothing() {
+        let source = MockSource::default().with_album(1, vec![track(10, 1, 1)]);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+        let queue = RequestQueue::from(download_tx);
+        queue.cancellation_token.cancel();
+
+        download_album(
+            1,
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            None,
+            progress_tx.clone(),
+            queue.clone(),
+        )
+        .await;
+        assert!(download_rx.try_recv().is_err());
+        assert!(!progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::GroupStarted(_, _, _))));
+
+        let groups = SharedGroups::default();
+        queue_items(
+            vec![DownloadItem::new(track(10, 1, 1))],
+            ExplicitContent::default(),
+            &groups,
+            &progress_tx,
+            &queue,
+        );
+        assert!(download_rx.try_recv().is_err());
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::Cancelled(track)) if track.id == 10
+        ));
+    }
+
+    #[tokio::test]
+    async fn queues_playlist_tracks_with_their_album_position() {
+        let in_album = |id, album| {
+            let mut track = track(id, 1, 1);
+            track.album.id = album;
+            track
+        };
+        let source = MockSource::default()
+            .with_album(1, vec![in_album(10, 1), in_album(11, 1)])
+            .with_album(2, vec![in_album(20, 2)])
+            .with_playlist(3, vec![11, 20, 30]);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_playlist(
+            3,
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            None,
+            progress_tx.clone(),
+            download_tx.clone().into(),
+        )
+        .await;
+        let items = queued(&download_rx);
+        let positions = items
+            .iter()
+            .map(|x| (x.track.id, x.track.track_position_in_album))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(11, 2), (20, 1)], positions);
+        assert!(items
+            .iter()
+            .all(|x| x.kind == RequestKind::Playlist && x.genre.as_deref() == Some("Pop")));
+        assert!(progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::PlaylistTrackNotFoundError(3, 30))));
+
+        download_playlist(
+            4,
+            MockSource::default(),
+            RequestOptions::default(),
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+        assert!(progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::PlaylistNotFoundError(4))));
+    }
+
+    #[tokio::test]
+    async fn empty_playlists_finish_right_away() {
+        let source = MockSource::default().with_playlist(1, vec![10]);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, _download_rx) = async_channel::unbounded();
+        let groups = SharedGroups::default();
+
+        download_playlist(
+            1,
+            source,
+            RequestOptions::default(),
+            groups.clone(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+        assert!(progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::GroupFinished(summary) if summary.total == 0)));
+        assert!(groups.lock().unwrap().groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn synced_playlists_only_queue_new_tracks() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-sync");
+        let _ = std::fs::remove_dir_all(&dir);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+        let writer = HistoryWriter::spawn(progress_tx.clone());
+        let sync = Arc::new(PlaylistSync::open_at(dir.join("playlists.jsonl"), writer));
+        sync.insert(3, 10);
+        let source = MockSource::default()
+            .with_album(1, vec![track(10, 1, 1), track(11, 2, 1), track(12, 3, 1)])
+            .with_playlist(3, vec![10, 11, 12]);
+
+        download_playlist(
+            3,
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            Some(sync),
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        let items = queued(&download_rx);
+        let ids = items.iter().map(|x| x.track.id).collect::<Vec<_>>();
+        assert_eq!(vec![11, 12], ids);
+        assert!(items.iter().all(|x| x.playlist == Some(3)));
+        assert!(progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::PlaylistSynced(_, 2))));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn large_albums_wait_for_confirmation() {
+        let mut tracks = vec![track(10, 1, 1), track(11, 2, 1)];
+        tracks.iter_mut().for_each(|x| x.duration = 200);
+        let source = MockSource::default().with_album(1, tracks);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_album(
+            1,
+            source,
+            RequestOptions {
+                confirm_batch_above: Some(1),
+                quality: QualityConfig::all(Quality::Mp3_128),
+                ..RequestOptions::default()
+            },
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(download_rx.try_recv().is_err());
+        let Ok(DownloadProgress::LargeBatch(batch)) = progress_rx.try_recv() else {
+            panic!("The album should wait for confirmation");
+        };
+        assert_eq!(2, batch.items.len());
+        assert_eq!(Some(1), batch.album);
+        // 400 seconds at 128 kbps
+        assert_eq!(6_400_000, batch.size);
+    }
+
+    #[tokio::test]
+    async fn large_playlists_wait_for_confirmation() {
+        let source = MockSource::default()
+            .with_album(1, vec![track(10, 1, 1), track(11, 2, 1)])
+            .with_playlist(2, vec![10, 11]);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_playlist(
+            2,
+            source,
+            RequestOptions {
+                confirm_batch_above: Some(1),
+                ..RequestOptions::default()
+            },
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(download_rx.try_recv().is_err());
+        let Ok(DownloadProgress::LargeBatch(batch)) = progress_rx.try_recv() else {
+            panic!("The playlist should wait for confirmation");
+        };
+        assert_eq!((None, 2), (batch.album, batch.items.len()));
+    }
+
+    #[tokio::test]
+    async fn albums_requested_twice_are_skipped() {
+        let source = Arc::new(MockSource::default().with_album(1, vec![track(10, 1, 1)]));
+        let requested_albums = RequestedAlbums::default();
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        for _ in 0..2 {
+            download_album(
+                1,
+                source.clone(),
+                RequestOptions::default(),
+                SharedGroups::default(),
+                Some(requested_albums.clone()),
+                progress_tx.clone(),
+                download_tx.clone().into(),
+            )
+            .await;
+        }
+
+        assert_eq!(1, queued(&download_rx).len());
+        assert!(progress_rx
+            .try_iter()
+            .any(|x| matches!(x, DownloadProgress::AlbumAlreadyRequested(1))));
+    }
+
+    #[tokio::test]
+    async fn missing_album_is_reported() {
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_album(
+            1,
+            MockSource::default(),
+            RequestOptions::default(),
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::AlbumNotFoundError(1))
+        ));
+        assert!(download_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn album_is_found_by_ean_equivalent_of_upc() {
+        let mut source = MockSource::default().with_album(1, vec![track(10, 1, 1)]);
+        source.barcodes.insert(String::from("0123456789012"), 1);
+        let (progress_tx, _progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_album_by_barcode(
+            String::from("123456789012"),
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            None,
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert_eq!(10, download_rx.try_recv().unwrap().0.track.id);
+    }
+
+    #[tokio::test]
+    async fn missing_song_is_reported() {
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_song(
+            1,
+            MockSource::default(),
+            RequestOptions::default(),
+            SharedGroups::default(),
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::SongNotFoundError(1))
+        ));
+        assert!(download_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unreadable_song_is_not_queued() {
+        let mut unreadable = track(1, 1, 1);
+        unreadable.readable = false;
+        let source = MockSource::default().with_track(unreadable);
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_song(
+            1,
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::SongNotFoundError(1))
+        ));
+        assert!(download_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn readable_song_is_queued() {
+        let source = MockSource::default().with_track(track(1, 1, 1));
+        let (progress_tx, progress_rx) = unbounded();
+        let (download_tx, download_rx) = async_channel::unbounded();
+
+        download_song(
+            1,
+            source,
+            RequestOptions::default(),
+            SharedGroups::default(),
+            progress_tx,
+            download_tx.into(),
+        )
+        .await;
+
+        assert!(matches!(
+            progress_rx.try_recv(),
+            Ok(DownloadProgress::Queue(item)) if item.track.id == 1
+        ));
+        assert_eq!(1, download_rx.try_recv().unwrap().0.track.id);
+    }
+
+    #[test]
+    fn moves_file() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-moves-file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("from.mp3");
+        let to = dir.join("to.mp3");
+        std::fs::write(&from, b"song").unwrap();
+
+        move_file(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(b"song".to_vec(), std::fs::read(&to).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checks_download_dir() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-checks-download-dir");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert!(check_writable_dir(&dir).is_ok());
+        assert!(check_writable_dir(&dir.join("missing")).is_err());
+        assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_songs_to_download_dir() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-writes-songs");
+        let _ = std::fs::remove_dir_all(&dir);
+        let item = DownloadItem::new(track(1, 1, 1));
+        let config = Config::default();
+        let (progress_tx, _progress_rx) = unbounded();
+
+        let path = song_path(&dir, &item, &Tag::new(), Quality::Mp3_128, &config).unwrap();
+        let part_path = part_path(&dir, &item, Quality::Mp3_128, &config);
+        std::fs::write(&part_path, b"song").unwrap();
+        write_song_to_file(
+            part_path.clone(),
+            Tag::new(),
+            &item,
+            &path,
+            &config,
+            &progress_tx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(dir.join("Artist - Title 1.mp3"), path);
+        assert!(path.is_file());
+        assert!(!part_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_part_files_after_the_track() {
+        let dir = Path::new("/music");
+        let item = |id, album: &str| {
+            let mut track = track(id, 1, 1);
+            track.album.title = album.to_string();
+            DownloadItem::new(track)
+        };
+        let config = Config {
+            temp_dir: Some(PathBuf::from("/tmp/songs")),
+            ..Config::default()
+        };
+
+        // Songs of two albums with the same file name
+        let quality = Quality::Mp3_128;
+        assert_eq!(
+            PathBuf::from("/tmp/songs/1-128.part"),
+            part_path(dir, &item(1, "First"), quality, &config)
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/songs/2-128.part"),
+            part_path(dir, &item(2, "Second"), quality, &config)
+        );
+        assert_eq!(
+            dir.join("2-128.part"),
+            part_path(dir, &item(2, "Second"), quality, &Config::default())
+        );
+        // Only resumed in the same quality
+        assert_eq!(
+            dir.join("2-1411.part"),
+            part_path(dir, &item(2, "Second"), Quality::Flac, &Config::default())
+        );
+    }
+
+    #[test]
+    fn reads_total_of_content_range() {
+        assert_eq!(
+            Some(10000),
+            parse_content_range_total("bytes 2048-9999/10000")
+        );
+        assert_eq!(None, parse_content_range_total("bytes 2048-9999/*"));
+        assert_eq!(None, parse_content_range_total("2048-9999/10000"));
+        assert!(is_resumable(&DownloadError::Timeout));
+        assert!(!is_resumable(&DownloadError::Unavailable));
+    }
+
+    #[test]
+    fn splits_featured_artists() {
+        assert_eq!(
+            ("Main", Some("Other")),
+            split_featured_artists("Main feat. Other")
+        );
+        assert_eq!(
+            ("Main", Some("Other")),
+            split_featured_artists("Main ft. Other")
+        );
+        assert_eq!(
+            ("Main", Some("Other & Another")),
+            split_featured_artists("Main Featuring Other & Another")
+        );
+        assert_eq!(
+            ("Main", Some("Other")),
+            split_featured_artists("Main (feat. Other)")
+        );
+        assert_eq!(("Daft Punk", None), split_featured_artists("Daft Punk"));
+    }
+
+    #[test]
+    fn moves_featured_artists_to_title() {
+        let mut track = track(1, 1, 1);
+        track.artist.name = String::from("Artist feat. Other");
+
+        let metadata = metadata_from_track(&track, CoverSize::Big, FeaturedArtists::InTitle);
+        assert_eq!("Artist", metadata.artist);
+        assert_eq!(format!("{} (feat. Other)", track.title), metadata.title);
+
+        let metadata = metadata_from_track(&track, CoverSize::Big, FeaturedArtists::Keep);
+        assert_eq!("Artist feat. Other", metadata.artist);
+    }
+
+    #[test]
+    fn finds_collisions_ignoring_case() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-collisions");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Artist - Title.mp3"), "").unwrap();
+
+        assert_eq!(
+            Some(dir.join("Artist - Title.mp3")),
+            find_collision(&dir.join("ARTIST - title.mp3"))
+        );
+        assert_eq!(None, find_collision(&dir.join("Artist - Other.mp3")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cover_cache_forgets_oldest_albums() {
+        let mut cache = CoverCache::default();
+        let first = cache.get(0);
+        first.set(None).unwrap();
+        for album in 1..COVER_CACHE_SIZE as Id {
+            let cover = Some(Arc::new(album.to_be_bytes().to_vec()));
+            cache.get(album).set(cover).unwrap();
+        }
+        assert!(Arc::ptr_eq(&first, &cache.get(0)));
+
+        cache.get(COVER_CACHE_SIZE as Id);
+        assert!(cache.get(0).get().is_none());
+        assert!(cache.get(2).get().is_some_and(|x| x.is_some()));
+
+        cache.remove(2);
+        assert!(cache.get(2).get().is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_covers_on_disk_until_their_album_is_done() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-covers");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut cache = CoverCache::new(Some(dir.clone()));
+        let path = cache.disk_path(1).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, b"cover").unwrap();
+
+        // Read from disk without fetching
+        let cover = album_cover("", None, Some(path.clone())).await;
+        assert_eq!(Some(b"cover".to_vec()), cover.as_deref().cloned());
+
+        cache.remove(1);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_synced_lyrics_as_lrc() {
+        let metadata = SongMetadata {
+            title: String::from("Harder, Better, Faster, Stronger"),
+            artist: String::from("Daft Punk"),
+            album: String::from("Discovery"),
+            cover: String::new(),
+        };
+        let line = |timestamp: &str, line: &str| SyncedLine {
+            timestamp: timestamp.to_string(),
+            line: line.to_string(),
+        };
+        let mut lyrics = SongLyrics {
+            text: String::from("Work it\nMake it"),
+            synced: vec![line("[00:49.03]", "Work it"), line("[00:49.53]", "Make it")],
+        };
+
+        assert_eq!(
+            Some(String::from(
+                "[ar:Daft Punk]\n[ti:Harder, Better, Faster, Stronger]\n[al:Discovery]\n\
+                 [00:49.03]Work it\n[00:49.53]Make it\n"
+            )),
+            lrc(&lyrics, &metadata)
+        );
+        lyrics.synced.clear();
+        assert_eq!(None, lrc(&lyrics, &metadata));
+    }
+
+    #[test]
+    fn checks_availability_in_market() {
+        let mut track = track(1, 1, 1);
+        assert!(is_available_in(&track, "fr"));
+
+        track.available_countries = vec![String::from("FR"), String::from("BE")];
+        assert!(is_available_in(&track, "fr"));
+        assert!(!is_available_in(&track, "US"));
+    }
+
+    #[test]
+    fn resizes_cover_url() {
+        assert_eq!(
+            Some(String::from(
+                "https://e-cdns-images.dzcdn.net/images/cover/abc/1400x1400-000000-80-0-0.jpg"
+            )),
+            resize_cover_url(
+                "https://e-cdns-images.dzcdn.net/images/cover/abc/500x500-000000-80-0-0.jpg",
+                1400
+            )
+        );
+        assert_eq!(
+            None,
+            resize_cover_url("https://example.com/cover.jpg", 1400)
+        );
+    }
+
+    #[test]
+    fn session_limit_is_reached_once() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-session-limit");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+        std::fs::write(&path, [0; 6]).unwrap();
+        let session_size = Mutex::new(0);
+
+        assert_eq!(None, record_session_size(&session_size, &path, Some(10)));
+        assert_eq!(
+            Some(12),
+            record_session_size(&session_size, &path, Some(10))
+        );
+        assert_eq!(None, record_session_size(&session_size, &path, Some(10)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_songs_without_metadata_after_their_id() {
+        let mut track = track(42, 1, 1);
+        let template = "{artist} - {title}";
+        assert_eq!(
+            "Artist - Title 42.mp3",
+            song_file_name(&Tag::new(), &track, template, Quality::Mp3_128, 255)
+        );
+
+        track.artist.name = String::new();
+        track.title = String::from(" ");
+        assert_eq!(
+            "42.mp3",
+            song_file_name(&Tag::new(), &track, template, Quality::Mp3_128, 255)
+        );
+        assert_eq!(
+            "42.flac",
+            song_file_name(&Tag::new(), &track, template, Quality::Flac, 255)
+        );
+    }
+
+    #[test]
+    fn names_songs_from_template() {
+        let mut track = track(42, 3, 1);
+        track.track_position_in_album = 3;
+
+        assert_eq!(
+            "03 - Artist - Title 42.mp3",
+            song_file_name(
+                &Tag::new(),
+                &track,
+                "{track_number} - {artist} - {title}",
+                Quality::Mp3_128,
+                255
+            )
+        );
+    }
+
+    #[test]
+    fn cuts_long_titles_short() {
+        let mut track = track(42, 3, 1);
+        track.track_position_in_album = 3;
+        let template = "{track_number} - {artist} - {title}";
+
+        // "03 - Artist - " is 14 bytes, the extension 4
+        track.title = "é".repeat(20);
+        let name = song_file_name(&Tag::new(), &track, template, Quality::Mp3_128, 58);
+        assert_eq!(format!("03 - Artist - {}.mp3", "é".repeat(20)), name);
+
+        let name = song_file_name(&Tag::new(), &track, template, Quality::Mp3_128, 57);
+        assert_eq!(format!("03 - Artist - {}….mp3", "é".repeat(18)), name);
+        assert!(name.len() <= 57);
+
+        let name = song_file_name(&Tag::new(), &track, template, Quality::Mp3_128, 56);
+        assert_eq!(format!("03 - Artist - {}….mp3", "é".repeat(17)), name);
+
+        // Too short for the title, the name itself is cut
+        let name = song_file_name(&Tag::new(), &track, template, Quality::Mp3_128, 12);
+        assert_eq!("03 - Art.mp3", name);
+    }
+
+    #[test]
+    fn falls_back_to_available_quality() {
+        let chain = [Quality::Mp3_320, Quality::Mp3_128];
+        let free = [Quality::Mp3_128];
+        let premium = [Quality::Mp3_128, Quality::Mp3_320, Quality::Flac];
+        assert_eq!(
+            Some(Quality::Mp3_128),
+            select_quality(Quality::Flac, &chain, &free)
+        );
+        assert_eq!(
+            Some(Quality::Flac),
+            select_quality(Quality::Flac, &chain, &premium)
+        );
+        assert_eq!(
+            Some(Quality::Mp3_320),
+            select_quality(Quality::Flac, &chain, &[Quality::Mp3_128, Quality::Mp3_320])
+        );
+        // Never below 320kbps
+        assert_eq!(
+            None,
+            select_quality(Quality::Flac, &[Quality::Mp3_320], &free)
+        );
+    }
+
+    #[test]
+    fn retries_failed_downloads_down_the_chain() {
+        let premium = [Quality::Mp3_128, Quality::Mp3_320, Quality::Flac];
+        assert_eq!(
+            vec![Quality::Flac, Quality::Mp3_320, Quality::Mp3_128],
+            quality_attempts(
+                Quality::Flac,
+                &[Quality::Flac, Quality::Mp3_320, Quality::Mp3_128],
+                &premium
+            )
+        );
+        assert_eq!(
+            vec![Quality::Mp3_320],
+            quality_attempts(Quality::Flac, &[Quality::Mp3_320], &[Quality::Mp3_320])
+        );
+
+        assert!(is_quality_failure(&DownloadError::Unavailable));
+        assert!(is_quality_failure(&DownloadError::Network(String::from(
+            "403 Forbidden"
+        ))));
+        let err = std::io::Error::other("disk full");
+        assert!(!is_quality_failure(&DownloadError::Io(
+            "unable to write the file",
+            err
+        )));
+        assert!(!is_quality_failure(&DownloadError::NoDownloadDir));
+    }
+
+    #[test]
+    fn reads_mp3_bitrate_after_tag() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-mp3-bitrate");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+        let mut content = b"ID3\x04\x00\x00\x00\x00\x00\x02\xff\xfb".to_vec();
+        content.extend([0xff, 0xfb, 0xe0, 0x00, 0x00]);
+        std::fs::write(&path, content).unwrap();
+
+        assert_eq!(Some(320), audio_bitrate(&path));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn estimates_bitrate() {
+        assert_eq!(320, FileInfo::new(8_000_000, 200).kbps);
+        assert_eq!(0, FileInfo::new(8_000_000, 0).kbps);
+        assert_eq!(
+            "7.6 MB, ~320 kbps",
+            FileInfo::new(8_000_000, 200).to_string()
+        );
+    }
+
+    #[test]
+    fn names_sidecar_covers_after_album() {
+        let item = DownloadItem {
+            album_artist: Some(String::from("AC/DC")),
+            ..DownloadItem::new(track(1, 1, 1))
+        };
+
+        let name = sidecar_cover_file_name(&item, "{artist} - {album}.jpg");
+        assert_eq!(format!("ACDC - {}.jpg", item.track.album.title), name);
+        assert_eq!(
+            "cover.jpg",
+            sidecar_cover_file_name(&item, &SidecarCover::default().file_name)
+        );
+    }
 
     #[test]
     fn replaces_invalid_chars() {
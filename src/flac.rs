@@ -0,0 +1,384 @@
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result};
+use id3::frame::{Comment, Lyrics, Picture, PictureType};
+use id3::Tag;
+
+use crate::downloader::is_text_frame_id;
+
+/// Marker FLAC files start with, before their metadata blocks.
+static MARKER: &[u8; 4] = b"fLaC";
+
+static STREAMINFO: u8 = 0;
+static VORBIS_COMMENT: u8 = 4;
+static PICTURE: u8 = 6;
+
+/// Written as the vendor of the Vorbis comments.
+static VENDOR: &str = "deeznuts-downloader";
+
+/// Names of the Vorbis comments of the ID3 text frames that have one, the others keep
+/// their frame id.
+static TEXT_FIELDS: [(&str, &str); 11] = [
+    ("TIT2", "TITLE"),
+    ("TPE1", "ARTIST"),
+    ("TALB", "ALBUM"),
+    ("TPE2", "ALBUMARTIST"),
+    ("TCON", "GENRE"),
+    ("TCOM", "COMPOSER"),
+    ("TDRC", "DATE"),
+    ("TYER", "DATE"),
+    ("TSRC", "ISRC"),
+    ("TPUB", "LABEL"),
+    ("TBPM", "BPM"),
+];
+
+/// A metadata block of a FLAC file.
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+/// Whether a file is a FLAC file, from its marker.
+pub fn is_flac(path: &Path) -> bool {
+    let mut marker = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut marker))
+        .is_ok_and(|_| &marker == MARKER)
+}
+
+/// Read the Vorbis comments and pictures of a FLAC file as the ID3 tag they would be
+/// written from by [write_tag].
+pub fn read_tag(path: &Path) -> Result<Tag> {
+    let content = std::fs::read(path)?;
+    let (blocks, _) = parse(&content)?;
+
+    let mut tag = Tag::new();
+    for block in &blocks {
+        if block.kind == VORBIS_COMMENT {
+            for (name, value) in parse_comments(&block.data)? {
+                add_field(&mut tag, &name, value);
+            }
+        } else if block.kind == PICTURE {
+            tag.add_picture(parse_picture(&block.data)?);
+        }
+    }
+    Ok(tag)
+}
+
+/// Replace the Vorbis comments and pictures of a FLAC file with those of an ID3 tag,
+/// leaving the audio and the other metadata blocks as they are.
+pub fn write_tag(path: &Path, tag: &Tag) -> Result<()> {
+    let content = std::fs::read(path)?;
+    let (blocks, audio) = parse(&content)?;
+
+    let mut blocks = blocks
+        .into_iter()
+        .filter(|x| x.kind != VORBIS_COMMENT && x.kind != PICTURE)
+        .collect::<Vec<_>>();
+    // The stream info has to stay the first block
+    blocks.insert(
+        1,
+        Block {
+            kind: VORBIS_COMMENT,
+            data: comments_block(&fields(tag)),
+        },
+    );
+    for picture in tag.pictures() {
+        blocks.push(Block {
+            kind: PICTURE,
+            data: picture_block(picture),
+        });
+    }
+
+    let mut written = MARKER.to_vec();
+    for (i, block) in blocks.iter().enumerate() {
+        let last = if i == blocks.len() - 1 { 0x80 } else { 0 };
+        let len = u32::try_from(block.data.len())
+            .ok()
+            .filter(|x| *x < 1 << 24)
+            .ok_or(eyre!(
+                "Metadata block of {} bytes is too large",
+                block.data.len()
+            ))?;
+        written.push(last | block.kind);
+        written.extend_from_slice(&len.to_be_bytes()[1..]);
+        written.extend_from_slice(&block.data);
+    }
+    written.extend_from_slice(audio);
+    std::fs::write(path, written)?;
+    Ok(())
+}
+
+/// Split a FLAC file into its metadata blocks and its audio frames.
+fn parse(content: &[u8]) -> Result<(Vec<Block>, &[u8])> {
+    if content.get(..4) != Some(MARKER) {
+        return Err(eyre!("Not a FLAC file"));
+    }
+
+    let mut blocks = Vec::new();
+    let mut offset = 4;
+    loop {
+        let header = content
+            .get(offset..offset + 4)
+            .ok_or(eyre!("Truncated FLAC metadata"))?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let data = content
+            .get(offset + 4..offset + 4 + len)
+            .ok_or(eyre!("Truncated FLAC metadata"))?;
+        blocks.push(Block {
+            kind: header[0] & 0x7f,
+            data: data.to_vec(),
+        });
+        offset += 4 + len;
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+
+    if blocks.first().map(|x| x.kind) != Some(STREAMINFO) {
+        return Err(eyre!("FLAC file without stream info"));
+    }
+    Ok((blocks, &content[offset..]))
+}
+
+/// Vorbis comments of the frames of an ID3 tag.
+fn fields(tag: &Tag) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for frame in tag.frames() {
+        let id = frame.id();
+        let content = frame.content();
+        if let Some(text) = content.text() {
+            // Split into a number and a total below
+            if id == "TRCK" || id == "TPOS" {
+                continue;
+            }
+            let name = TEXT_FIELDS
+                .iter()
+                .find(|(frame_id, _)| *frame_id == id)
+                .map_or(id, |(_, name)| name);
+            // A year and a date are only both set by tags converted from another one
+            if name != "DATE" || fields.iter().all(|(x, _)| x != "DATE") {
+                fields.push((name.to_string(), text.to_string()));
+            }
+        } else if let Some(text) = content.extended_text() {
+            fields.push((text.description.clone(), text.value.clone()));
+        } else if let Some(comment) = content.comment() {
+            fields.push((String::from("COMMENT"), comment.text.clone()));
+        } else if let Some(lyrics) = content.lyrics() {
+            fields.push((String::from("LYRICS"), lyrics.text.clone()));
+        }
+    }
+
+    let numbers = [
+        ("TRACKNUMBER", tag.track()),
+        ("TRACKTOTAL", tag.total_tracks()),
+        ("DISCNUMBER", tag.disc()),
+        ("DISCTOTAL", tag.total_discs()),
+    ];
+    for (name, number) in numbers {
+        if let Some(number) = number.filter(|x| *x > 0) {
+            fields.push((name.to_string(), number.to_string()));
+        }
+    }
+    fields
+}
+
+/// Add a Vorbis comment to an ID3 tag, as the frame [fields] would write it from.
+fn add_field(tag: &mut Tag, name: &str, value: String) {
+    let number = || value.trim().parse().ok();
+    match name.to_uppercase().as_str() {
+        "TRACKNUMBER" => number().map_or((), |x| tag.set_track(x)),
+        "TRACKTOTAL" => number().map_or((), |x| tag.set_total_tracks(x)),
+        "DISCNUMBER" => number().map_or((), |x| tag.set_disc(x)),
+        "DISCTOTAL" => number().map_or((), |x| tag.set_total_discs(x)),
+        "COMMENT" => tag.add_comment(Comment {
+            lang: String::from("eng"),
+            description: String::new(),
+            text: value,
+        }),
+        "LYRICS" => tag.add_lyrics(Lyrics {
+            lang: String::from("eng"),
+            description: String::new(),
+            text: value,
+        }),
+        name => match TEXT_FIELDS.iter().find(|(_, x)| *x == name) {
+            Some((id, _)) => tag.set_text(*id, value),
+            None if is_text_frame_id(name) => tag.set_text(name, value),
+            None => tag.add_extended_text(name, value),
+        },
+    }
+}
+
+fn comments_block(fields: &[(String, String)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    data.extend_from_slice(VENDOR.as_bytes());
+    data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    for (name, value) in fields {
+        let comment = format!("{}={}", name, value);
+        data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        data.extend_from_slice(comment.as_bytes());
+    }
+    data
+}
+
+fn parse_comments(data: &[u8]) -> Result<Vec<(String, String)>> {
+    let mut reader = Reader { data, offset: 0 };
+    let vendor_len = reader.u32_le()? as usize;
+    reader.bytes(vendor_len)?;
+
+    let count = reader.u32_le()?;
+    let mut comments = Vec::new();
+    for _ in 0..count {
+        let len = reader.u32_le()? as usize;
+        let comment = String::from_utf8_lossy(reader.bytes(len)?);
+        if let Some((name, value)) = comment.split_once('=') {
+            comments.push((name.to_string(), value.to_string()));
+        }
+    }
+    Ok(comments)
+}
+
+fn picture_block(picture: &Picture) -> Vec<u8> {
+    fn push(data: &mut Vec<u8>, bytes: &[u8]) {
+        data.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        data.extend_from_slice(bytes);
+    }
+
+    let mut data = Vec::new();
+    let picture_type = match picture.picture_type {
+        PictureType::Icon => 1,
+        PictureType::OtherIcon => 2,
+        PictureType::CoverFront => 3,
+        PictureType::CoverBack => 4,
+        _ => 0,
+    };
+    data.extend_from_slice(&(picture_type as u32).to_be_bytes());
+    push(&mut data, picture.mime_type.as_bytes());
+    push(&mut data, picture.description.as_bytes());
+    // Width, height, color depth and number of colors, unknown
+    data.extend_from_slice(&[0; 16]);
+    push(&mut data, &picture.data);
+    data
+}
+
+fn parse_picture(data: &[u8]) -> Result<Picture> {
+    let mut reader = Reader { data, offset: 0 };
+    let picture_type = match reader.u32_be()? {
+        1 => PictureType::Icon,
+        2 => PictureType::OtherIcon,
+        3 => PictureType::CoverFront,
+        4 => PictureType::CoverBack,
+        _ => PictureType::Other,
+    };
+    let len = reader.u32_be()? as usize;
+    let mime_type = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+    let len = reader.u32_be()? as usize;
+    let description = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+    reader.bytes(16)?;
+    let len = reader.u32_be()? as usize;
+
+    Ok(Picture {
+        mime_type,
+        picture_type,
+        description,
+        data: reader.bytes(len)?.to_vec(),
+    })
+}
+
+/// Reads the fields of a metadata block in order.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or(eyre!("Truncated FLAC metadata block"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u32_le(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u32_be(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_tags_as_vorbis_comments() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-flac");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.flac");
+        // Stream info, padding, then the audio frames
+        let mut content = MARKER.to_vec();
+        content.extend_from_slice(&[STREAMINFO, 0, 0, 34]);
+        content.extend_from_slice(&[1; 34]);
+        content.extend_from_slice(&[0x80 | 1, 0, 0, 8]);
+        content.extend_from_slice(&[0; 8]);
+        content.extend_from_slice(b"audio");
+        std::fs::write(&path, &content).unwrap();
+        assert!(is_flac(&path));
+
+        let mut tag = Tag::new();
+        tag.set_title("Title");
+        tag.set_artist("Artist");
+        tag.set_track(3);
+        tag.set_total_tracks(12);
+        tag.add_extended_text("DEEZER_TRACK_ID", "42");
+        tag.add_picture(Picture {
+            mime_type: String::from("image/jpeg"),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data: b"cover".to_vec(),
+        });
+        write_tag(&path, &tag).unwrap();
+        // Written twice, the previous comments and pictures are replaced
+        write_tag(&path, &tag).unwrap();
+
+        let written = std::fs::read(&path).unwrap();
+        assert!(written.ends_with(b"audio"));
+        let (blocks, _) = parse(&written).unwrap();
+        let kinds = blocks.iter().map(|x| x.kind).collect::<Vec<_>>();
+        assert_eq!(vec![STREAMINFO, VORBIS_COMMENT, 1, PICTURE], kinds);
+        let comments = parse_comments(&blocks[1].data).unwrap();
+        assert!(comments.contains(&(String::from("TITLE"), String::from("Title"))));
+        assert!(comments.contains(&(String::from("TRACKTOTAL"), String::from("12"))));
+
+        let read = read_tag(&path).unwrap();
+        assert_eq!(Some("Title"), read.title());
+        assert_eq!(Some("Artist"), read.artist());
+        assert_eq!((Some(3), Some(12)), (read.track(), read.total_tracks()));
+        assert_eq!(
+            Some("42"),
+            read.extended_texts()
+                .find(|x| x.description == "DEEZER_TRACK_ID")
+                .map(|x| x.value.as_str())
+        );
+        assert_eq!(
+            vec![b"cover".to_vec()],
+            read.pictures().map(|x| x.data.clone()).collect::<Vec<_>>()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_other_files() {
+        assert!(parse(b"ID3\x03").is_err());
+        // A block longer than the file
+        assert!(parse(b"fLaC\x80\x00\x00\x22").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use deezer::models::Track;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::downloader::DownloadProgress;
+
+/// A line appended to a file by the history writer.
+#[derive(Debug)]
+struct Line {
+    path: PathBuf,
+    content: String,
+}
+
+/// A finished download, as recorded in the history file.
+#[derive(Debug, Serialize)]
+struct HistoryEntry<'a> {
+    id: u64,
+    artist: &'a str,
+    title: &'a str,
+    path: &'a Path,
+    downloaded_at: String,
+}
+
+/// A finished download read back from the history file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RecentDownload {
+    pub artist: String,
+    pub title: String,
+    pub path: PathBuf,
+}
+
+/// File finished downloads are recorded to, `None` if no data directory was found.
+fn history_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "deeznuts-downloader")
+        .map(|dirs| dirs.data_dir().join("history.jsonl"))
+}
+
+/// Read the last downloads recorded to the history file, most recent last.
+pub fn recent_downloads(count: usize) -> Vec<RecentDownload> {
+    history_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .map(|content| parse_recent_downloads(&content, count))
+        .unwrap_or_default()
+}
+
+fn parse_recent_downloads(content: &str, count: usize) -> Vec<RecentDownload> {
+    let mut recent = content
+        .lines()
+        .rev()
+        // Lines cut by a crash or written by a later version are left out
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .take(count)
+        .collect::<Vec<_>>();
+    recent.reverse();
+    recent
+}
+
+/// Handle to the task writing the history and other shared files. Lines are written one
+/// at a time in the order they are sent, so workers never interleave them.
+#[derive(Debug, Clone)]
+pub struct HistoryWriter {
+    line_tx: Sender<Line>,
+    /// File finished downloads are recorded to, `None` if no data directory was found.
+    history_path: Option<PathBuf>,
+}
+
+impl HistoryWriter {
+    /// Start the writer task, reporting write failures as progress. It stops once every
+    /// handle is dropped.
+    pub fn spawn(progress_tx: Sender<DownloadProgress>) -> Self {
+        let (line_tx, line_rx) = unbounded();
+        std::thread::spawn(move || write_lines(line_rx, progress_tx));
+
+        HistoryWriter {
+            line_tx,
+            history_path: history_path(),
+        }
+    }
+
+    /// Append a line to a file, creating it and its directory if needed.
+    pub fn append(&self, path: &Path, content: String) {
+        // The writer task only stops once every handle is dropped
+        let _ = self.line_tx.send(Line {
+            path: path.to_path_buf(),
+            content,
+        });
+    }
+
+    /// Record a finished download to the history file.
+    pub fn record(&self, track: &Track, path: &Path) {
+        let Some(history_path) = &self.history_path else {
+            return;
+        };
+
+        let entry = HistoryEntry {
+            id: track.id,
+            artist: &track.artist.name,
+            title: &track.title,
+            path,
+            downloaded_at: Local::now().to_rfc3339(),
+        };
+        let content = serde_json::to_string(&entry).expect("Entry should be serializable.");
+        self.append(history_path, content);
+    }
+}
+
+fn write_lines(line_rx: Receiver<Line>, progress_tx: Sender<DownloadProgress>) {
+    for line in line_rx {
+        if let Err(err) = append_line(&line) {
+            let message = format!("{}: {}", line.path.display(), err);
+            // Nobody is left to report to once the downloader is dropped
+            let _ = progress_tx.send(DownloadProgress::HistoryError(message));
+        }
+    }
+}
+
+fn append_line(line: &Line) -> std::io::Result<()> {
+    if let Some(parent) = line.path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&line.path)?;
+    writeln!(file, "{}", line.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn reads_last_downloads() {
+        let content = [
+            r#"{"id":1,"artist":"A","title":"One","path":"/music/one.mp3","downloaded_at":""}"#,
+            r#"{"id":2,"artist":"B","title":"Two","path":"/music/two.mp3","downloaded_at":""}"#,
+            r#"{"id":3,"artist":"C","title":"Th"#,
+            r#"{"id":4,"artist":"D","title":"Four","path":"/music/four.mp3","downloaded_at":""}"#,
+        ]
+        .join("\n");
+
+        let recent = parse_recent_downloads(&content, 2);
+
+        let titles = recent.iter().map(|x| x.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["Two", "Four"], titles);
+    }
+
+    #[test]
+    fn lines_from_several_threads_are_not_interleaved() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-history");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("history.jsonl");
+        let (progress_tx, _progress_rx) = unbounded();
+        let writer = HistoryWriter::spawn(progress_tx);
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let writer = writer.clone();
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        writer.append(&path, format!("{}-{}-{}", thread, i, "x".repeat(500)));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let content = loop {
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            if content.lines().count() == 400 || Instant::now() > deadline {
+                break content;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        assert_eq!(400, content.lines().count());
+        assert!(content.lines().all(|line| line.ends_with(&"x".repeat(500))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+
+use crate::cli::handle_requests;
+use crate::config::Config;
+use crate::downloader::{http_client, DownloadProgress, DownloadRequest, Downloader, Id};
+
+/// Number of search results considered for a line.
+static SEARCH_RESULTS: usize = 10;
+
+/// Share of common words under which a search result isn't considered a match.
+static FUZZY_THRESHOLD: f64 = 0.5;
+
+/// Share of common words of the title under which a search result isn't considered a
+/// match, however close its artist is, so that another song of the artist isn't picked.
+static TITLE_THRESHOLD: f64 = 0.5;
+
+/// Difference of score under which two different results are too close to pick one.
+static AMBIGUITY_MARGIN: f64 = 0.1;
+
+/// A track found by a search.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct SearchTrack {
+    id: Id,
+    title: String,
+    artist: SearchArtist,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct SearchArtist {
+    name: String,
+}
+
+/// Track matched to a line of a playlist.
+#[derive(Debug, PartialEq)]
+enum Match {
+    /// Same artist and title.
+    Exact(SearchTrack),
+    /// Close enough artist and title, to be reviewed.
+    Fuzzy(SearchTrack),
+    /// Several tracks match as well as each other.
+    Ambiguous(Vec<SearchTrack>),
+    NotFound,
+}
+
+/// Match each "Artist - Title" line of a playlist exported from another service to a
+/// Deezer track and download the matches. Lines that couldn't be matched, or not to a
+/// single track, are written to a `.unmatched.txt` file next to the playlist.
+pub async fn run_import(config: Config, path: PathBuf) -> Result<()> {
+    let content = std::fs::read_to_string(&path)?;
+    let mut ids = HashSet::new();
+    let mut unmatched = Vec::new();
+    let (mut exact, mut fuzzy) = (0, 0);
+
+    for line in content.lines().map(str::trim).filter(|x| !x.is_empty()) {
+        let Some((artist, title)) = parse_line(line) else {
+            println!("Not an \"Artist - Title\" line: {}", line);
+            unmatched.push(line);
+            continue;
+        };
+
+        let found = match find_match(artist, title).await {
+            Ok(found) => found,
+            Err(err) => {
+                println!("Unable to search for {}: {}", line, err);
+                unmatched.push(line);
+                continue;
+            }
+        };
+
+        match found {
+            Match::Exact(track) => {
+                exact += 1;
+                ids.insert(track.id);
+            }
+            Match::Fuzzy(track) => {
+                fuzzy += 1;
+                println!(
+                    "Matched {} to {} - {} (#{}), check it",
+                    line, track.artist.name, track.title, track.id
+                );
+                ids.insert(track.id);
+            }
+            Match::Ambiguous(tracks) => {
+                println!("Several matches for {}:", line);
+                for track in tracks {
+                    println!("  {} - {} (#{})", track.artist.name, track.title, track.id);
+                }
+                unmatched.push(line);
+            }
+            Match::NotFound => {
+                println!("No match for {}", line);
+                unmatched.push(line);
+            }
+        }
+    }
+
+    println!(
+        "Matched {} lines exactly and {} approximately, {} unmatched",
+        exact,
+        fuzzy,
+        unmatched.len()
+    );
+    if !unmatched.is_empty() {
+        let unmatched_path = path.with_extension("unmatched.txt");
+        std::fs::write(&unmatched_path, unmatched.join("\n") + "\n")?;
+        println!("Unmatched lines written to {}", unmatched_path.display());
+    }
+
+    // Nobody is there to answer a prompt
+    let downloader = Downloader::new(Config {
+        ask_on_collision: false,
+        confirm_batch_above: None,
+        ..config
+    });
+    let requests = ids.into_iter().map(DownloadRequest::Song).collect();
+    let (mut downloaded, mut failed) = (0, 0);
+    handle_requests(&downloader, requests, |progress| {
+        match progress {
+            DownloadProgress::Finish(_, _) => downloaded += 1,
+            DownloadProgress::DownloadError(track, err) => {
+                failed += 1;
+                println!(
+                    "Unable to download {} - {}: {}",
+                    track.artist.name, track.title, err
+                );
+            }
+            DownloadProgress::SongNotFoundError(id) => {
+                failed += 1;
+                println!("Track {} can't be downloaded", id);
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    println!("Downloaded {} tracks, {} failed", downloaded, failed);
+
+    Ok(())
+}
+
+/// Search for the track of a line, exactly then approximately.
+async fn find_match(artist: &str, title: &str) -> Result<Match> {
+    let results = search_tracks(&format!("artist:\"{}\" track:\"{}\"", artist, title)).await?;
+    if let Some(track) = exact_match(artist, title, &results) {
+        return Ok(Match::Exact(track));
+    }
+
+    let results = search_tracks(&format!("{} {}", artist, title)).await?;
+    Ok(fuzzy_match(artist, title, &results))
+}
+
+async fn search_tracks(query: &str) -> Result<Vec<SearchTrack>> {
+    #[derive(Deserialize)]
+    struct Search {
+        data: Vec<SearchTrack>,
+    }
+
+    let search: Search = http_client()
+        .get("https://api.deezer.com/search/track")
+        .query(&[("q", query), ("limit", &SEARCH_RESULTS.to_string())])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(search.data)
+}
+
+/// Split an "Artist - Title" line, on the first dash since titles have some more often.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let (artist, title) = line.split_once(" - ")?;
+    let (artist, title) = (artist.trim(), title.trim());
+    (!artist.is_empty() && !title.is_empty()).then_some((artist, title))
+}
+
+/// Lowercase words of a name, without punctuation.
+fn words(name: &str) -> Vec<String> {
+    name.to_lowercase()
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// First result with the same artist and title, ignoring case and punctuation.
+fn exact_match(artist: &str, title: &str, results: &[SearchTrack]) -> Option<SearchTrack> {
+    results
+        .iter()
+        .find(|x| words(&x.artist.name) == words(artist) && words(&x.title) == words(title))
+        .cloned()
+}
+
+/// Share of the words of two names they have in common, from 0 to 1.
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (words(a), words(b));
+    let common = a.iter().filter(|x| b.contains(x)).count();
+    let total = a.len().max(b.len());
+    match total {
+        0 => 0.0,
+        _ => common as f64 / total as f64,
+    }
+}
+
+/// Result whose artist and title are the closest to the line, as long as no different
+/// track is about as close.
+fn fuzzy_match(artist: &str, title: &str, results: &[SearchTrack]) -> Match {
+    let mut scored = results
+        .iter()
+        .filter(|x| similarity(&x.title, title) >= TITLE_THRESHOLD)
+        .map(|x| {
+            let score = (similarity(&x.artist.name, artist) + similarity(&x.title, title)) / 2.0;
+            (score, x)
+        })
+        .filter(|(score, _)| *score >= FUZZY_THRESHOLD)
+        .collect::<Vec<_>>();
+    // Stable, so results Deezer ranks first win ties
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let Some((best_score, best)) = scored.first() else {
+        return Match::NotFound;
+    };
+    // The same track is often found on several albums, those aren't ambiguous
+    let close = scored
+        .iter()
+        .filter(|(score, x)| {
+            best_score - score < AMBIGUITY_MARGIN
+                && (words(&x.title) != words(&best.title)
+                    || words(&x.artist.name) != words(&best.artist.name))
+        })
+        .map(|(_, x)| (*x).clone())
+        .collect::<Vec<_>>();
+
+    if close.is_empty() {
+        Match::Fuzzy((*best).clone())
+    } else {
+        Match::Ambiguous(std::iter::once((*best).clone()).chain(close).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(id: Id, artist: &str, title: &str) -> SearchTrack {
+        SearchTrack {
+            id,
+            title: String::from(title),
+            artist: SearchArtist {
+                name: String::from(artist),
+            },
+        }
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(
+            Some(("Daft Punk", "Harder - Better")),
+            parse_line("Daft Punk - Harder - Better")
+        );
+        assert_eq!(None, parse_line("Daft Punk"));
+        assert_eq!(None, parse_line(" - Title"));
+    }
+
+    #[test]
+    fn prefers_exact_matches() {
+        let results = [
+            result(1, "Daft Punk", "One More Time (Radio Edit)"),
+            result(2, "Daft Punk", "One more time"),
+        ];
+
+        assert_eq!(
+            Some(2),
+            exact_match("daft punk", "One More Time", &results).map(|x| x.id)
+        );
+        assert_eq!(None, exact_match("Daft Punk", "Aerodynamic", &results));
+    }
+
+    #[test]
+    fn matches_close_results() {
+        let results = [
+            result(1, "Someone Else", "Another Song"),
+            result(2, "Daft Punk", "One More Time (Radio Edit)"),
+        ];
+        assert_eq!(
+            Match::Fuzzy(results[1].clone()),
+            fuzzy_match("Daft Punk", "One More Time", &results)
+        );
+
+        assert_eq!(
+            Match::NotFound,
+            fuzzy_match("Daft Punk", "Aerodynamic", &results[..1])
+        );
+        // Another song of the same artist
+        assert_eq!(
+            Match::NotFound,
+            fuzzy_match("Daft Punk", "Aerodynamic", &results[1..])
+        );
+
+        let results = [
+            result(1, "Daft Punk", "Time (Live)"),
+            result(2, "Daft Punk", "Time (Remix)"),
+        ];
+        assert!(matches!(
+            fuzzy_match("Daft Punk", "Time", &results),
+            Match::Ambiguous(tracks) if tracks.len() == 2
+        ));
+    }
+}
@@ -1,29 +1,311 @@
-use crate::downloader::DownloadProgress;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+use crate::downloader::{DownloadProgress, GroupId, Id};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LogEntry {
     Error(String),
     Success(String),
+    Info(String),
 }
 
-pub fn get_log_from_progress(download_progress: &DownloadProgress) -> Option<LogEntry> {
-    match download_progress {
-        DownloadProgress::Queue(_) | DownloadProgress::Start(_) => None,
-        DownloadProgress::Finish(track) => Some(LogEntry::Success(format!(
-            "{} - {} downloaded",
+impl LogEntry {
+    pub fn level(&self) -> &'static str {
+        match self {
+            LogEntry::Error(_) => "Error",
+            LogEntry::Success(_) => "Success",
+            LogEntry::Info(_) => "Info",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            LogEntry::Error(msg) | LogEntry::Success(msg) | LogEntry::Info(msg) => msg,
+        }
+    }
+}
+
+/// A log entry with when it was logged and the track it is about, if any.
+#[derive(Debug)]
+pub struct LogLine {
+    pub entry: LogEntry,
+    pub time: DateTime<Local>,
+    pub track_id: Option<Id>,
+    /// Group of tracks the entry is about, if any.
+    pub group: Option<GroupId>,
+}
+
+impl From<LogEntry> for LogLine {
+    fn from(entry: LogEntry) -> Self {
+        LogLine {
+            entry,
+            time: Local::now(),
+            track_id: None,
+            group: None,
+        }
+    }
+}
+
+/// Log line of a progress event, if it is logged. Queued and started tracks are only
+/// logged when `verbose`, as they are mostly noise.
+pub fn get_log_from_progress(
+    download_progress: &DownloadProgress,
+    verbose: bool,
+) -> Option<LogLine> {
+    let entry = match download_progress {
+        DownloadProgress::Queue(item) if verbose => Some(LogEntry::Info(format!(
+            "Queued {} - {}",
+            item.track.artist.name, item.track.title
+        ))),
+        DownloadProgress::Start(track) if verbose => Some(LogEntry::Info(format!(
+            "Started {} - {}",
             track.artist.name, track.title
         ))),
-        DownloadProgress::DownloadError(track) => Some(LogEntry::Error(format!(
-            "Error while downloading {} - {}",
+        _ => get_entry_from_progress(download_progress),
+    }?;
+    Some(LogLine {
+        track_id: get_track_id(download_progress),
+        group: get_group(download_progress),
+        ..LogLine::from(entry)
+    })
+}
+
+fn get_group(download_progress: &DownloadProgress) -> Option<GroupId> {
+    match download_progress {
+        DownloadProgress::Queue(item) => item.group,
+        DownloadProgress::GroupStarted(group, _, _) => Some(*group),
+        DownloadProgress::GroupFinished(summary) => Some(summary.id),
+        _ => None,
+    }
+}
+
+fn get_track_id(download_progress: &DownloadProgress) -> Option<Id> {
+    match download_progress {
+        DownloadProgress::Queue(item) => Some(item.track.id),
+        DownloadProgress::Start(track)
+        | DownloadProgress::Finish(track, _)
+        | DownloadProgress::Progress(track, _)
+        | DownloadProgress::Upgrade(track, _, _)
+        | DownloadProgress::QualityDowngrade(track, _, _)
+        | DownloadProgress::QualityRetry(track, _, _, _)
+        | DownloadProgress::Overwrite(track, _, _)
+        | DownloadProgress::DownloadError(track, _)
+        | DownloadProgress::Cancelled(track)
+        | DownloadProgress::Skipped(track, _)
+        | DownloadProgress::Collision(track, _, _, _, _, _) => Some(track.id),
+        DownloadProgress::SongNotFoundError(id) => Some(*id),
+        _ => None,
+    }
+}
+
+fn get_entry_from_progress(download_progress: &DownloadProgress) -> Option<LogEntry> {
+    match download_progress {
+        DownloadProgress::Queue(_)
+        | DownloadProgress::Start(_)
+        | DownloadProgress::Progress(_, _)
+        | DownloadProgress::Mp3CopyProgress(_, _)
+        | DownloadProgress::Cancelled(_)
+        | DownloadProgress::WorkerStatus(_, _)
+        | DownloadProgress::AlbumTracks(_, _) => None,
+        DownloadProgress::Finish(track, path) => Some(LogEntry::Success(format!(
+            "{} - {} downloaded to {}",
+            track.artist.name,
+            track.title,
+            path.display()
+        ))),
+        DownloadProgress::Upgrade(track, existing, quality) => Some(LogEntry::Info(format!(
+            "{} - {} is upgraded from ~{} kbps to {}",
+            track.artist.name, track.title, existing, quality
+        ))),
+        DownloadProgress::QualityDowngrade(track, requested, quality) => {
+            Some(LogEntry::Info(format!(
+                "{} - {} isn't available in {}, downloading it in {}",
+                track.artist.name, track.title, requested, quality
+            )))
+        }
+        DownloadProgress::QualityRetry(track, failed, quality, err) => {
+            Some(LogEntry::Info(format!(
+                "{} - {} failed in {} ({}), retrying in {}",
+                track.artist.name, track.title, failed, err, quality
+            )))
+        }
+        DownloadProgress::Overwrite(track, existing, new) => Some(LogEntry::Info(format!(
+            "{} - {} replaces an existing file ({}) with the new download ({})",
+            track.artist.name, track.title, existing, new
+        ))),
+        DownloadProgress::Collision(track, _, existing, existing_info, new_info, _) => {
+            Some(LogEntry::Info(format!(
+                "{} - {} ({}) waits for a new name, {} ({}) is taken",
+                track.artist.name,
+                track.title,
+                new_info,
+                existing.display(),
+                existing_info
+            )))
+        }
+        DownloadProgress::LargeBatch(batch) => Some(LogEntry::Info(format!(
+            "{} has {} tracks, waiting for confirmation to queue them",
+            batch.name,
+            batch.items.len()
+        ))),
+        DownloadProgress::AlbumAlreadyRequested(id) => Some(LogEntry::Info(format!(
+            "Album with id {} is already queued",
+            id
+        ))),
+        DownloadProgress::TrackRangeOutOfBounds(id, range, total) => {
+            Some(LogEntry::Error(format!(
+                "Album with id {} has {} tracks, tracks {} can't be downloaded",
+                id, total, range
+            )))
+        }
+        DownloadProgress::ContentFiltered(count) => Some(LogEntry::Info(format!(
+            "{} tracks left out by the explicit content filter",
+            count
+        ))),
+        DownloadProgress::Skipped(track, reason) => Some(LogEntry::Info(format!(
+            "{} - {} skipped, {}",
+            track.artist.name, track.title, reason
+        ))),
+        DownloadProgress::Paused(failures) => Some(LogEntry::Error(format!(
+            "Downloads paused after {} failures in a row, check your connection then press Ctrl+P to resume",
+            failures
+        ))),
+        DownloadProgress::SessionLimitReached(size) => Some(LogEntry::Error(format!(
+            "Downloads paused after writing {} MB this session, press Ctrl+P to resume",
+            size / 1024 / 1024
+        ))),
+        DownloadProgress::WorkerInitError(err) => Some(LogEntry::Error(err.clone())),
+        DownloadProgress::ProxyError(err) => Some(LogEntry::Error(format!(
+            "Unable to reach Deezer through the proxy: {}",
+            err
+        ))),
+        DownloadProgress::HistoryError(err) => Some(LogEntry::Error(format!(
+            "Unable to write to the history: {}",
+            err
+        ))),
+        DownloadProgress::PostDownloadCommandError(track, err) => Some(LogEntry::Error(format!(
+            "The command run after downloading {} - {} failed: {}",
+            track.artist.name, track.title, err
+        ))),
+        DownloadProgress::SidecarCoverError(track, err) => Some(LogEntry::Error(format!(
+            "Unable to save the cover of {} - {}: {}",
+            track.artist.name, track.title, err
+        ))),
+        DownloadProgress::LyricsError(track, err) => Some(LogEntry::Error(format!(
+            "Unable to save the lyrics of {} - {}: {}",
+            track.artist.name, track.title, err
+        ))),
+        DownloadProgress::Mp3CopyFinish(track, path) => Some(LogEntry::Success(format!(
+            "MP3 copy of {} - {} written to {}",
+            track.artist.name,
+            track.title,
+            path.display()
+        ))),
+        DownloadProgress::Mp3CopyError(track, err) => Some(LogEntry::Error(format!(
+            "Unable to write the MP3 copy of {} - {}: {}",
+            track.artist.name, track.title, err
+        ))),
+        DownloadProgress::Mp3CopySkipped(track) => Some(LogEntry::Info(format!(
+            "No MP3 copy of {} - {} was written, ffmpeg isn't installed",
             track.artist.name, track.title
         ))),
+        DownloadProgress::AlbumMetadataError(album, err) => Some(LogEntry::Error(format!(
+            "Unable to write the metadata file of {}: {}",
+            album, err
+        ))),
+        DownloadProgress::NoWorkers => Some(LogEntry::Error(String::from(
+            "No download worker could be initialized, check your connection then restart the app",
+        ))),
+        DownloadProgress::DownloadError(track, err) => Some(LogEntry::Error(format!(
+            "Error while downloading {} - {}: {}",
+            track.artist.name, track.title, err
+        ))),
+        DownloadProgress::GroupStarted(_, name, total) => Some(LogEntry::Info(format!(
+            "{}: {} tracks queued",
+            name, total
+        ))),
+        DownloadProgress::GroupFinished(summary) => {
+            let mut message = format!(
+                "{}: {}/{} downloaded, {} failed",
+                summary.name, summary.downloaded, summary.total, summary.failed
+            );
+            if summary.unavailable > 0 {
+                message.push_str(&format!(
+                    ", {} unavailable in your region",
+                    summary.unavailable
+                ));
+            }
+
+            if summary.downloaded == summary.total {
+                Some(LogEntry::Success(message))
+            } else {
+                Some(LogEntry::Error(message))
+            }
+        }
+        DownloadProgress::NotFoundRetry(id, attempt) => Some(LogEntry::Info(format!(
+            "Nothing found with id {}, looking it up again (attempt {})",
+            id, attempt
+        ))),
         DownloadProgress::SongNotFoundError(id) => Some(LogEntry::Error(format!(
             "Song with id {} was not found",
             id
         ))),
+        DownloadProgress::BarcodeNotFoundError(upc) => Some(LogEntry::Error(format!(
+            "No album found with barcode {}",
+            upc
+        ))),
+        DownloadProgress::ShortLinkError(link) => Some(LogEntry::Error(format!(
+            "Short link {} doesn't lead to a track, album or playlist",
+            link
+        ))),
         DownloadProgress::AlbumNotFoundError(id) => Some(LogEntry::Error(format!(
             "Album with id {} was not found",
             id
         ))),
+        DownloadProgress::PlaylistNotFoundError(id) => Some(LogEntry::Error(format!(
+            "Playlist with id {} was not found",
+            id
+        ))),
+        DownloadProgress::PlaylistSynced(title, new) => Some(LogEntry::Info(format!(
+            "{} new tracks in playlist \"{}\" since last sync",
+            new, title
+        ))),
+        DownloadProgress::PlaylistTrackNotFoundError(playlist, track) => {
+            Some(LogEntry::Error(format!(
+                "Track with id {} of playlist {} was not found, downloading the others",
+                track, playlist
+            )))
+        }
+        DownloadProgress::AlbumTrackNotFoundError(album, track) => {
+            Some(LogEntry::Error(format!(
+                "Track with id {} of album {} was not found, downloading the others",
+                track, album
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::tests::track;
+
+    #[test]
+    fn logs_started_tracks_only_when_verbose() {
+        let progress = DownloadProgress::Start(track(1, 1, 1));
+
+        assert!(get_log_from_progress(&progress, false).is_none());
+        let log = get_log_from_progress(&progress, true).unwrap();
+        assert_eq!("Started Artist - Title 1", log.entry.message());
+        assert_eq!(Some(1), log.track_id);
+    }
+
+    #[test]
+    fn does_not_log_download_progress() {
+        let progress = DownloadProgress::Progress(track(1, 1, 1), 50);
+
+        assert!(get_log_from_progress(&progress, false).is_none());
+        assert!(get_log_from_progress(&progress, true).is_none());
     }
 }
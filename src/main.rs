@@ -1,18 +1,39 @@
 mod app;
+mod cache;
+mod cli;
+mod config;
 mod downloader;
+mod flac;
+mod history;
+mod import;
 mod log;
+mod media;
+mod session;
+mod sync;
 mod tui;
+mod verify;
+
+use std::io::IsTerminal;
 
 use app::App;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
+use config::Config;
 
 pub type Frame<'a> = ratatui::Frame<'a>;
 
 #[derive(Clone, Debug)]
 pub enum Event {
-    Error,
+    Error(String),
     Tick,
     Key(crossterm::event::KeyEvent),
+    /// The terminal was resized, it is drawn again to fit.
+    Resize,
+    /// Result of looking up the song id being typed.
+    Preview(downloader::Id, Option<Box<deezer::models::Track>>),
+    /// Request read from the requests source, or the line that isn't one.
+    ExternalRequest(std::result::Result<downloader::DownloadRequest, String>),
+    /// The requests source was read to its end, or failed with the given error.
+    RequestsEnded(Option<String>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,14 +41,97 @@ enum Action {
     Tick,
     Quit,
     ToggleInputMode,
+    CycleFocus,
     Download,
+    CancelAll,
     ScrollLogsUp,
     ScrollLogsDown,
+    ToggleLogWrap,
+    ToggleVerboseLogs,
+    ToggleRecentDownloads,
+    ToggleWorkers,
+    SelectPreviousWorker,
+    SelectNextWorker,
+    ToggleWorkerPaused,
+    PreviousInput,
+    NextInput,
+    ShowQueueItemDetails,
+    CloseQueueItemDetails,
+    SelectPreviousQueueItem,
+    SelectNextQueueItem,
+    QueuePageUp,
+    QueuePageDown,
+    SelectFirstQueueItem,
+    SelectLastQueueItem,
+    ToggleInactiveItems,
+    CopyTrackUrl,
+    RedownloadSelected,
+    SortQueue,
+    ResumeDownloads,
+    OpenDirPrompt,
+    DirPromptCancel,
+    DirPromptConfirm,
+    CollisionRename,
+    CollisionOverwrite,
+    CollisionSkip,
+    CollisionOverwriteAll,
+    CollisionSkipAll,
+    OpenTrackPicker,
+    PickerUp,
+    PickerDown,
+    PickerToggle,
+    PickerToggleAll,
+    PickerConfirm,
+    PickerCancel,
+    RestoreSession,
+    DiscardSession,
+    ConfirmBatch,
+    DeclineBatch,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     color_eyre::install()?;
-    let mut app = App::default();
-    app.run().await
+    let args = cli::Args::parse(std::env::args().skip(1))?;
+    let mut config = Config::load()?;
+    config.apply_env()?;
+    if let Some(lookup_cache) = args.lookup_cache {
+        config.lookup_cache = lookup_cache;
+    }
+    // The environment can only be changed safely while no other thread runs, so before
+    // the runtime starts its own
+    config.apply_proxy();
+    config.apply_user_agent()?;
+
+    tokio::runtime::Runtime::new()?.block_on(run(args, config))
+}
+
+async fn run(args: cli::Args, config: Config) -> Result<()> {
+    if let Some(max_workers) = args.benchmark {
+        return cli::run_benchmark(config, args.requests, max_workers).await;
+    }
+    if let Some(path) = args.import {
+        return import::run_import(config, path).await;
+    }
+    if let Some(dir) = args.verify {
+        return verify::run_verify(config, dir, args.repair).await;
+    }
+
+    match args.output_format {
+        cli::OutputFormat::Tui => {
+            // The TUI is drawn on stderr, which can't be put in raw mode when redirected
+            if !std::io::stderr().is_terminal() {
+                return Err(eyre!(
+                    "The interface needs a terminal, use --output-format json with --song or \
+                    --album to download from a script"
+                ));
+            }
+
+            let mut app = App::new(config);
+            if let Some(source) = args.requests_from {
+                app.read_requests_from(source);
+            }
+            app.run().await
+        }
+        cli::OutputFormat::Json => cli::run_json(config, args.requests).await,
+    }
 }
@@ -0,0 +1,367 @@
+use std::sync::Arc;
+
+use block_modes::block_padding::NoPadding;
+use block_modes::{BlockMode, Cbc};
+use blowfish::Blowfish;
+use color_eyre::eyre::{eyre, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Quality;
+use crate::downloader::{client_builder, Id};
+
+/// Gateway of the Deezer website, giving what the public API doesn't.
+static GATEWAY_URL: &str = "https://www.deezer.com/ajax/gw-light.php";
+
+/// Site the `arl` cookie of an account belongs to.
+static DEEZER_URL: &str = "https://www.deezer.com";
+
+/// Where the URL of the media of a track is asked for.
+static MEDIA_URL: &str = "https://media.deezer.com/v1/get_url";
+
+/// Mixed with the hash of the track id into the key its media is encrypted with.
+static TRACK_KEY_SECRET: &[u8; 16] = b"g4el58wc0zvf9na1";
+
+static TRACK_IV: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+/// Size of the chunks the media of a track is split into, only one in three of them
+/// being encrypted.
+pub static CHUNK_SIZE: usize = 2048;
+
+type BlowfishCbc = Cbc<Blowfish, NoPadding>;
+
+/// Session with the Deezer gateway, needed to get the media of tracks.
+#[derive(Debug, Clone)]
+pub struct MediaSession {
+    client: reqwest::Client,
+    api_token: String,
+    license_token: String,
+    /// Qualities the account is allowed to stream.
+    qualities: Vec<Quality>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GatewayResponse<T> {
+    results: Option<T>,
+    /// Empty when the call succeeded, either as an object or an array.
+    #[serde(default)]
+    error: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserData {
+    #[serde(rename = "checkForm")]
+    api_token: String,
+    #[serde(rename = "USER")]
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    /// 0 when not logged in.
+    #[serde(rename = "USER_ID")]
+    id: Id,
+    #[serde(rename = "OPTIONS")]
+    options: UserOptions,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserOptions {
+    license_token: String,
+    /// Whether 320kbps MP3s can be streamed.
+    #[serde(default)]
+    web_hq: bool,
+    #[serde(default)]
+    web_lossless: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SongData {
+    #[serde(rename = "TRACK_TOKEN")]
+    track_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LyricsData {
+    #[serde(rename = "LYRICS_TEXT", default)]
+    text: String,
+    #[serde(rename = "LYRICS_SYNC_JSON", default)]
+    synced: Vec<SyncedLine>,
+}
+
+/// A line of synchronized lyrics.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SyncedLine {
+    /// When the line starts, like `[01:02.34]`. Empty for the breaks between verses.
+    #[serde(rename = "lrc_timestamp", default)]
+    pub timestamp: String,
+    #[serde(default)]
+    pub line: String,
+}
+
+/// Lyrics of a track, along with their synchronized lines when Deezer has them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SongLyrics {
+    pub text: String,
+    pub synced: Vec<SyncedLine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaResponse {
+    data: Vec<MediaData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaData {
+    /// Empty when the track isn't available in the requested format.
+    #[serde(default)]
+    media: Vec<Media>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Media {
+    sources: Vec<MediaSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaSource {
+    url: String,
+}
+
+impl MediaSession {
+    /// Open a session, logged in to the account of an `arl` cookie if given, anonymous
+    /// otherwise.
+    pub async fn new(arl: Option<&str>) -> Result<Self> {
+        let jar = reqwest::cookie::Jar::default();
+        if let Some(arl) = arl {
+            let url = DEEZER_URL.parse().expect("The Deezer URL should be valid.");
+            jar.add_cookie_str(&format!("arl={}", arl), &url);
+        }
+        let client = client_builder().cookie_provider(Arc::new(jar)).build()?;
+        let mut session = MediaSession {
+            client,
+            // Only asked for along with the user data
+            api_token: String::from("null"),
+            license_token: String::new(),
+            qualities: Vec::new(),
+        };
+
+        let user_data: UserData = session.gateway("deezer.getUserData", json!({})).await?;
+        let user = user_data.user;
+        if arl.is_some() && user.id == 0 {
+            return Err(eyre!(
+                "The arl doesn't log in to Deezer, it may have expired"
+            ));
+        }
+        session.api_token = user_data.api_token;
+        session.license_token = user.options.license_token;
+        session.qualities = std::iter::once(Quality::Mp3_128)
+            .chain(user.options.web_hq.then_some(Quality::Mp3_320))
+            .chain(user.options.web_lossless.then_some(Quality::Flac))
+            .collect();
+        Ok(session)
+    }
+
+    /// Qualities the account is allowed to stream, at least 128kbps MP3s.
+    pub fn qualities(&self) -> &[Quality] {
+        &self.qualities
+    }
+
+    /// Call a method of the gateway, returning its results.
+    async fn gateway<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T> {
+        let response: GatewayResponse<T> = self
+            .client
+            .post(GATEWAY_URL)
+            .query(&[
+                ("method", method),
+                ("input", "3"),
+                ("api_version", "1.0"),
+                ("api_token", &self.api_token),
+            ])
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let failed = match &response.error {
+            serde_json::Value::Object(error) => !error.is_empty(),
+            serde_json::Value::Array(error) => !error.is_empty(),
+            _ => false,
+        };
+        match response.results {
+            Some(results) if !failed => Ok(results),
+            _ => Err(eyre!("{} failed: {}", method, response.error)),
+        }
+    }
+
+    /// Token needed to get the media of a track.
+    pub async fn track_token(&self, id: Id) -> Result<String> {
+        let song: SongData = self
+            .gateway("song.getData", json!({ "SNG_ID": id }))
+            .await?;
+        Ok(song.track_token)
+    }
+
+    /// Lyrics of a track, `None` when it has none. The gateway fails for those.
+    pub async fn lyrics(&self, id: Id) -> Result<Option<SongLyrics>> {
+        let lyrics: LyricsData = self
+            .gateway("song.getLyrics", json!({ "SNG_ID": id }))
+            .await?;
+        let synced = lyrics
+            .synced
+            .into_iter()
+            .filter(|x| !x.timestamp.is_empty())
+            .collect::<Vec<_>>();
+
+        Ok(
+            (!lyrics.text.trim().is_empty() || !synced.is_empty()).then_some(SongLyrics {
+                text: lyrics.text,
+                synced,
+            }),
+        )
+    }
+
+    /// URL of the encrypted media of a track in a quality, `None` if it isn't available
+    /// in it to this session.
+    pub async fn media_url(&self, track_token: &str, quality: Quality) -> Result<Option<String>> {
+        let body = json!({
+            "license_token": self.license_token,
+            "media": [{
+                "type": "FULL",
+                "formats": [{ "cipher": "BF_CBC_STRIPE", "format": media_format(quality) }],
+            }],
+            "track_tokens": [track_token],
+        });
+        let response: MediaResponse = self
+            .client
+            .post(MEDIA_URL)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .data
+            .into_iter()
+            .flat_map(|x| x.media)
+            .flat_map(|x| x.sources)
+            .map(|x| x.url)
+            .next())
+    }
+}
+
+/// Name of a quality for the media API.
+fn media_format(quality: Quality) -> &'static str {
+    match quality {
+        Quality::Mp3_128 => "MP3_128",
+        Quality::Mp3_320 => "MP3_320",
+        Quality::Flac => "FLAC",
+    }
+}
+
+/// Key the media of a track is encrypted with.
+fn track_key(id: Id) -> [u8; 16] {
+    let hash = format!("{:x}", md5::compute(id.to_string()));
+    let hash = hash.as_bytes();
+    std::array::from_fn(|i| hash[i] ^ hash[i + 16] ^ TRACK_KEY_SECRET[i])
+}
+
+/// Decrypts the media of a track as its bytes arrive. Every third chunk is encrypted on
+/// its own, the last one is never encrypted when it is shorter than the others.
+pub struct Decryptor {
+    key: [u8; 16],
+    /// Index of the next chunk in the media.
+    chunk: usize,
+    buffer: Vec<u8>,
+}
+
+impl Decryptor {
+    /// Decrypt the media of a track from the start of one of its chunks, the first one
+    /// for the whole media.
+    pub fn new(id: Id, chunk: usize) -> Self {
+        Decryptor {
+            key: track_key(id),
+            chunk,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// Decrypt the chunks completed by the bytes.
+    pub fn update(&mut self, mut bytes: &[u8]) -> Vec<u8> {
+        let mut decrypted = Vec::with_capacity(self.buffer.len() + bytes.len());
+        while !bytes.is_empty() {
+            let len = bytes.len().min(CHUNK_SIZE - self.buffer.len());
+            self.buffer.extend_from_slice(&bytes[..len]);
+            bytes = &bytes[len..];
+
+            if self.buffer.len() == CHUNK_SIZE {
+                if self.chunk.is_multiple_of(3) {
+                    BlowfishCbc::new_from_slices(&self.key, &TRACK_IV)
+                        .expect("The key and IV should be of valid lengths.")
+                        .decrypt(&mut self.buffer)
+                        .expect("Chunks should be a multiple of the block size.");
+                }
+                decrypted.append(&mut self.buffer);
+                self.chunk += 1;
+            }
+        }
+        decrypted
+    }
+
+    /// The bytes left after the last full chunk.
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrypts_every_third_chunk() {
+        let id = 3135556;
+        let media = (0..CHUNK_SIZE * 4 + 100)
+            .map(|x| (x % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut encrypted = media.clone();
+        for chunk in encrypted.chunks_mut(CHUNK_SIZE).step_by(3) {
+            if chunk.len() == CHUNK_SIZE {
+                BlowfishCbc::new_from_slices(&track_key(id), &TRACK_IV)
+                    .unwrap()
+                    .encrypt(chunk, CHUNK_SIZE)
+                    .unwrap();
+            }
+        }
+        assert_ne!(media[..CHUNK_SIZE], encrypted[..CHUNK_SIZE]);
+
+        // The bytes don't arrive along the chunks
+        let mut decryptor = Decryptor::new(id, 0);
+        let mut decrypted = encrypted
+            .chunks(1000)
+            .flat_map(|x| decryptor.update(x))
+            .collect::<Vec<_>>();
+        decrypted.extend(decryptor.finish());
+        assert_eq!(media, decrypted);
+
+        // Resumed after the first three chunks
+        let mut decryptor = Decryptor::new(id, 3);
+        let mut resumed = decryptor.update(&encrypted[CHUNK_SIZE * 3..]);
+        resumed.extend(decryptor.finish());
+        assert_eq!(media[CHUNK_SIZE * 3..], resumed);
+    }
+
+    #[test]
+    fn derives_the_key_from_the_track_id() {
+        assert_eq!(*b"llfk9f,7e%u`<d49", track_key(3135556));
+    }
+}
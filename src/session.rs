@@ -0,0 +1,174 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::downloader::{DownloadItem, DownloadStatus, Id};
+use crate::log::{LogEntry, LogLine};
+
+const LOCK_FILE: &str = "session.lock";
+
+/// State of a running session, saved regularly so that it can be restored if the app
+/// doesn't exit cleanly.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    pub queue: Vec<SavedQueueItem>,
+    pub logs: Vec<SavedLog>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedQueueItem {
+    pub item: DownloadItem,
+    pub status: DownloadStatus,
+    pub path: Option<PathBuf>,
+    /// Why the download failed, absent from the sessions saved before it was kept.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedLog {
+    pub entry: LogEntry,
+    /// When the entry was logged, in RFC 3339.
+    pub time: String,
+    pub track_id: Option<Id>,
+}
+
+impl From<&LogLine> for SavedLog {
+    fn from(log: &LogLine) -> Self {
+        SavedLog {
+            entry: log.entry.clone(),
+            time: log.time.to_rfc3339(),
+            track_id: log.track_id,
+        }
+    }
+}
+
+impl From<SavedLog> for LogLine {
+    fn from(log: SavedLog) -> Self {
+        LogLine {
+            entry: log.entry,
+            time: DateTime::parse_from_rfc3339(&log.time)
+                .map(|x| x.with_timezone(&Local))
+                .unwrap_or_else(|_| Local::now()),
+            track_id: log.track_id,
+            // Groups are numbered anew by each session
+            group: None,
+        }
+    }
+}
+
+/// Lock and state files of the running session. The lock file is held exclusively while
+/// the app runs and left behind when it doesn't exit cleanly, which is how the next
+/// session knows to offer a restore.
+#[derive(Debug)]
+pub struct Session {
+    dir: PathBuf,
+    lock: File,
+}
+
+impl Session {
+    /// Start a session in the data directory, returning the state of the previous one
+    /// if it didn't exit cleanly. `None` if no data directory was found or another
+    /// instance of the app holds the session.
+    pub fn start() -> Option<(Self, Option<SessionState>)> {
+        let dirs = ProjectDirs::from("", "", "deeznuts-downloader")?;
+        Self::start_in(dirs.data_dir()).ok()
+    }
+
+    fn start_in(dir: &Path) -> std::io::Result<(Self, Option<SessionState>)> {
+        std::fs::create_dir_all(dir)?;
+        let lock_path = dir.join(LOCK_FILE);
+        let left_behind = lock_path.exists();
+        let mut lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        // The lock is released with the file, even when the app crashes
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(std::io::Error::other(
+                    "The session is held by another instance",
+                ))
+            }
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+        lock.set_len(0)?;
+        write!(lock, "{}", std::process::id())?;
+
+        let session = Session {
+            dir: dir.to_path_buf(),
+            lock,
+        };
+
+        // A state that can't be read is as good as none, e.g. from a crash mid-write
+        let previous = left_behind
+            .then(|| std::fs::read_to_string(session.state_path()).ok())
+            .flatten()
+            .and_then(|content| serde_json::from_str(&content).ok());
+
+        Ok((session, previous))
+    }
+
+    /// Save the state of the session, replacing the previous one at once so that a crash
+    /// while writing never leaves a partial state.
+    pub fn save(&self, state: &SessionState) -> std::io::Result<()> {
+        let content = serde_json::to_string(state)?;
+        let partial_path = self.state_path().with_extension("json.part");
+        std::fs::write(&partial_path, content)?;
+        std::fs::rename(&partial_path, self.state_path())
+    }
+
+    /// End the session cleanly, nothing is offered to be restored next time.
+    pub fn end(self) {
+        let _ = std::fs::remove_file(self.state_path());
+        // Some platforms don't remove files still open
+        drop(self.lock);
+        let _ = std::fs::remove_file(self.dir.join(LOCK_FILE));
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.dir.join("session.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_sessions_that_did_not_end() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-session");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let (session, previous) = Session::start_in(&dir).unwrap();
+        assert!(previous.is_none());
+        let state = SessionState {
+            logs: vec![SavedLog::from(&LogLine::from(LogEntry::Info(
+                String::from("Downloading"),
+            )))],
+            ..SessionState::default()
+        };
+        session.save(&state).unwrap();
+
+        // Another instance doesn't take over a running session
+        assert!(Session::start_in(&dir).is_err());
+
+        // Crashed without ending the session
+        drop(session);
+        let (session, previous) = Session::start_in(&dir).unwrap();
+        let logs = previous.unwrap().logs;
+        assert_eq!("Downloading", logs[0].entry.message());
+
+        session.end();
+        let (session, previous) = Session::start_in(&dir).unwrap();
+        assert!(previous.is_none());
+        session.end();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::downloader::Id;
+use crate::history::HistoryWriter;
+
+/// A track downloaded from a playlist, recorded to the sync file, one per line.
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncedTrack {
+    playlist: Id,
+    track: Id,
+}
+
+/// Tracks already downloaded from each playlist, kept across sessions so that requesting
+/// a playlist again only queues the tracks added since.
+#[derive(Debug)]
+pub struct PlaylistSync {
+    playlists: Mutex<HashMap<Id, HashSet<Id>>>,
+    path: PathBuf,
+    writer: HistoryWriter,
+}
+
+/// File synced tracks are recorded to, `None` if no data directory was found.
+fn sync_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "deeznuts-downloader")
+        .map(|dirs| dirs.data_dir().join("playlists.jsonl"))
+}
+
+impl PlaylistSync {
+    /// Load the tracks synced by the previous sessions, `None` if no data directory was
+    /// found.
+    pub fn open(writer: HistoryWriter) -> Option<Self> {
+        Some(Self::open_at(sync_path()?, writer))
+    }
+
+    pub(crate) fn open_at(path: PathBuf, writer: HistoryWriter) -> Self {
+        let mut playlists = HashMap::<Id, HashSet<Id>>::new();
+        let content = std::fs::read_to_string(&path).unwrap_or_default();
+        // Lines cut by a crash are left out
+        for synced in content
+            .lines()
+            .filter_map(|x| serde_json::from_str::<SyncedTrack>(x).ok())
+        {
+            playlists
+                .entry(synced.playlist)
+                .or_default()
+                .insert(synced.track);
+        }
+
+        PlaylistSync {
+            playlists: Mutex::new(playlists),
+            path,
+            writer,
+        }
+    }
+
+    /// Tracks already downloaded from the playlist, empty if it was never synced.
+    pub fn synced(&self, playlist: Id) -> HashSet<Id> {
+        self.playlists
+            .lock()
+            .unwrap()
+            .get(&playlist)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Record that a track of the playlist was downloaded, once.
+    pub fn insert(&self, playlist: Id, track: Id) {
+        let inserted = self
+            .playlists
+            .lock()
+            .unwrap()
+            .entry(playlist)
+            .or_default()
+            .insert(track);
+        if inserted {
+            let content = serde_json::to_string(&SyncedTrack { playlist, track })
+                .expect("Synced track should be serializable.");
+            self.writer.append(&self.path, content);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+
+    #[test]
+    fn synced_tracks_are_kept_across_sessions() {
+        let dir = std::env::temp_dir().join("deeznuts-downloader-playlists");
+        let _ = std::fs::remove_dir_all(&dir);
+        let (progress_tx, _progress_rx) = unbounded();
+        let writer = HistoryWriter::spawn(progress_tx);
+
+        let path = dir.join("playlists.jsonl");
+        let sync = PlaylistSync::open_at(path.clone(), writer.clone());
+        sync.insert(1, 10);
+        sync.insert(1, 11);
+        sync.insert(1, 10);
+        sync.insert(2, 20);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(&path).map_or(0, |x| x.lines().count()) < 3
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let sync = PlaylistSync::open_at(path.clone(), writer);
+        assert_eq!(HashSet::from([10, 11]), sync.synced(1));
+        assert_eq!(HashSet::from([20]), sync.synced(2));
+        assert!(sync.synced(3).is_empty());
+        assert_eq!(3, std::fs::read_to_string(&path).unwrap().lines().count());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,29 @@
 use color_eyre::eyre::Result;
 use futures::{FutureExt, StreamExt};
-use ratatui::{backend::CrosstermBackend as Backend, prelude::*};
+use ratatui::{backend::CrosstermBackend, prelude::*};
 
 use crate::Event;
 
-pub struct Tui {
-    pub terminal: Terminal<Backend<std::io::Stderr>>,
+pub struct Tui<B: Backend = CrosstermBackend<std::io::Stderr>> {
+    pub terminal: Terminal<B>,
     pub task: tokio::task::JoinHandle<()>,
     pub cancellation_token: tokio_util::sync::CancellationToken,
     pub event_rx: tokio::sync::mpsc::UnboundedReceiver<Event>,
     pub event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
+    /// Whether the terminal was put in raw mode and needs to be restored.
+    entered: bool,
 }
 
 impl Tui {
     pub fn new() -> Result<Tui> {
-        let terminal = ratatui::Terminal::new(Backend::new(std::io::stderr()))?;
+        Tui::with_backend(CrosstermBackend::new(std::io::stderr()))
+    }
+}
+
+impl<B: Backend> Tui<B> {
+    /// Create a [Tui] drawing to any backend, e.g. a `TestBackend` to test the rendering.
+    pub fn with_backend(backend: B) -> Result<Tui<B>> {
+        let terminal = ratatui::Terminal::new(backend)?;
         let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel();
         let cancellation_token = tokio_util::sync::CancellationToken::new();
         let task = tokio::spawn(async {});
@@ -24,6 +33,7 @@ impl Tui {
             cancellation_token,
             event_rx,
             event_tx,
+            entered: false,
         })
     }
 
@@ -38,11 +48,17 @@ impl Tui {
             crossterm::terminal::EnterAlternateScreen,
             crossterm::cursor::Hide
         )?;
+        self.entered = true;
         self.start();
         Ok(())
     }
 
-    pub fn exit(&self) -> Result<()> {
+    pub fn exit(&mut self) -> Result<()> {
+        if !self.entered {
+            return Ok(());
+        }
+        self.entered = false;
+
         crossterm::execute!(
             std::io::stderr(),
             crossterm::terminal::LeaveAlternateScreen,
@@ -76,11 +92,14 @@ impl Tui {
                               _event_tx.send(Event::Key(key)).unwrap();
                             }
                           },
+                          crossterm::event::Event::Resize(_, _) => {
+                            _event_tx.send(Event::Resize).unwrap();
+                          },
                           _ => {}
                         }
                       }
-                      Some(Err(_)) => {
-                        _event_tx.send(Event::Error).unwrap();
+                      Some(Err(err)) => {
+                        _event_tx.send(Event::Error(err.to_string())).unwrap();
                       }
                       None => {},
                     }
@@ -94,21 +113,21 @@ impl Tui {
     }
 }
 
-impl std::ops::Deref for Tui {
-    type Target = ratatui::Terminal<Backend<std::io::Stderr>>;
+impl<B: Backend> std::ops::Deref for Tui<B> {
+    type Target = ratatui::Terminal<B>;
 
     fn deref(&self) -> &Self::Target {
         &self.terminal
     }
 }
 
-impl std::ops::DerefMut for Tui {
+impl<B: Backend> std::ops::DerefMut for Tui<B> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.terminal
     }
 }
 
-impl Drop for Tui {
+impl<B: Backend> Drop for Tui<B> {
     fn drop(&mut self) {
         self.exit().unwrap();
     }
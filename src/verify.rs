@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use deezer::DeezerClient;
+
+use crate::cli::handle_requests;
+use crate::config::{Config, SameTrackExisting};
+use crate::downloader::{
+    audio_bitrate, read_song_tag, select_quality, DownloadProgress, DownloadRequest, Downloader,
+    Id, RequestKind, TrackSource,
+};
+use crate::media::MediaSession;
+
+/// Share of the expected size under which a file is considered truncated, in percent.
+static TRUNCATED_PERCENT: u64 = 90;
+
+/// Something wrong with the library, as found by [run_verify].
+#[derive(Debug, PartialEq, Eq)]
+enum LibraryIssue {
+    /// A track of an album in the library has no file.
+    Missing { album: Id, track: Id },
+    /// The file is empty or shorter than its track, with the track if its tag says it.
+    Corrupt { path: PathBuf, track: Option<Id> },
+    /// The file, of the given bitrate, can be downloaded in a better quality.
+    Upgradable { path: PathBuf, track: Id, kbps: u32 },
+}
+
+impl LibraryIssue {
+    /// Track to download again to fix the issue, if known.
+    fn track(&self) -> Option<Id> {
+        match self {
+            LibraryIssue::Missing { track, .. } | LibraryIssue::Upgradable { track, .. } => {
+                Some(*track)
+            }
+            LibraryIssue::Corrupt { track, .. } => *track,
+        }
+    }
+
+    /// File with the issue, none when the track is missing.
+    fn path(&self) -> Option<&Path> {
+        match self {
+            LibraryIssue::Missing { .. } => None,
+            LibraryIssue::Corrupt { path, .. } | LibraryIssue::Upgradable { path, .. } => {
+                Some(path)
+            }
+        }
+    }
+}
+
+impl Display for LibraryIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibraryIssue::Missing { album, track } => {
+                write!(f, "Missing: track {} of album {}", track, album)
+            }
+            LibraryIssue::Corrupt { path, .. } => write!(f, "Corrupt: {}", path.display()),
+            LibraryIssue::Upgradable { path, kbps, .. } => {
+                write!(f, "Upgradable from ~{} kbps: {}", kbps, path.display())
+            }
+        }
+    }
+}
+
+/// Check a library downloaded with Deezer ids in its tags, reporting the tracks missing
+/// from its albums and the files that are corrupt or can be upgraded. With `repair`,
+/// the tracks of the issues are then downloaded again into the library, where the file
+/// name config puts them. A file with an issue is only replaced when its new download
+/// has the same path, the others are reported to be checked and removed.
+pub async fn run_verify(config: Config, dir: PathBuf, repair: bool) -> Result<()> {
+    if !dir.is_dir() {
+        return Err(eyre!("{} is not an existing directory", dir.display()));
+    }
+
+    let client = DeezerClient::new();
+    // Files are only upgradable to what the account can download
+    let session = MediaSession::new(config.arl.as_deref()).await?;
+    // Libraries are checked album by album
+    let requested = config.quality.for_request(RequestKind::Album);
+    let best_kbps =
+        select_quality(requested, &config.fallback_chain, session.qualities()).map(|x| x.kbps());
+    let mut issues = Vec::new();
+    let mut untagged = 0;
+    let mut albums: HashMap<Id, HashSet<Id>> = HashMap::new();
+
+    let files = find_songs(&dir)?;
+    for path in &files {
+        let size = std::fs::metadata(path)?.len();
+        let ids = read_deezer_ids(path);
+        if let Some((track, album)) = ids {
+            albums.entry(album).or_default().insert(track);
+        } else {
+            untagged += 1;
+        }
+
+        let track = match ids {
+            Some((id, _)) => client.track(id).await.ok().flatten(),
+            None => None,
+        };
+        let issue = check_file(
+            path,
+            size,
+            audio_bitrate(path),
+            ids.map(|x| x.0),
+            track.map(|x| x.duration),
+            best_kbps,
+        );
+        issues.extend(issue);
+    }
+
+    for (album, tracks) in &albums {
+        match client.album_info(*album).await {
+            Ok(Some(info)) => issues.extend(missing_tracks(&info.track_ids, tracks).map(|track| {
+                LibraryIssue::Missing {
+                    album: *album,
+                    track,
+                }
+            })),
+            _ => println!("Album {} couldn't be looked up", album),
+        }
+    }
+
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let count = |f: fn(&LibraryIssue) -> bool| issues.iter().filter(|x| f(x)).count();
+    println!(
+        "Checked {} files ({} without Deezer ids): {} missing, {} corrupt, {} upgradable",
+        files.len(),
+        untagged,
+        count(|x| matches!(x, LibraryIssue::Missing { .. })),
+        count(|x| matches!(x, LibraryIssue::Corrupt { .. })),
+        count(|x| matches!(x, LibraryIssue::Upgradable { .. })),
+    );
+
+    if !repair {
+        return Ok(());
+    }
+
+    let requests = issues
+        .iter()
+        .filter_map(|x| x.track())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(DownloadRequest::Song)
+        .collect::<Vec<_>>();
+    let replaced = issues
+        .iter()
+        .filter_map(|x| Some((x.track()?, x.path()?.to_path_buf())))
+        .collect::<HashMap<_, _>>();
+    let downloader = Downloader::new(Config {
+        upgrade_existing: false,
+        ask_on_collision: false,
+        // The file of a corrupt song is from the same track, it must not be kept
+        same_track_existing: SameTrackExisting::Redownload,
+        confirm_batch_above: None,
+        ..config
+    });
+    downloader.set_download_dir(dir)?;
+
+    let (mut downloaded, mut failed, mut left) = (0, 0, 0);
+    handle_requests(&downloader, requests, |progress| {
+        match progress {
+            DownloadProgress::Finish(track, path) => {
+                downloaded += 1;
+                println!(
+                    "Downloaded {} - {} to {}",
+                    track.artist.name,
+                    track.title,
+                    path.display()
+                );
+                // Files laid out differently from the current config aren't replaced
+                if let Some(previous) = replaced.get(&track.id).filter(|x| *x != path) {
+                    left += 1;
+                    println!(
+                        "{} is left in place, remove it once checked",
+                        previous.display()
+                    );
+                }
+            }
+            DownloadProgress::DownloadError(track, err) => {
+                failed += 1;
+                println!(
+                    "Unable to download {} - {}: {}",
+                    track.artist.name, track.title, err
+                );
+            }
+            DownloadProgress::SongNotFoundError(id) => {
+                failed += 1;
+                println!("Track {} is no longer on Deezer", id);
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    println!(
+        "Downloaded {} tracks again, {} failed, {} previous files left in place",
+        downloaded, failed, left
+    );
+
+    Ok(())
+}
+
+/// Every MP3 file in a directory and its subdirectories.
+fn find_songs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut songs = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|x| x.eq_ignore_ascii_case("mp3") || x.eq_ignore_ascii_case("flac"))
+            {
+                songs.push(path);
+            }
+        }
+    }
+    songs.sort();
+    Ok(songs)
+}
+
+/// Ids of the track and album of a song, from the tags written by `tag_deezer_ids`.
+fn read_deezer_ids(path: &Path) -> Option<(Id, Id)> {
+    let tag = read_song_tag(path).ok()?;
+    let id = |description: &str| {
+        tag.extended_texts()
+            .find(|x| x.description == description)
+            .and_then(|x| x.value.parse().ok())
+    };
+    Some((id("DEEZER_TRACK_ID")?, id("DEEZER_ALBUM_ID")?))
+}
+
+/// Issue with a file, from its size and bitrate and the duration of its track on Deezer.
+fn check_file(
+    path: &Path,
+    size: u64,
+    kbps: Option<u32>,
+    track: Option<Id>,
+    duration: Option<u64>,
+    best_kbps: Option<u32>,
+) -> Option<LibraryIssue> {
+    let Some(kbps) = kbps.filter(|_| size > 0) else {
+        return Some(LibraryIssue::Corrupt {
+            path: path.to_path_buf(),
+            track,
+        });
+    };
+
+    let expected_size = duration.unwrap_or_default() * kbps as u64 * 1000 / 8;
+    if size * 100 < expected_size * TRUNCATED_PERCENT {
+        return Some(LibraryIssue::Corrupt {
+            path: path.to_path_buf(),
+            track,
+        });
+    }
+
+    match (track, best_kbps) {
+        (Some(track), Some(best_kbps)) if kbps < best_kbps => Some(LibraryIssue::Upgradable {
+            path: path.to_path_buf(),
+            track,
+            kbps,
+        }),
+        _ => None,
+    }
+}
+
+/// Tracks of an album that aren't in the library, in album order.
+fn missing_tracks<'a>(
+    album_tracks: &'a [Id],
+    local_tracks: &'a HashSet<Id>,
+) -> impl Iterator<Item = Id> + 'a {
+    album_tracks
+        .iter()
+        .copied()
+        .filter(|x| !local_tracks.contains(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_corrupt_and_upgradable_files() {
+        let path = Path::new("song.mp3");
+        let corrupt = Some(LibraryIssue::Corrupt {
+            path: path.to_path_buf(),
+            track: Some(1),
+        });
+
+        // 200 seconds at 128 kbps
+        assert_eq!(
+            None,
+            check_file(path, 3_200_000, Some(128), Some(1), Some(200), Some(128))
+        );
+        assert_eq!(
+            corrupt,
+            check_file(path, 0, Some(128), Some(1), Some(200), Some(128))
+        );
+        assert_eq!(
+            corrupt,
+            check_file(path, 3_200_000, None, Some(1), Some(200), Some(128))
+        );
+        assert_eq!(
+            corrupt,
+            check_file(path, 1_600_000, Some(128), Some(1), Some(200), Some(128))
+        );
+        assert_eq!(
+            Some(LibraryIssue::Upgradable {
+                path: path.to_path_buf(),
+                track: 1,
+                kbps: 128
+            }),
+            check_file(path, 3_200_000, Some(128), Some(1), Some(200), Some(320))
+        );
+    }
+
+    #[test]
+    fn lists_missing_album_tracks() {
+        let local = HashSet::from([1, 3]);
+        let missing = missing_tracks(&[1, 2, 3, 4], &local).collect::<Vec<_>>();
+        assert_eq!(vec![2, 4], missing);
+    }
+}